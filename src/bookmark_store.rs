@@ -0,0 +1,217 @@
+//! Persistent bookmarks
+//!
+//! Bookmarks are saved to `~/.uemacs-bookmarks` (or
+//! `%USERPROFILE%\.uemacs-bookmarks` on Windows), next to the macros file.
+//! Each bookmark is one section:
+//!
+//! ```text
+//! [parser entry]
+//! file = /home/me/src/parser.rs
+//! line = 120
+//! column = 4
+//! context = pub fn parse(input: &str) -> Result<Ast> {
+//! ```
+//!
+//! `context` is the start of the bookmarked line. If the file has been
+//! edited so the line moved, a jump looks for the nearest line that still
+//! starts with it.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::line::Line;
+
+/// Longest context string stored, in characters
+const CONTEXT_LEN: usize = 40;
+
+/// A named position in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    /// Name shown in completion and the *Bookmarks* list
+    pub name: String,
+    /// File the bookmark is in
+    pub file: PathBuf,
+    /// Line (0-indexed)
+    pub line: usize,
+    /// Column (byte offset within the line)
+    pub col: usize,
+    /// Start of the line's text, without leading whitespace
+    pub context: String,
+}
+
+impl Bookmark {
+    /// Create a bookmark at a position, taking the context from the line
+    pub fn new(name: String, file: PathBuf, line: usize, col: usize, text: &str) -> Self {
+        Self {
+            name,
+            file,
+            line,
+            col,
+            context: line_context(text),
+        }
+    }
+
+    /// Find the bookmarked line in `lines`, returns (line, col)
+    ///
+    /// The saved line is used if it still matches the context, otherwise the
+    /// nearest matching line; if none matches, the saved line is used
+    /// anyway. The column is clamped to the line found.
+    pub fn locate(&self, lines: &[&Line]) -> (usize, usize) {
+        if lines.is_empty() {
+            return (0, 0);
+        }
+        let last = lines.len() - 1;
+        let saved = self.line.min(last);
+        let matches = |idx: usize| line_context(lines[idx].text()).starts_with(&self.context);
+
+        let found = (0..=last)
+            .flat_map(|distance| {
+                let above = saved.checked_sub(distance);
+                let below = Some(saved + distance).filter(|&l| l <= last && distance > 0);
+                above.into_iter().chain(below)
+            })
+            .find(|&idx| matches(idx))
+            .unwrap_or(saved);
+
+        let text = lines.get(found).map(|l| l.text()).unwrap_or("");
+        let mut col = self.col.min(text.len());
+        while !text.is_char_boundary(col) {
+            col -= 1;
+        }
+        (found, col)
+    }
+}
+
+/// Context string for a line: its start, without leading whitespace
+fn line_context(text: &str) -> String {
+    text.trim_start().chars().take(CONTEXT_LEN).collect()
+}
+
+/// Get the path to the bookmarks file
+pub fn bookmarks_file_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var("USERPROFILE")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-bookmarks"))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("HOME")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-bookmarks"))
+    }
+}
+
+/// Parse bookmarks from the file format
+pub fn parse_bookmarks(content: &str) -> Vec<Bookmark> {
+    let mut bookmarks = Vec::new();
+    let mut current: Option<Bookmark> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        // Skip empty lines and comments
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+
+        // Section header [name] starts a new bookmark
+        if trimmed.starts_with('[') && trimmed.ends_with(']') && trimmed.len() >= 2 {
+            bookmarks.extend(current.take().filter(|b| !b.file.as_os_str().is_empty()));
+            current = Some(Bookmark {
+                name: trimmed[1..trimmed.len() - 1].to_string(),
+                file: PathBuf::new(),
+                line: 0,
+                col: 0,
+                context: String::new(),
+            });
+            continue;
+        }
+
+        // key = value (the context keeps its inner spacing)
+        if let (Some(bookmark), Some((key, value))) = (current.as_mut(), line.split_once('=')) {
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match key.trim() {
+                "file" => bookmark.file = PathBuf::from(value.trim()),
+                "line" => bookmark.line = value.trim().parse::<usize>().unwrap_or(1).saturating_sub(1),
+                "column" => bookmark.col = value.trim().parse().unwrap_or(0),
+                "context" => bookmark.context = value.to_string(),
+                _ => {}
+            }
+        }
+    }
+    bookmarks.extend(current.filter(|b| !b.file.as_os_str().is_empty()));
+    bookmarks
+}
+
+/// Format bookmarks for the bookmarks file
+pub fn format_bookmarks(bookmarks: &[Bookmark]) -> String {
+    let mut out = String::new();
+    out.push_str("# uEmacs.rs bookmarks\n");
+    out.push_str("# Each [name] section is one bookmark; line is 1-based\n");
+    for bookmark in bookmarks {
+        out.push('\n');
+        out.push_str(&format!("[{}]\n", bookmark.name));
+        out.push_str(&format!("file = {}\n", bookmark.file.display()));
+        out.push_str(&format!("line = {}\n", bookmark.line + 1));
+        out.push_str(&format!("column = {}\n", bookmark.col));
+        out.push_str(&format!("context = {}\n", bookmark.context));
+    }
+    out
+}
+
+/// Load bookmarks from the bookmarks file
+pub fn load_bookmarks() -> Vec<Bookmark> {
+    bookmarks_file_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_bookmarks(&content))
+        .unwrap_or_default()
+}
+
+/// Save bookmarks to the bookmarks file
+pub fn save_bookmarks(bookmarks: &[Bookmark]) -> io::Result<()> {
+    let path = match bookmarks_file_path() {
+        Some(p) => p,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Could not determine bookmarks file path",
+            ))
+        }
+    };
+    let mut file = fs::File::create(&path)?;
+    file.write_all(format_bookmarks(bookmarks).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let bookmarks = vec![
+            Bookmark::new("entry [main]".into(), PathBuf::from("/tmp/a.rs"), 9, 4, "    fn main() {  "),
+            Bookmark::new("end".into(), PathBuf::from("/tmp/b.txt"), 0, 0, ""),
+        ];
+        assert_eq!(bookmarks[0].context, "fn main() {  ");
+        let parsed = parse_bookmarks(&format_bookmarks(&bookmarks));
+        assert_eq!(parsed, bookmarks);
+    }
+
+    #[test]
+    fn test_locate_follows_moved_line() {
+        let bookmark = Bookmark::new("b".into(), PathBuf::from("f"), 1, 3, "target line");
+        let text = ["one", "two", "target line", "target line too"];
+        let lines: Vec<Line> = text.iter().map(|t| Line::from(*t)).collect();
+        let refs: Vec<&Line> = lines.iter().collect();
+        // Nearest match wins, column kept
+        assert_eq!(bookmark.locate(&refs), (2, 3));
+        // Saved line still matches
+        assert_eq!(bookmark.locate(&refs[1..]), (1, 3));
+        // No match: saved line, column clamped
+        let short = [Line::from("x"), Line::from("ab")];
+        assert_eq!(bookmark.locate(&short.iter().collect::<Vec<_>>()), (1, 2));
+    }
+}
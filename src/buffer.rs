@@ -1,8 +1,16 @@
 //! Buffer representation - a collection of lines with associated metadata
 
+use std::io::{BufRead, BufReader, BufWriter, Read};
 use std::path::PathBuf;
 
+use crate::file_format::{self, Encoding, FileFormat, LineEnding};
 use crate::line::Line;
+use crate::line_store::LineStore;
+use crate::marker::{MarkerId, MarkerSet};
+use crate::undo::UndoTree;
+
+/// Most positions a mark ring holds
+pub const MARK_RING_MAX: usize = 16;
 
 /// An entry in the undo stack
 #[derive(Debug, Clone)]
@@ -28,6 +36,15 @@ pub enum UndoEntry {
     DeleteNewline {
         line: usize,
         col: usize,  // Where the join happened
+        eol: Option<LineEnding>,  // The first line's own ending
+    },
+    /// Line endings were converted - to undo, restore the old style and
+    /// the lines that had their own ending
+    SetLineEnding {
+        from: LineEnding,
+        mixed: bool,
+        overrides: Vec<(usize, LineEnding)>,
+        to: LineEnding,
     },
     /// Boundary marker for grouping multiple operations
     Boundary,
@@ -41,114 +58,135 @@ pub struct BufferModes {
     pub exact: bool,     // Exact case matching for search
     pub view: bool,      // Read-only view mode
     pub overwrite: bool, // Overwrite mode
+    pub visual_line: bool, // Wrap long lines onto more screen rows
 }
 
 /// A buffer containing text and metadata
 #[derive(Debug)]
 pub struct Buffer {
     /// Lines of text
-    lines: Vec<Line>,
+    lines: LineStore,
     /// Buffer name (e.g., "main", "*scratch*")
     name: String,
+    /// Line endings, final newline and BOM used when writing
+    format: FileFormat,
     /// Associated file path (None for unnamed buffers)
     filename: Option<PathBuf>,
     /// Whether buffer has unsaved changes
     modified: bool,
+    /// Whether buffer has changed since it was last saved or auto-saved
+    auto_save_due: bool,
     /// Buffer modes
     modes: BufferModes,
-    /// Undo stack
-    undo_stack: Vec<UndoEntry>,
+    /// Undo history
+    undo_tree: UndoTree,
     /// Whether to record undo entries (disabled during undo itself)
     recording_undo: bool,
+    /// Positions that follow edits (marks, other windows' cursors)
+    markers: MarkerSet,
+    /// Start and end of the visible part when narrowed
+    narrowing: Option<(MarkerId, MarkerId)>,
+    /// Earlier marks and jump origins, oldest first
+    mark_ring: Vec<MarkerId>,
 }
 
 impl Buffer {
     /// Create a new empty buffer with the given name
     pub fn new(name: impl Into<String>) -> Self {
         Self {
-            lines: vec![Line::new()], // Always have at least one line
+            lines: LineStore::from_lines([Line::new()]), // Always have at least one line
             name: name.into(),
+            format: FileFormat::default(),
             filename: None,
             modified: false,
+            auto_save_due: false,
             modes: BufferModes::default(),
-            undo_stack: Vec::new(),
+            undo_tree: UndoTree::new(),
             recording_undo: true,
+            markers: MarkerSet::new(),
+            narrowing: None,
+            mark_ring: Vec::new(),
         }
     }
 
     /// Create a buffer from string content
     pub fn from_content(name: impl Into<String>, content: &str) -> Self {
-        let lines: Vec<Line> = if content.is_empty() {
-            vec![Line::new()]
-        } else {
-            content.lines().map(Line::from).collect()
-        };
-
-        let lines = if lines.is_empty() {
-            vec![Line::new()]
-        } else {
-            lines
-        };
-
         Self {
-            lines,
+            lines: lines_from_str(content),
             name: name.into(),
+            format: FileFormat::default(),
             filename: None,
             modified: false,
+            auto_save_due: false,
             modes: BufferModes::default(),
-            undo_stack: Vec::new(),
+            undo_tree: UndoTree::new(),
             recording_undo: false, // Don't record undo for generated buffers
+            markers: MarkerSet::new(),
+            narrowing: None,
+            mark_ring: Vec::new(),
         }
     }
 
     /// Set buffer content from string (replaces all lines)
     pub fn set_content(&mut self, content: &str) {
-        self.lines = if content.is_empty() {
-            vec![Line::new()]
-        } else {
-            content.lines().map(Line::from).collect()
-        };
-
-        if self.lines.is_empty() {
-            self.lines = vec![Line::new()];
-        }
-
+        self.widen();
+        self.lines = lines_from_str(content);
         self.modified = false;
-        self.undo_stack.clear();
+        self.auto_save_due = false;
+        self.undo_tree.clear();
     }
 
     /// Create a buffer from file contents
+    ///
+    /// UTF-8 files are read line by line so the whole text is never held in
+    /// memory twice, which matters for very large files. The encoding, line
+    /// endings, final newline and BOM are recorded so `write_to` reproduces
+    /// the file byte for byte. A file that cannot be decoded is an error.
     pub fn from_file(path: &PathBuf) -> std::io::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
         let name = path
             .file_name()
             .map(|s| s.to_string_lossy().into_owned())
             .unwrap_or_else(|| "unnamed".to_string());
-
-        let lines: Vec<Line> = if content.is_empty() {
-            vec![Line::new()]
-        } else {
-            content.lines().map(Line::from).collect()
-        };
-
-        // Ensure at least one line
-        let lines = if lines.is_empty() {
-            vec![Line::new()]
-        } else {
-            lines
-        };
+        let (lines, format) = read_lines(path, None)?;
 
         Ok(Self {
             lines,
             name,
+            format,
             filename: Some(path.clone()),
             modified: false,
+            auto_save_due: false,
             modes: BufferModes::default(),
-            undo_stack: Vec::new(),
+            undo_tree: UndoTree::new(),
             recording_undo: true,
+            markers: MarkerSet::new(),
+            narrowing: None,
+            mark_ring: Vec::new(),
         })
     }
 
+    /// Reload the buffer's file from disk, discarding changes and undo
+    pub fn reload(&mut self) -> std::io::Result<()> {
+        self.reload_with_encoding(None)
+    }
+
+    /// Reload the buffer's file in a given encoding (None = detect)
+    ///
+    /// The buffer is left untouched if the file cannot be decoded.
+    pub fn reload_with_encoding(&mut self, encoding: Option<Encoding>) -> std::io::Result<()> {
+        let path = self.filename.clone().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "No filename set")
+        })?;
+        let (lines, format) = read_lines(&path, encoding)?;
+        self.widen();
+        self.lines = lines;
+        self.format = format;
+        self.modified = false;
+        self.auto_save_due = false;
+        self.undo_tree.clear();
+        Ok(())
+    }
+
     /// Get buffer name
     pub fn name(&self) -> &str {
         &self.name
@@ -175,8 +213,60 @@ impl Buffer {
     }
 
     /// Mark buffer as modified
+    ///
+    /// An unmodified buffer's undo state is taken as the one matching its
+    /// file, so undo and redo back to it clear the flag again.
     pub fn set_modified(&mut self, modified: bool) {
         self.modified = modified;
+        self.auto_save_due = modified;
+        if !modified {
+            self.undo_tree.mark_clean();
+        }
+    }
+
+    /// Check if buffer has changed since it was last saved or auto-saved
+    pub fn auto_save_due(&self) -> bool {
+        self.auto_save_due
+    }
+
+    /// Note that the buffer has been auto-saved
+    pub fn set_auto_saved(&mut self) {
+        self.auto_save_due = false;
+    }
+
+    /// Get the on-disk file format
+    pub fn format(&self) -> &FileFormat {
+        &self.format
+    }
+
+    /// Convert every line to one line-ending style
+    pub fn set_line_ending(&mut self, ending: LineEnding) {
+        if self.format.line_ending == ending && !self.format.mixed {
+            return;
+        }
+        let overrides = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, line)| line.eol().map(|eol| (i, eol)))
+            .collect();
+        self.push_undo(UndoEntry::SetLineEnding {
+            from: self.format.line_ending,
+            mixed: self.format.mixed,
+            overrides,
+            to: ending,
+        });
+        self.convert_line_endings(ending);
+        self.set_modified(true);
+    }
+
+    /// Give every line one line-ending style without recording undo
+    fn convert_line_endings(&mut self, ending: LineEnding) {
+        for line in self.lines.iter_mut() {
+            line.set_eol(None);
+        }
+        self.format.line_ending = ending;
+        self.format.mixed = false;
     }
 
     /// Get buffer modes
@@ -204,16 +294,17 @@ impl Buffer {
         self.lines.get_mut(idx)
     }
 
-    /// Get all lines
-    pub fn lines(&self) -> &[Line] {
-        &self.lines
+    /// Iterate over all lines
+    pub fn lines(&self) -> impl Iterator<Item = &Line> {
+        self.lines.iter()
     }
 
     /// Insert a character at position
     pub fn insert_char(&mut self, line_idx: usize, byte_pos: usize, ch: char) {
         if let Some(line) = self.lines.get_mut(line_idx) {
             line.insert_char(byte_pos, ch);
-            self.modified = true;
+            self.set_modified(true);
+            self.markers.insert_text(line_idx, byte_pos, ch.len_utf8());
             self.push_undo(UndoEntry::Insert {
                 line: line_idx,
                 col: byte_pos,
@@ -222,12 +313,30 @@ impl Buffer {
         }
     }
 
+    /// Insert a string (without newlines) at position
+    pub fn insert_str(&mut self, line_idx: usize, byte_pos: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(line) = self.lines.get_mut(line_idx) {
+            line.insert_str(byte_pos, text);
+            self.set_modified(true);
+            self.markers.insert_text(line_idx, byte_pos, text.len());
+            self.push_undo(UndoEntry::Insert {
+                line: line_idx,
+                col: byte_pos,
+                text: text.to_string(),
+            });
+        }
+    }
+
     /// Insert a newline, splitting the current line
     pub fn insert_newline(&mut self, line_idx: usize, byte_pos: usize) {
         if let Some(line) = self.lines.get_mut(line_idx) {
             let new_line = line.split_off(byte_pos);
             self.lines.insert(line_idx + 1, new_line);
-            self.modified = true;
+            self.set_modified(true);
+            self.markers.split_line(line_idx, byte_pos);
             self.push_undo(UndoEntry::InsertNewline {
                 line: line_idx,
                 col: byte_pos,
@@ -237,13 +346,17 @@ impl Buffer {
 
     /// Delete a character at position, returns the deleted char
     pub fn delete_char(&mut self, line_idx: usize, byte_pos: usize) -> Option<char> {
+        if (line_idx, byte_pos) >= self.visible_end() {
+            return None;
+        }
         if let Some(line) = self.lines.get_mut(line_idx) {
             let text = line.text();
             if byte_pos < text.len() {
                 let ch = text[byte_pos..].chars().next()?;
                 let ch_len = ch.len_utf8();
                 line.delete_range(byte_pos, byte_pos + ch_len);
-                self.modified = true;
+                self.set_modified(true);
+                self.markers.delete_text(line_idx, byte_pos, ch_len);
                 self.push_undo(UndoEntry::Delete {
                     line: line_idx,
                     col: byte_pos,
@@ -257,6 +370,9 @@ impl Buffer {
 
     /// Delete backward (backspace), returns deleted char
     pub fn delete_backward(&mut self, line_idx: usize, byte_pos: usize) -> Option<(char, usize)> {
+        if (line_idx, byte_pos) <= self.visible_start() {
+            return None;
+        }
         if byte_pos > 0 {
             if let Some(line) = self.lines.get_mut(line_idx) {
                 // Find the char before byte_pos
@@ -266,7 +382,8 @@ impl Buffer {
                     let ch_len = ch.len_utf8();
                     let new_pos = byte_pos - ch_len;
                     line.delete_range(new_pos, byte_pos);
-                    self.modified = true;
+                    self.set_modified(true);
+                    self.markers.delete_text(line_idx, new_pos, ch_len);
                     self.push_undo(UndoEntry::Delete {
                         line: line_idx,
                         col: new_pos,
@@ -281,15 +398,21 @@ impl Buffer {
 
     /// Join line with the next line (delete newline at end of line)
     pub fn join_line(&mut self, line_idx: usize) -> bool {
+        if line_idx >= self.visible_end().0 {
+            return false;
+        }
         if line_idx + 1 < self.lines.len() {
             let next_line = self.lines.remove(line_idx + 1);
             if let Some(line) = self.lines.get_mut(line_idx) {
                 let join_col = line.len();
+                let eol = line.eol();
                 line.append(next_line);
-                self.modified = true;
+                self.set_modified(true);
+                self.markers.join_lines(line_idx, join_col);
                 self.push_undo(UndoEntry::DeleteNewline {
                     line: line_idx,
                     col: join_col,
+                    eol,
                 });
                 return true;
             }
@@ -299,15 +422,21 @@ impl Buffer {
 
     /// Join with previous line (when backspacing at start of line)
     pub fn join_with_previous(&mut self, line_idx: usize) -> Option<usize> {
+        if line_idx <= self.visible_start().0 {
+            return None;
+        }
         if line_idx > 0 {
             let current_line = self.lines.remove(line_idx);
             if let Some(prev_line) = self.lines.get_mut(line_idx - 1) {
                 let join_pos = prev_line.len();
+                let eol = prev_line.eol();
                 prev_line.append(current_line);
-                self.modified = true;
+                self.set_modified(true);
+                self.markers.join_lines(line_idx - 1, join_pos);
                 self.push_undo(UndoEntry::DeleteNewline {
                     line: line_idx - 1,
                     col: join_pos,
+                    eol,
                 });
                 return Some(join_pos);
             }
@@ -317,9 +446,20 @@ impl Buffer {
 
     /// Delete a line by index
     pub fn delete_line(&mut self, line_idx: usize) {
+        // When narrowed, only whole lines inside the narrowing can go
+        let len = self.lines.get(line_idx).map(|l| l.len()).unwrap_or(0);
+        if self.is_narrowed() && self.visible_span(line_idx) != Some((0, len)) {
+            return;
+        }
         if line_idx < self.lines.len() && self.lines.len() > 1 {
             let removed = self.lines.remove(line_idx);
-            self.modified = true;
+            self.set_modified(true);
+            // Markers on the line end up where it was joined to its neighbour
+            let to = match line_idx {
+                0 => (0, 0),
+                _ => (line_idx - 1, self.lines.get(line_idx - 1).map(|l| l.len()).unwrap_or(0)),
+            };
+            self.markers.remove_line(line_idx, to);
             // Record deletion of line content
             if !removed.is_empty() {
                 self.push_undo(UndoEntry::Delete {
@@ -330,43 +470,47 @@ impl Buffer {
             }
             // Record deletion of the newline that joined this with next/prev
             if line_idx > 0 {
-                // Line was joined with previous
-                self.push_undo(UndoEntry::DeleteNewline {
-                    line: line_idx - 1,
-                    col: self.lines.get(line_idx - 1).map(|l| l.len()).unwrap_or(0),
-                });
+                // Line was joined with previous, which takes over its ending
+                let prev = self.lines.get_mut(line_idx - 1).expect("line before a removed line");
+                let eol = prev.eol();
+                prev.set_eol(removed.eol());
+                let col = prev.len();
+                self.push_undo(UndoEntry::DeleteNewline { line: line_idx - 1, col, eol });
+            } else {
+                // First line: the (now empty) line was joined with the next
+                self.push_undo(UndoEntry::DeleteNewline { line: 0, col: 0, eol: removed.eol() });
             }
         }
     }
 
-    /// Append a new empty line at the end of the buffer
-    pub fn append_line(&mut self) {
-        self.lines.push(Line::new());
-        self.modified = true;
-    }
-
     /// Insert a new empty line at the given index
     pub fn insert_line_at(&mut self, line_idx: usize) {
         if line_idx <= self.lines.len() {
             self.lines.insert(line_idx, Line::new());
-            self.modified = true;
+            self.set_modified(true);
+            self.markers.insert_line(line_idx);
         }
     }
 
     /// Kill from position to end of line, returns killed text
     pub fn kill_to_eol(&mut self, line_idx: usize, byte_pos: usize) -> Option<String> {
+        let visible_end = self.visible_end();
         if let Some(line) = self.lines.get_mut(line_idx) {
-            let line_len = line.len();
+            let line_len = match line_idx == visible_end.0 {
+                true => visible_end.1.min(line.len()),
+                false => line.len(),
+            };
             if byte_pos < line_len {
                 let killed = line.delete_range(byte_pos, line_len);
-                self.modified = true;
+                self.set_modified(true);
+                self.markers.delete_text(line_idx, byte_pos, killed.len());
                 self.push_undo(UndoEntry::Delete {
                     line: line_idx,
                     col: byte_pos,
                     text: killed.clone(),
                 });
                 return Some(killed);
-            } else if line_idx + 1 < self.lines.len() {
+            } else if line_idx + 1 < self.lines.len() && line_idx < visible_end.0 {
                 // At end of line, kill the newline (join with next)
                 // join_line will record its own undo entry
                 self.join_line(line_idx);
@@ -376,6 +520,27 @@ impl Buffer {
         None
     }
 
+    /// Delete bytes `start..end` of a line, returns the deleted text
+    ///
+    /// The range is clipped to the visible part of the line.
+    pub fn delete_range(&mut self, line_idx: usize, start: usize, end: usize) -> Option<String> {
+        let (visible_start, visible_end) = self.visible_span(line_idx)?;
+        let (start, end) = (start.max(visible_start), end.min(visible_end));
+        let line = self.lines.get_mut(line_idx)?;
+        if start >= end || end > line.len() {
+            return None;
+        }
+        let deleted = line.delete_range(start, end);
+        self.set_modified(true);
+        self.markers.delete_text(line_idx, start, deleted.len());
+        self.push_undo(UndoEntry::Delete {
+            line: line_idx,
+            col: start,
+            text: deleted.clone(),
+        });
+        Some(deleted)
+    }
+
     /// Write buffer to file
     pub fn save(&self) -> std::io::Result<()> {
         if let Some(path) = &self.filename {
@@ -389,109 +554,757 @@ impl Buffer {
     }
 
     /// Write buffer to specific path
+    ///
+    /// Text in other encodings is encoded in memory first, so a character
+    /// the encoding cannot represent fails the save before the file is touched.
     pub fn write_to(&self, path: &PathBuf) -> std::io::Result<()> {
         use std::io::Write;
-        let mut file = std::fs::File::create(path)?;
-        for (i, line) in self.lines.iter().enumerate() {
-            write!(file, "{}", line.text())?;
-            if i < self.lines.len() - 1 {
-                writeln!(file)?;
+        let encoding = self.format.encoding;
+        if encoding != Encoding::Utf8 {
+            let mut data = Vec::new();
+            file_format::write_bom(&mut data, &self.format)?;
+            for (text, ending) in self.terminated_lines() {
+                encoding.encode(text, &mut data)?;
+                encoding.encode(ending, &mut data)?;
             }
+            return std::fs::write(path, data);
         }
-        Ok(())
+
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        file_format::write_bom(&mut file, &self.format)?;
+        for (text, ending) in self.terminated_lines() {
+            file.write_all(text.as_bytes())?;
+            file.write_all(ending.as_bytes())?;
+        }
+        file.flush()
+    }
+
+    /// Iterate over (text, terminator) pairs as they are written to disk
+    pub fn terminated_lines(&self) -> impl Iterator<Item = (&str, &'static str)> {
+        let last = self.lines.len().saturating_sub(1);
+        let format = self.format;
+        self.lines.iter().enumerate().map(move |(i, line)| {
+            let ending = if i < last || format.final_newline {
+                line.eol().unwrap_or(format.line_ending).as_str()
+            } else {
+                ""
+            };
+            (line.text(), ending)
+        })
     }
 
     /// Push an undo entry (if recording is enabled)
     fn push_undo(&mut self, entry: UndoEntry) {
         if self.recording_undo {
-            self.undo_stack.push(entry);
+            self.undo_tree.push(entry);
         }
     }
 
     /// Add a boundary marker to group operations
     pub fn add_undo_boundary(&mut self) {
-        if self.recording_undo && !self.undo_stack.is_empty() {
-            // Only add if last entry isn't already a boundary
-            if !matches!(self.undo_stack.last(), Some(UndoEntry::Boundary)) {
-                self.undo_stack.push(UndoEntry::Boundary);
-            }
-        }
+        self.push_undo(UndoEntry::Boundary);
     }
 
     /// Perform undo, returns (line, col) to move cursor to, or None if nothing to undo
     pub fn undo(&mut self) -> Option<(usize, usize)> {
-        // Disable recording while undoing
-        self.recording_undo = false;
+        let entries = self.undo_tree.undo()?;
+        let cursor_pos = self.revert_entries(&entries);
+        self.set_modified(!self.undo_tree.is_saved());
+        cursor_pos
+    }
 
-        // Skip any boundary at the top
-        while matches!(self.undo_stack.last(), Some(UndoEntry::Boundary)) {
-            self.undo_stack.pop();
-        }
+    /// Redo the most recently undone change on the current branch
+    pub fn redo(&mut self) -> Option<(usize, usize)> {
+        let entries = self.undo_tree.redo()?;
+        let cursor_pos = self.apply_entries(&entries);
+        self.set_modified(!self.undo_tree.is_saved());
+        cursor_pos
+    }
 
+    /// Move to any state in the undo tree, returns the cursor position
+    pub fn goto_undo_state(&mut self, state: usize) -> Option<(usize, usize)> {
+        let (ups, downs) = self.undo_tree.path_to(state)?;
         let mut cursor_pos = None;
+        for _ in 0..ups {
+            cursor_pos = self.undo().or(cursor_pos);
+        }
+        for child in downs {
+            self.undo_tree.select_child(child);
+            cursor_pos = self.redo().or(cursor_pos);
+        }
+        cursor_pos
+    }
+
+    /// Get the undo history
+    pub fn undo_tree(&self) -> &UndoTree {
+        &self.undo_tree
+    }
 
-        // Undo entries until we hit a boundary or empty
-        while let Some(entry) = self.undo_stack.pop() {
+    /// Get mutable access to the undo history
+    pub fn undo_tree_mut(&mut self) -> &mut UndoTree {
+        &mut self.undo_tree
+    }
+
+    /// Replace the undo history (e.g. with one restored from disk)
+    pub fn set_undo_tree(&mut self, tree: UndoTree) {
+        self.undo_tree = tree;
+    }
+
+    /// Revert a change group (last entry first)
+    fn revert_entries(&mut self, entries: &[UndoEntry]) -> Option<(usize, usize)> {
+        let mut cursor_pos = None;
+        for entry in entries.iter().rev() {
             match entry {
-                UndoEntry::Boundary => break,
+                UndoEntry::Boundary => {}
                 UndoEntry::Insert { line, col, text } => {
                     // Text was inserted, so delete it
-                    if let Some(line_ref) = self.lines.get_mut(line) {
+                    if let Some(line_ref) = self.lines.get_mut(*line) {
                         let end = col + text.len();
                         if end <= line_ref.len() {
-                            line_ref.delete_range(col, end);
+                            line_ref.delete_range(*col, end);
+                            self.markers.delete_text(*line, *col, text.len());
                         }
                     }
-                    cursor_pos = Some((line, col));
+                    cursor_pos = Some((*line, *col));
                 }
                 UndoEntry::Delete { line, col, text } => {
                     // Text was deleted, so insert it back
-                    if let Some(line_ref) = self.lines.get_mut(line) {
-                        let text_to_insert = text.clone();
-                        for (i, ch) in text_to_insert.chars().enumerate() {
-                            line_ref.insert_char(col + i, ch);
+                    if let Some(line_ref) = self.lines.get_mut(*line) {
+                        if *col <= line_ref.len() {
+                            line_ref.insert_str(*col, text);
+                            self.markers.insert_text(*line, *col, text.len());
                         }
                     }
-                    cursor_pos = Some((line, col + text.len()));
+                    cursor_pos = Some((*line, col + text.len()));
                 }
                 UndoEntry::InsertNewline { line, col } => {
                     // Newline was inserted, so join the lines
-                    if line + 1 < self.lines.len() {
-                        let next_line = self.lines.remove(line + 1);
-                        if let Some(current) = self.lines.get_mut(line) {
-                            current.append(next_line);
-                        }
-                    }
-                    cursor_pos = Some((line, col));
+                    self.join_raw(*line);
+                    cursor_pos = Some((*line, *col));
                 }
-                UndoEntry::DeleteNewline { line, col } => {
+                UndoEntry::DeleteNewline { line, col, eol } => {
                     // Newline was deleted (lines joined), so split them
-                    if let Some(line_ref) = self.lines.get_mut(line) {
-                        let new_line = line_ref.split_off(col);
-                        self.lines.insert(line + 1, new_line);
+                    self.split_raw(*line, *col);
+                    if let Some(line_ref) = self.lines.get_mut(*line) {
+                        line_ref.set_eol(*eol);
                     }
-                    cursor_pos = Some((line, col));
+                    cursor_pos = Some((*line, *col));
+                }
+                UndoEntry::SetLineEnding { from, mixed, overrides, .. } => {
+                    self.format.line_ending = *from;
+                    self.format.mixed = *mixed;
+                    for &(line, eol) in overrides {
+                        if let Some(line_ref) = self.lines.get_mut(line) {
+                            line_ref.set_eol(Some(eol));
+                        }
+                    }
+                    cursor_pos = cursor_pos.or(Some((0, 0)));
                 }
             }
         }
+        cursor_pos
+    }
 
-        self.recording_undo = true;
+    /// Reapply a change group (first entry first)
+    fn apply_entries(&mut self, entries: &[UndoEntry]) -> Option<(usize, usize)> {
+        let mut cursor_pos = None;
+        for entry in entries {
+            match entry {
+                UndoEntry::Boundary => {}
+                UndoEntry::Insert { line, col, text } => {
+                    if let Some(line_ref) = self.lines.get_mut(*line) {
+                        if *col <= line_ref.len() {
+                            line_ref.insert_str(*col, text);
+                            self.markers.insert_text(*line, *col, text.len());
+                        }
+                    }
+                    cursor_pos = Some((*line, col + text.len()));
+                }
+                UndoEntry::Delete { line, col, text } => {
+                    if let Some(line_ref) = self.lines.get_mut(*line) {
+                        let end = col + text.len();
+                        if end <= line_ref.len() {
+                            line_ref.delete_range(*col, end);
+                            self.markers.delete_text(*line, *col, text.len());
+                        }
+                    }
+                    cursor_pos = Some((*line, *col));
+                }
+                UndoEntry::InsertNewline { line, col } => {
+                    self.split_raw(*line, *col);
+                    cursor_pos = Some((line + 1, 0));
+                }
+                UndoEntry::DeleteNewline { line, col, .. } => {
+                    self.join_raw(*line);
+                    cursor_pos = Some((*line, *col));
+                }
+                UndoEntry::SetLineEnding { to, .. } => {
+                    self.convert_line_endings(*to);
+                    cursor_pos = cursor_pos.or(Some((0, 0)));
+                }
+            }
+        }
+        cursor_pos
+    }
 
-        if cursor_pos.is_some() {
-            self.modified = true;
+    /// Split a line without recording undo
+    fn split_raw(&mut self, line_idx: usize, col: usize) {
+        if let Some(line_ref) = self.lines.get_mut(line_idx) {
+            if col <= line_ref.len() {
+                let new_line = line_ref.split_off(col);
+                self.lines.insert(line_idx + 1, new_line);
+                self.markers.split_line(line_idx, col);
+            }
         }
+    }
 
-        cursor_pos
+    /// Join a line with the next without recording undo
+    fn join_raw(&mut self, line_idx: usize) {
+        if line_idx + 1 < self.lines.len() {
+            let next_line = self.lines.remove(line_idx + 1);
+            if let Some(current) = self.lines.get_mut(line_idx) {
+                let join_col = current.len();
+                current.append(next_line);
+                self.markers.join_lines(line_idx, join_col);
+            }
+        }
     }
 
     /// Check if there's anything to undo
     pub fn can_undo(&self) -> bool {
-        self.undo_stack.iter().any(|e| !matches!(e, UndoEntry::Boundary))
+        self.undo_tree.can_undo()
+    }
+
+    /// Register a marker that follows edits, starting at (line, col)
+    pub fn add_marker(&mut self, line: usize, col: usize) -> MarkerId {
+        self.markers.add(line, col)
+    }
+
+    /// Get a marker's position, or None if it isn't in this buffer
+    ///
+    /// The position is clamped to the buffer, since lines edited directly
+    /// through `line_mut` or replaced wholesale don't update markers.
+    pub fn marker(&self, id: MarkerId) -> Option<(usize, usize)> {
+        let (line, col) = self.markers.get(id)?;
+        Some(self.clamp_position(line, col))
+    }
+
+    /// Nearest position in the buffer to (line, col): the last line if
+    /// past the end, and the end of the line or the start of a character
+    /// for the column
+    pub fn clamp_position(&self, line: usize, col: usize) -> (usize, usize) {
+        let line = line.min(self.lines.len().saturating_sub(1));
+        let text = self.lines.get(line).map(|l| l.text()).unwrap_or("");
+        let mut col = col.min(text.len());
+        while !text.is_char_boundary(col) {
+            col -= 1;
+        }
+        (line, col)
+    }
+
+    /// Move a marker, returns false if it isn't in this buffer
+    pub fn set_marker(&mut self, id: MarkerId, line: usize, col: usize) -> bool {
+        self.markers.set(id, line, col)
+    }
+
+    /// Unregister a marker (a no-op if it isn't in this buffer)
+    pub fn remove_marker(&mut self, id: MarkerId) {
+        self.markers.remove(id);
+    }
+
+    /// Restrict the buffer to the text between two positions
+    ///
+    /// The bounds are markers, so the narrowed part grows and shrinks as it
+    /// is edited; text typed at its end stays inside it.
+    pub fn narrow(&mut self, start: (usize, usize), end: (usize, usize)) {
+        self.widen();
+        let start = self.markers.add(start.0, start.1);
+        let end = self.markers.add_advancing(end.0, end.1);
+        self.narrowing = Some((start, end));
+    }
+
+    /// Remove any narrowing
+    pub fn widen(&mut self) {
+        if let Some((start, end)) = self.narrowing.take() {
+            self.markers.remove(start);
+            self.markers.remove(end);
+        }
+    }
+
+    /// Check if the buffer is narrowed
+    pub fn is_narrowed(&self) -> bool {
+        self.narrowing.is_some()
+    }
+
+    /// First visible position (the start of the buffer unless narrowed)
+    pub fn visible_start(&self) -> (usize, usize) {
+        self.narrowing
+            .and_then(|(start, _)| self.marker(start))
+            .unwrap_or((0, 0))
+    }
+
+    /// Last visible position (the end of the buffer unless narrowed)
+    pub fn visible_end(&self) -> (usize, usize) {
+        let last = self.lines.len().saturating_sub(1);
+        let buffer_end = (last, self.lines.get(last).map(|l| l.len()).unwrap_or(0));
+        self.narrowing
+            .and_then(|(_, end)| self.marker(end))
+            .unwrap_or(buffer_end)
+    }
+
+    /// Visible byte range of a line, or None if the line is outside the
+    /// narrowing
+    pub fn visible_span(&self, line_idx: usize) -> Option<(usize, usize)> {
+        let len = self.lines.get(line_idx)?.len();
+        let (start, end) = (self.visible_start(), self.visible_end());
+        if line_idx < start.0 || line_idx > end.0 {
+            return None;
+        }
+        let from = if line_idx == start.0 { start.1 } else { 0 };
+        let to = if line_idx == end.0 { end.1 } else { len };
+        Some((from, to.max(from)))
+    }
+
+    /// Move a position into the visible part of the buffer
+    pub fn clamp_visible(&self, line: usize, col: usize) -> (usize, usize) {
+        (line, col).clamp(self.visible_start(), self.visible_end())
+    }
+
+    /// Push a position onto the mark ring, dropping the oldest entry if
+    /// the ring is full (a repeat of the newest entry is ignored)
+    pub fn push_mark(&mut self, line: usize, col: usize) {
+        let newest = self.mark_ring.last().and_then(|&id| self.marker(id));
+        if newest == Some((line, col)) {
+            return;
+        }
+        if self.mark_ring.len() >= MARK_RING_MAX {
+            let oldest = self.mark_ring.remove(0);
+            self.markers.remove(oldest);
+        }
+        let id = self.markers.add(line, col);
+        self.mark_ring.push(id);
+    }
+
+    /// Take the newest position off the mark ring
+    pub fn pop_mark(&mut self) -> Option<(usize, usize)> {
+        let id = self.mark_ring.pop()?;
+        let pos = self.marker(id);
+        self.markers.remove(id);
+        pos
+    }
+
+    /// Take the newest position off the mark ring and put `(line, col)` at
+    /// its oldest end, so repeated calls cycle through the ring
+    pub fn rotate_mark_ring(&mut self, line: usize, col: usize) -> Option<(usize, usize)> {
+        let id = self.mark_ring.pop()?;
+        let pos = self.marker(id);
+        self.markers.set(id, line, col);
+        self.mark_ring.insert(0, id);
+        pos
     }
 }
 
+/// Read a file into a line store, detecting its format
+///
+/// With no encoding given, a BOM selects UTF-8 or UTF-16; otherwise the
+/// file is tried as UTF-8 and falls back to a single-byte encoding.
+fn read_lines(path: &PathBuf, encoding: Option<Encoding>) -> std::io::Result<(LineStore, FileFormat)> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = BufReader::with_capacity(1 << 16, file);
+    let bom = Encoding::from_bom(reader.fill_buf()?);
+    let encoding = encoding.or(bom);
+    let has_bom = bom.is_some() && bom == encoding;
+    if let (true, Some(enc)) = (has_bom, encoding) {
+        reader.consume(enc.bom().len());
+    }
+
+    let (lines, mut format) = match encoding {
+        None | Some(Encoding::Utf8) => match split_lines(&mut reader) {
+            Ok(result) => result,
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData && encoding.is_none() => {
+                let data = std::fs::read(path)?;
+                decode_lines(&data, Encoding::detect_single_byte(&data))?
+            }
+            Err(e) => return Err(e),
+        },
+        Some(enc) => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            decode_lines(&data, enc)?
+        }
+    };
+    format.bom = has_bom;
+    Ok((lines, format))
+}
+
+/// Decode a whole file in a non-streaming encoding and split it into lines
+fn decode_lines(data: &[u8], encoding: Encoding) -> std::io::Result<(LineStore, FileFormat)> {
+    let text = encoding.decode(data)?;
+    let (lines, mut format) = split_lines(&mut text.as_bytes())?;
+    format.encoding = encoding;
+    Ok((lines, format))
+}
+
+/// Split UTF-8 input into lines, recording line endings and final newline
+fn split_lines<R: BufRead>(reader: &mut R) -> std::io::Result<(LineStore, FileFormat)> {
+    let mut lines = LineStore::new();
+    let mut format = FileFormat::unterminated();
+    let mut counts = [0usize; 3];
+    let mut raw = Vec::new();
+
+    loop {
+        let ending = file_format::read_raw_line(reader, &mut raw)?;
+        if ending.is_none() && raw.is_empty() {
+            break;
+        }
+        let text = String::from_utf8(std::mem::take(&mut raw))
+            .map_err(|_| Encoding::Utf8.decode_error())?;
+        let mut line = Line::from_string(text);
+        line.set_eol(ending);
+        lines.push(line);
+
+        match ending {
+            Some(ending) => counts[file_format::ending_index(ending)] += 1,
+            None => break,
+        }
+    }
+
+    format.final_newline = lines
+        .len()
+        .checked_sub(1)
+        .and_then(|last| lines.get(last))
+        .is_some_and(|l| l.eol().is_some());
+    format.line_ending = file_format::dominant_ending(counts);
+    format.mixed = counts.iter().filter(|&&c| c > 0).count() > 1;
+
+    // Keep overrides only where a line differs from the dominant style
+    for line in lines.iter_mut() {
+        if line.eol() == Some(format.line_ending) || !format.mixed {
+            line.set_eol(None);
+        }
+    }
+
+    // Ensure at least one line
+    if lines.is_empty() {
+        lines.push(Line::new());
+    }
+
+    Ok((lines, format))
+}
+
+/// Split string content into a line store (always at least one line)
+fn lines_from_str(content: &str) -> LineStore {
+    let mut lines = LineStore::from_lines(content.lines().map(Line::from));
+    if lines.is_empty() {
+        lines.push(Line::new());
+    }
+    lines
+}
+
 impl Default for Buffer {
     fn default() -> Self {
         Self::new("*scratch*")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load `data` from a temp file, save it back and return the bytes written
+    fn roundtrip(name: &str, data: &[u8]) -> (Buffer, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!("uemacs-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        let buffer = Buffer::from_file(&path).unwrap();
+        buffer.save().unwrap();
+        let written = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        (buffer, written)
+    }
+
+    #[test]
+    fn test_roundtrip_preserves_format() {
+        let cases: &[&[u8]] = &[
+            b"",
+            b"\n",
+            b"one\ntwo\n",
+            b"one\ntwo",
+            b"one\r\ntwo\r\n",
+            b"one\rtwo\r",
+            b"a\nb\r\nc\rd\n\n",
+            b"\xEF\xBB\xBFbom\r\n",
+        ];
+        for (i, data) in cases.iter().enumerate() {
+            let (_, written) = roundtrip(&format!("fmt{}", i), data);
+            assert_eq!(&written, data, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn test_detects_format() {
+        let (buffer, _) = roundtrip("detect", b"\xEF\xBB\xBFa\r\nb\r\nc\nd");
+        let format = buffer.format();
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+        assert!(format.mixed);
+        assert!(format.bom);
+        assert!(!format.final_newline);
+        assert_eq!(buffer.line(0).unwrap().text(), "a");
+        assert_eq!(buffer.line_count(), 4);
+    }
+
+    #[test]
+    fn test_roundtrip_other_encodings() {
+        let cases: &[(&[u8], Encoding)] = &[
+            (b"\xFF\xFEa\0\r\0\n\0\xE9\0", Encoding::Utf16Le),
+            (b"\xFE\xFF\0a\0\n", Encoding::Utf16Be),
+            (b"caf\xE9 \x80\n", Encoding::Windows1252),
+            (b"\x7FELF\0\x01\xFF\n", Encoding::Raw),
+        ];
+        for (i, (data, encoding)) in cases.iter().enumerate() {
+            let (buffer, written) = roundtrip(&format!("enc{}", i), data);
+            assert_eq!(buffer.format().encoding, *encoding, "case {}", i);
+            assert_eq!(&written, data, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn test_undecodable_file_is_an_error() {
+        let path = std::env::temp_dir().join(format!("uemacs-test-{}-bad-utf16", std::process::id()));
+        std::fs::write(&path, b"\xFF\xFEa").unwrap();
+        let err = Buffer::from_file(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        std::fs::write(&path, b"caf\xE9").unwrap();
+        let mut buffer = Buffer::from_file(&path).unwrap();
+        assert_eq!(buffer.line(0).unwrap().text(), "caf\u{e9}");
+        assert!(buffer.reload_with_encoding(Some(Encoding::Utf8)).is_err());
+        assert_eq!(buffer.format().encoding, Encoding::Windows1252);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unencodable_save_leaves_file_intact() {
+        let path = std::env::temp_dir().join(format!("uemacs-test-{}-latin1", std::process::id()));
+        std::fs::write(&path, b"caf\xE9").unwrap();
+        let mut buffer = Buffer::from_file(&path).unwrap();
+        buffer.reload_with_encoding(Some(Encoding::Latin1)).unwrap();
+        buffer.insert_char(0, 0, '\u{4e2d}');
+        assert!(buffer.save().is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xE9");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn text_of(buffer: &Buffer) -> String {
+        buffer.lines().map(|l| l.text()).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn test_undo_redo_roundtrip() {
+        let mut buffer = Buffer::new("undo");
+        let mut col = 0;
+        for ch in "héllo".chars() {
+            buffer.insert_char(0, col, ch);
+            col += ch.len_utf8();
+        }
+        buffer.add_undo_boundary();
+        buffer.insert_newline(0, 3);
+        buffer.add_undo_boundary();
+        buffer.delete_backward(1, 2);
+        buffer.delete_line(0);
+        assert_eq!(text_of(&buffer), "lo");
+
+        buffer.undo();
+        assert_eq!(text_of(&buffer), "hé\nllo");
+        buffer.undo();
+        assert_eq!(text_of(&buffer), "héllo");
+        buffer.redo();
+        buffer.redo();
+        assert_eq!(text_of(&buffer), "lo");
+        assert!(buffer.redo().is_none());
+    }
+
+    #[test]
+    fn test_undo_to_saved_state_clears_modified() {
+        let mut buffer = Buffer::new("saved");
+        buffer.insert_char(0, 0, 'a');
+        buffer.add_undo_boundary();
+        // Saved here
+        buffer.set_modified(false);
+        buffer.insert_char(0, 1, 'b');
+        assert!(buffer.is_modified());
+
+        buffer.undo();
+        assert!(!buffer.is_modified());
+        buffer.undo();
+        assert!(buffer.is_modified());
+        buffer.redo();
+        assert!(!buffer.is_modified());
+        buffer.redo();
+        assert!(buffer.is_modified());
+    }
+
+    #[test]
+    fn test_goto_undo_state_across_branches() {
+        let mut buffer = Buffer::new("tree");
+        buffer.insert_char(0, 0, 'a');
+        buffer.add_undo_boundary();
+        buffer.undo();
+        buffer.insert_char(0, 0, 'b');
+        buffer.add_undo_boundary();
+        assert_eq!(text_of(&buffer), "b");
+
+        buffer.goto_undo_state(1);
+        assert_eq!(text_of(&buffer), "a");
+        buffer.goto_undo_state(2);
+        assert_eq!(text_of(&buffer), "b");
+        buffer.goto_undo_state(0);
+        assert_eq!(text_of(&buffer), "");
+    }
+
+    #[test]
+    fn test_set_line_ending_converts_mixed() {
+        let (mut buffer, _) = roundtrip("convert", b"a\r\nb\nc\r\n");
+        buffer.set_line_ending(LineEnding::Lf);
+        assert!(buffer.is_modified());
+        assert!(buffer.auto_save_due());
+        buffer.set_auto_saved();
+        assert!(buffer.is_modified() && !buffer.auto_save_due());
+        assert!(!buffer.format().mixed);
+        let path = std::env::temp_dir().join(format!("uemacs-test-{}-convert-out", std::process::id()));
+        buffer.write_to(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\nb\nc\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_undo_keeps_line_endings() {
+        let on_disk = |buffer: &Buffer| {
+            buffer.terminated_lines().map(|(text, ending)| format!("{}{}", text, ending)).collect::<String>()
+        };
+        let (mut buffer, _) = roundtrip("undo-eol", b"a\nb\r\nc\r\nd\rx\r\ne\r\n");
+        let original = on_disk(&buffer);
+
+        // Joins and line deletions undo back to each line's own ending
+        buffer.join_line(0);
+        buffer.add_undo_boundary();
+        buffer.join_with_previous(1);
+        buffer.add_undo_boundary();
+        buffer.delete_line(1);
+        buffer.add_undo_boundary();
+        buffer.delete_line(0);
+        buffer.add_undo_boundary();
+        let edited = on_disk(&buffer);
+        assert_eq!(edited, "x\r\ne\r\n");
+        while buffer.undo().is_some() {}
+        assert_eq!(on_disk(&buffer), original);
+        while buffer.redo().is_some() {}
+        assert_eq!(on_disk(&buffer), edited);
+        while buffer.undo().is_some() {}
+
+        // Converting line endings is undone and redone too
+        buffer.set_line_ending(LineEnding::Lf);
+        buffer.add_undo_boundary();
+        assert_eq!(on_disk(&buffer), "a\nb\nc\nd\nx\ne\n");
+        assert!(buffer.undo().is_some());
+        assert_eq!(on_disk(&buffer), original);
+        assert!(buffer.format().mixed);
+        assert!(buffer.redo().is_some());
+        assert_eq!(on_disk(&buffer), "a\nb\nc\nd\nx\ne\n");
+        assert!(!buffer.format().mixed);
+    }
+
+    #[test]
+    fn test_markers_follow_edits_and_undo() {
+        let mut buffer = Buffer::from_content("markers", "one\ntwo\nthree");
+        let mark = buffer.add_marker(2, 3);
+        buffer.insert_newline(0, 1);
+        buffer.insert_char(3, 0, '>');
+        assert_eq!(buffer.marker(mark), Some((3, 4)));
+        buffer.join_line(2);
+        assert_eq!(buffer.marker(mark), Some((2, 7)));
+        buffer.delete_line(1);
+        assert_eq!(buffer.marker(mark), Some((1, 7)));
+
+        let mut buffer = Buffer::new("undo");
+        buffer.insert_char(0, 0, 'a');
+        buffer.add_undo_boundary();
+        buffer.insert_newline(0, 0);
+        let mark = buffer.add_marker(1, 1);
+        buffer.undo();
+        assert_eq!(buffer.marker(mark), Some((0, 1)));
+        buffer.redo();
+        assert_eq!(buffer.marker(mark), Some((1, 1)));
+        buffer.remove_marker(mark);
+        assert_eq!(buffer.marker(mark), None);
+    }
+
+    #[test]
+    fn test_insert_str_and_delete_range_undo() {
+        let mut buffer = Buffer::new("rect");
+        buffer.insert_str(0, 0, "ab\tcd");
+        buffer.add_undo_boundary();
+        let mark = buffer.add_marker(0, 3);
+        buffer.insert_str(0, 2, "XY");
+        assert_eq!(text_of(&buffer), "abXY\tcd");
+        assert_eq!(buffer.marker(mark), Some((0, 5)));
+        buffer.add_undo_boundary();
+        assert_eq!(buffer.delete_range(0, 1, 4).as_deref(), Some("bXY"));
+        assert_eq!(text_of(&buffer), "a\tcd");
+        buffer.add_undo_boundary();
+
+        buffer.undo();
+        assert_eq!(text_of(&buffer), "abXY\tcd");
+        buffer.undo();
+        assert_eq!(text_of(&buffer), "ab\tcd");
+        assert_eq!(buffer.marker(mark), Some((0, 3)));
+    }
+
+    #[test]
+    fn test_narrowing_limits_edits() {
+        let mut buffer = Buffer::from_content("narrow", "one\ntwo three\nfour\nfive");
+        buffer.narrow((1, 4), (2, 2));
+        assert_eq!(buffer.visible_span(0), None);
+        assert_eq!(buffer.visible_span(1), Some((4, 9)));
+        assert_eq!(buffer.visible_span(2), Some((0, 2)));
+        assert_eq!(buffer.clamp_visible(3, 0), (2, 2));
+
+        // Nothing outside the narrowing can be deleted
+        assert_eq!(buffer.delete_backward(1, 4), None);
+        assert_eq!(buffer.join_with_previous(1), None);
+        assert_eq!(buffer.delete_char(2, 2), None);
+        assert!(!buffer.join_line(2));
+        assert_eq!(buffer.kill_to_eol(2, 0).as_deref(), Some("fo"));
+        assert_eq!(buffer.delete_range(1, 0, 9).as_deref(), Some("three"));
+
+        // Text typed at the end stays inside
+        buffer.insert_str(2, 0, "4");
+        assert_eq!(buffer.visible_end(), (2, 1));
+        assert_eq!(text_of(&buffer), "one\ntwo \n4ur\nfive");
+
+        buffer.widen();
+        assert_eq!(buffer.visible_start(), (0, 0));
+        assert_eq!(buffer.visible_end(), (3, 4));
+    }
+
+    #[test]
+    fn test_mark_ring() {
+        let mut buffer = Buffer::from_content("ring", "one\ntwo\nthree");
+        buffer.push_mark(0, 1);
+        buffer.push_mark(1, 2);
+        buffer.push_mark(1, 2);
+        buffer.push_mark(2, 0);
+        // Entries follow edits
+        buffer.insert_str(1, 0, "xx");
+        assert_eq!(buffer.rotate_mark_ring(0, 0), Some((2, 0)));
+        assert_eq!(buffer.rotate_mark_ring(2, 0), Some((1, 4)));
+        assert_eq!(buffer.pop_mark(), Some((0, 1)));
+        assert_eq!(buffer.pop_mark(), Some((0, 0)));
+        assert_eq!(buffer.pop_mark(), Some((2, 0)));
+        assert_eq!(buffer.pop_mark(), None);
+
+        for line in 0..MARK_RING_MAX + 2 {
+            buffer.push_mark(line, 0);
+        }
+        assert_eq!(buffer.mark_ring.len(), MARK_RING_MAX);
+    }
+}
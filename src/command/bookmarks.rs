@@ -0,0 +1,219 @@
+//! Bookmark commands - named positions in files, kept across sessions
+//!
+//! In the *Bookmarks* buffer, jump, rename and delete act on the bookmark
+//! on the cursor line instead of asking for a name.
+
+use crate::bookmark_store::{self, Bookmark};
+use crate::editor::{EditorState, PromptAction};
+use crate::error::Result;
+use super::CommandStatus;
+
+/// Name of the bookmark list buffer
+const LIST_BUFFER: &str = "*Bookmarks*";
+
+/// Lines before the first bookmark in the list buffer
+const LIST_HEADER_LINES: usize = 4;
+
+/// Set a bookmark at point (C-x r m)
+pub fn bookmark_set(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let default = match editor.current_buffer().filename() {
+        Some(path) => path
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        None => {
+            editor.display.set_message("Buffer is not visiting a file");
+            return Ok(CommandStatus::Failure);
+        }
+    };
+    editor.start_prompt("Set bookmark", PromptAction::BookmarkSet, Some(default));
+    Ok(CommandStatus::Success)
+}
+
+/// Jump to a bookmark (C-x r b)
+pub fn bookmark_jump(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if let Some(name) = bookmark_at_point(editor) {
+        jump_to_bookmark(editor, &name);
+        return Ok(CommandStatus::Success);
+    }
+    if editor.bookmarks.is_empty() {
+        editor.display.set_message("No bookmarks");
+        return Ok(CommandStatus::Failure);
+    }
+    editor.start_prompt("Jump to bookmark", PromptAction::BookmarkJump, None);
+    Ok(CommandStatus::Success)
+}
+
+/// Rename a bookmark (C-x r R)
+pub fn bookmark_rename(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if let Some(name) = bookmark_at_point(editor) {
+        let prompt = format!("Rename bookmark {} to", name);
+        editor.start_prompt(&prompt, PromptAction::BookmarkRenameTo(name), None);
+        return Ok(CommandStatus::Success);
+    }
+    if editor.bookmarks.is_empty() {
+        editor.display.set_message("No bookmarks");
+        return Ok(CommandStatus::Failure);
+    }
+    editor.start_prompt("Rename bookmark", PromptAction::BookmarkRename, None);
+    Ok(CommandStatus::Success)
+}
+
+/// Delete a bookmark (C-x r D)
+pub fn bookmark_delete(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if let Some(name) = bookmark_at_point(editor) {
+        delete_bookmark(editor, &name);
+        return Ok(CommandStatus::Success);
+    }
+    if editor.bookmarks.is_empty() {
+        editor.display.set_message("No bookmarks");
+        return Ok(CommandStatus::Failure);
+    }
+    editor.start_prompt("Delete bookmark", PromptAction::BookmarkDelete, None);
+    Ok(CommandStatus::Success)
+}
+
+/// List bookmarks in the *Bookmarks* buffer (C-x r l)
+pub fn bookmark_bmenu_list(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let content = list_content(&editor.bookmarks);
+    editor.show_generated_buffer(LIST_BUFFER, &content);
+    let first = LIST_HEADER_LINES.min(editor.current_buffer().line_count() - 1);
+    editor.current_window_mut().set_cursor(first, 0);
+    Ok(CommandStatus::Success)
+}
+
+/// Names of all bookmarks, for completion
+pub(crate) fn bookmark_names(editor: &EditorState) -> Vec<String> {
+    editor.bookmarks.iter().map(|b| b.name.clone()).collect()
+}
+
+/// Set a bookmark at point (the bookmark-set prompt)
+pub(crate) fn set_bookmark(editor: &mut EditorState, name: &str) {
+    if name.is_empty() {
+        editor.display.set_message("No bookmark name");
+        return;
+    }
+    let file = match editor.current_buffer().filename() {
+        Some(path) => std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()),
+        None => {
+            editor.display.set_message("Buffer is not visiting a file");
+            return;
+        }
+    };
+    let line = editor.current_window().cursor_line();
+    let col = editor.current_window().cursor_col();
+    let text = editor.current_buffer().line(line).map(|l| l.text()).unwrap_or("");
+    let bookmark = Bookmark::new(name.to_string(), file, line, col, text);
+
+    editor.bookmarks.retain(|b| b.name != name);
+    editor.bookmarks.push(bookmark);
+    editor.bookmarks.sort_by(|a, b| a.name.cmp(&b.name));
+    if save_bookmarks(editor) {
+        editor.display.set_message(format!("Bookmark {} set", name));
+    }
+}
+
+/// Jump to a bookmark by name (the bookmark-jump prompt)
+pub(crate) fn jump_to_bookmark(editor: &mut EditorState, name: &str) {
+    let bookmark = match editor.bookmarks.iter().find(|b| b.name == name) {
+        Some(bookmark) => bookmark.clone(),
+        None => {
+            editor.display.set_message(format!("No bookmark named {}", name));
+            return;
+        }
+    };
+    if let Err(e) = editor.visit_file(&bookmark.file) {
+        editor.display.set_message(format!("Cannot open {}: {}", bookmark.file.display(), e));
+        return;
+    }
+    let lines: Vec<_> = editor.current_buffer().lines().collect();
+    let (line, col) = bookmark.locate(&lines);
+    editor.current_window_mut().set_cursor(line, col);
+    editor.ensure_cursor_visible();
+    editor.display.force_redraw();
+}
+
+/// Rename a bookmark (the bookmark-rename prompts)
+pub(crate) fn rename_bookmark(editor: &mut EditorState, old: &str, new: &str) {
+    if new.is_empty() {
+        editor.display.set_message("No bookmark name");
+        return;
+    }
+    if old != new && editor.bookmarks.iter().any(|b| b.name == new) {
+        editor.display.set_message(format!("Bookmark {} already exists", new));
+        return;
+    }
+    match editor.bookmarks.iter_mut().find(|b| b.name == old) {
+        Some(bookmark) => bookmark.name = new.to_string(),
+        None => {
+            editor.display.set_message(format!("No bookmark named {}", old));
+            return;
+        }
+    }
+    editor.bookmarks.sort_by(|a, b| a.name.cmp(&b.name));
+    if save_bookmarks(editor) {
+        editor.display.set_message(format!("Renamed bookmark {} to {}", old, new));
+    }
+}
+
+/// Delete a bookmark by name (the bookmark-delete prompt)
+pub(crate) fn delete_bookmark(editor: &mut EditorState, name: &str) {
+    let count = editor.bookmarks.len();
+    editor.bookmarks.retain(|b| b.name != name);
+    if editor.bookmarks.len() == count {
+        editor.display.set_message(format!("No bookmark named {}", name));
+        return;
+    }
+    if save_bookmarks(editor) {
+        editor.display.set_message(format!("Deleted bookmark {}", name));
+    }
+}
+
+/// Helper: name of the bookmark on the cursor line of the *Bookmarks* buffer
+fn bookmark_at_point(editor: &EditorState) -> Option<String> {
+    if editor.current_buffer().name() != LIST_BUFFER {
+        return None;
+    }
+    let line = editor.current_window().cursor_line();
+    let idx = line.checked_sub(LIST_HEADER_LINES)?;
+    editor.bookmarks.get(idx).map(|b| b.name.clone())
+}
+
+/// Helper: write the bookmarks file and refresh the list buffer,
+/// returns false (with a message) if the file couldn't be written
+fn save_bookmarks(editor: &mut EditorState) -> bool {
+    if let Some(idx) = editor.buffers.iter().position(|b| b.name() == LIST_BUFFER) {
+        let content = list_content(&editor.bookmarks);
+        editor.buffers[idx].set_content(&content);
+        let last = editor.buffers[idx].line_count() - 1;
+        for window in editor.windows.iter_mut().filter(|w| w.buffer_idx() == idx) {
+            let line = window.cursor_line().min(last);
+            window.set_cursor(line, 0);
+        }
+        editor.display.force_redraw();
+    }
+    match bookmark_store::save_bookmarks(&editor.bookmarks) {
+        Ok(()) => true,
+        Err(e) => {
+            editor.display.set_message(format!("Cannot save bookmarks: {}", e));
+            false
+        }
+    }
+}
+
+/// Helper: text of the *Bookmarks* buffer
+fn list_content(bookmarks: &[Bookmark]) -> String {
+    let mut content = String::new();
+    content.push_str("Bookmarks - on a bookmark's line, C-x r b jumps, C-x r R renames, C-x r D deletes\n\n");
+    content.push_str(&format!("{:<24} {:>6}  {}\n", "Name", "Line", "File"));
+    content.push_str(&format!("{:<24} {:>6}  {}\n", "----", "----", "----"));
+    for bookmark in bookmarks {
+        content.push_str(&format!(
+            "{:<24} {:>6}  {}\n",
+            bookmark.name,
+            bookmark.line + 1,
+            bookmark.file.display()
+        ));
+    }
+    content
+}
@@ -0,0 +1,174 @@
+//! Multiple cursors - make the same edit at several places at once
+//!
+//! Extra cursors are added with the commands below; while there are any,
+//! self-insert and the commands in `EVERY_CURSOR_COMMANDS` run at each of
+//! them in turn (see `EditorState::run_at_cursors`). Other commands only
+//! act at the main cursor. C-g removes the extra cursors.
+
+use crate::editor::EditorState;
+use crate::error::Result;
+use super::CommandStatus;
+use super::mark::get_region;
+use super::navigation::is_word_char;
+
+/// Commands that apply at every cursor
+const EVERY_CURSOR_COMMANDS: &[&str] = &[
+    "forward-char",
+    "backward-char",
+    "next-line",
+    "previous-line",
+    "beginning-of-line",
+    "end-of-line",
+    "back-to-indentation",
+    "forward-word",
+    "backward-word",
+    "delete-char",
+    "delete-backward-char",
+    "kill-line",
+    "kill-word",
+    "backward-kill-word",
+    "yank",
+    "newline",
+    "open-line",
+    "newline-and-indent",
+    "tab-to-tab-stop",
+    "transpose-chars",
+    "upcase-word",
+    "downcase-word",
+    "capitalize-word",
+    "just-one-space",
+    "delete-horizontal-space",
+];
+
+/// Check if a command applies at every cursor
+pub fn runs_at_every_cursor(name: &'static str) -> bool {
+    EVERY_CURSOR_COMMANDS.contains(&name)
+}
+
+/// Leave a cursor at point and move down a line (C-x M-n)
+pub fn mark_next_lines(editor: &mut EditorState, _f: bool, n: i32) -> Result<CommandStatus> {
+    for _ in 0..n.max(1) {
+        let line = editor.current_window().cursor_line();
+        let col = editor.current_window().cursor_col();
+        if line + 1 >= editor.current_buffer().line_count() {
+            editor.display.set_message("End of buffer");
+            return Ok(CommandStatus::Failure);
+        }
+        editor.move_cursor_down();
+        editor.add_cursor(line, col);
+    }
+    let count = editor.cursors.len() + 1;
+    editor.display.set_message(format!("{} cursors", count));
+    Ok(CommandStatus::Success)
+}
+
+/// Add a cursor at the next match of the region or the word at point (M-n)
+///
+/// Each new cursor sits at the same offset within its match as the main
+/// cursor does within the original text.
+pub fn mark_next_like_this(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let line = editor.current_window().cursor_line();
+    let col = editor.current_window().cursor_col();
+
+    // Text to look for and where it starts relative to the cursor
+    let (needle, start) = match get_region(editor) {
+        Some((start_line, start_col, end_line, end_col)) if start_line == end_line && start_col < end_col => {
+            let text = editor.current_buffer().line(start_line).map(|l| l.text()).unwrap_or("");
+            (text[start_col..end_col].to_string(), start_col)
+        }
+        Some((start_line, _, end_line, _)) if start_line != end_line => {
+            editor.display.set_message("Region must be on one line");
+            return Ok(CommandStatus::Failure);
+        }
+        _ => match word_at(editor, line, col) {
+            Some((start, end)) => {
+                let text = editor.current_buffer().line(line).map(|l| l.text()).unwrap_or("");
+                (text[start..end].to_string(), start)
+            }
+            None => {
+                editor.display.set_message("No word at point");
+                return Ok(CommandStatus::Failure);
+            }
+        },
+    };
+    let offset = col - start;
+
+    // Search after the last cursor's match
+    let (last_line, last_col) = editor
+        .cursor_positions()
+        .into_iter()
+        .chain(std::iter::once((line, col)))
+        .max()
+        .unwrap_or((line, col));
+    let mut from = (last_line, last_col.saturating_sub(offset) + needle.len());
+
+    let line_count = editor.current_buffer().line_count();
+    while from.0 < line_count {
+        let text = editor.current_buffer().line(from.0).map(|l| l.text()).unwrap_or("");
+        if let Some(found) = text.get(from.1..).and_then(|rest| rest.find(&needle)) {
+            let match_col = from.1 + found;
+            editor.add_cursor(from.0, match_col + offset);
+            let count = editor.cursors.len() + 1;
+            editor.display.set_message(format!("{} cursors", count));
+            return Ok(CommandStatus::Success);
+        }
+        from = (from.0 + 1, 0);
+    }
+
+    editor.display.set_message(format!("No more matches for \"{}\"", needle));
+    Ok(CommandStatus::Failure)
+}
+
+/// Add a cursor on each line of the region (C-x M-e)
+///
+/// Cursors go to the main cursor's column, or the end of shorter lines.
+pub fn edit_lines(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let (start_line, _, end_line, _) = match get_region(editor) {
+        Some(region) => region,
+        None => {
+            editor.display.set_message("No mark set");
+            return Ok(CommandStatus::Failure);
+        }
+    };
+    let line = editor.current_window().cursor_line();
+    let col = editor.current_window().cursor_col();
+
+    for line_idx in start_line..=end_line {
+        if line_idx == line {
+            continue;
+        }
+        let text = editor.current_buffer().line(line_idx).map(|l| l.text()).unwrap_or("");
+        let mut line_col = col.min(text.len());
+        while !text.is_char_boundary(line_col) {
+            line_col -= 1;
+        }
+        editor.add_cursor(line_idx, line_col);
+    }
+
+    editor.clear_mark();
+    let count = editor.cursors.len() + 1;
+    editor.display.set_message(format!("{} cursors", count));
+    Ok(CommandStatus::Success)
+}
+
+/// Helper: byte range of the word at or just before a position
+fn word_at(editor: &EditorState, line: usize, col: usize) -> Option<(usize, usize)> {
+    let text = editor.current_buffer().line(line)?.text();
+    let start = text[..col]
+        .char_indices()
+        .rev()
+        .take_while(|(_, ch)| is_word_char(*ch))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(col);
+    let end = text[col..]
+        .char_indices()
+        .find(|(_, ch)| !is_word_char(*ch))
+        .map(|(i, _)| col + i)
+        .unwrap_or(text.len());
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,38 @@
+//! Desktop commands - save and restore the editing session
+//!
+//! With `desktop = true` in the config file, the desktop is also saved on
+//! exit and restored on startup.
+
+use crate::desktop_store;
+use crate::editor::EditorState;
+use crate::error::Result;
+use super::CommandStatus;
+
+/// Save the open files, windows and search and kill state to the desktop
+/// file (C-x M-d)
+pub fn desktop_save(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    match desktop_store::save_desktop(&editor.desktop_state()) {
+        Ok(path) => {
+            editor.display.set_message(format!("Desktop saved to {}", path.display()));
+            Ok(CommandStatus::Success)
+        }
+        Err(e) => {
+            editor.display.set_message(format!("Cannot save desktop: {}", e));
+            Ok(CommandStatus::Failure)
+        }
+    }
+}
+
+/// Restore the session saved in the desktop file (C-x M-r)
+pub fn desktop_read(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let desktop = match desktop_store::load_desktop() {
+        Some(desktop) => desktop,
+        None => {
+            editor.display.set_message("No desktop file");
+            return Ok(CommandStatus::Failure);
+        }
+    };
+    let opened = editor.restore_desktop(&desktop);
+    editor.display.set_message(format!("Desktop restored: {} file(s)", opened));
+    Ok(CommandStatus::Success)
+}
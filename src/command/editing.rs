@@ -166,8 +166,7 @@ pub fn yank(editor: &mut EditorState, _f: bool, n: i32) -> Result<CommandStatus>
 
     let end_line = editor.current_window().cursor_line();
     let end_col = editor.current_window().cursor_col();
-    editor.last_yank_start = Some((start_line, start_col));
-    editor.last_yank_end = Some((end_line, end_col));
+    editor.set_yank_region((start_line, start_col), (end_line, end_col));
     editor.last_was_yank = true;
     editor.reset_kill_ring_idx();
 
@@ -183,13 +182,8 @@ pub fn yank_pop(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandSt
         return Ok(CommandStatus::Failure);
     }
 
-    let (start_line, start_col) = match editor.last_yank_start {
-        Some(pos) => pos,
-        None => return Ok(CommandStatus::Failure),
-    };
-
-    let (end_line, end_col) = match editor.last_yank_end {
-        Some(pos) => pos,
+    let ((start_line, start_col), (end_line, end_col)) = match editor.yank_region() {
+        Some(region) => region,
         None => return Ok(CommandStatus::Failure),
     };
 
@@ -201,21 +195,15 @@ pub fn yank_pop(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandSt
 
     for line_idx in (start_line..=end_line).rev() {
         if line_idx == start_line && line_idx == end_line {
-            if let Some(line_mut) = editor.current_buffer_mut().line_mut(line_idx) {
-                line_mut.delete_range(start_col, end_col);
-            }
+            editor.current_buffer_mut().delete_range(line_idx, start_col, end_col);
         } else if line_idx == end_line {
-            if let Some(line_mut) = editor.current_buffer_mut().line_mut(line_idx) {
-                line_mut.delete_range(0, end_col);
-            }
+            editor.current_buffer_mut().delete_range(line_idx, 0, end_col);
             if line_idx > 0 {
                 editor.current_buffer_mut().join_line(line_idx - 1);
             }
         } else if line_idx == start_line {
-            if let Some(line_mut) = editor.current_buffer_mut().line_mut(line_idx) {
-                let line_len = line_mut.len();
-                line_mut.delete_range(start_col, line_len);
-            }
+            let line_len = editor.current_buffer().line(line_idx).map(|l| l.len()).unwrap_or(0);
+            editor.current_buffer_mut().delete_range(line_idx, start_col, line_len);
         } else {
             editor.current_buffer_mut().delete_line(line_idx);
         }
@@ -238,7 +226,7 @@ pub fn yank_pop(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandSt
 
     let new_end_line = editor.current_window().cursor_line();
     let new_end_col = editor.current_window().cursor_col();
-    editor.last_yank_end = Some((new_end_line, new_end_col));
+    editor.set_yank_region((start_line, start_col), (new_end_line, new_end_col));
     editor.last_was_yank = true;
 
     editor.current_buffer_mut().set_modified(true);
@@ -6,7 +6,29 @@ use super::CommandStatus;
 
 /// Find file (open or create)
 pub fn find_file(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
-    editor.start_prompt("Find file", crate::editor::PromptAction::FindFile, None);
+    editor.start_file_prompt("Find file", crate::editor::PromptAction::FindFile, None);
+    Ok(CommandStatus::Success)
+}
+
+/// Open a recently opened file (C-x M-f)
+///
+/// Defaults to the most recent file other than the current buffer's.
+pub fn recentf_open(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if editor.recent_files.is_empty() {
+        editor.display.set_message("No recent files");
+        return Ok(CommandStatus::Failure);
+    }
+    let current = editor
+        .current_buffer()
+        .filename()
+        .map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone()));
+    let home = crate::completion::home_dir();
+    let default = editor
+        .recent_files
+        .iter()
+        .find(|r| Some(&r.file) != current.as_ref())
+        .map(|r| crate::completion::abbreviate_path(&r.file, home.as_deref()));
+    editor.start_prompt("Open recent file", crate::editor::PromptAction::RecentFile, default);
     Ok(CommandStatus::Success)
 }
 
@@ -70,7 +92,7 @@ pub fn previous_buffer(editor: &mut EditorState, _f: bool, n: i32) -> Result<Com
 
 /// Insert file contents at cursor (C-x i)
 pub fn insert_file(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
-    editor.start_prompt("Insert file", crate::editor::PromptAction::InsertFile, None);
+    editor.start_file_prompt("Insert file", crate::editor::PromptAction::InsertFile, None);
     Ok(CommandStatus::Success)
 }
 
@@ -88,7 +110,7 @@ pub fn write_file(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Command
     let default = editor.current_buffer()
         .filename()
         .map(|p| p.display().to_string());
-    editor.start_prompt("Write file", crate::editor::PromptAction::WriteFile, default);
+    editor.start_file_prompt("Write file", crate::editor::PromptAction::WriteFile, default);
     Ok(CommandStatus::Success)
 }
 
@@ -123,6 +145,7 @@ pub fn save_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Comman
             if let Some(path) = editor.current_buffer().filename() {
                 editor.delete_auto_save_file(path);
             }
+            editor.note_buffer_saved(editor.current_window().buffer_idx());
             editor
                 .display
                 .set_message(&format!("Wrote {} lines to {}", line_count, filename));
@@ -165,8 +188,6 @@ pub fn toggle_auto_save(editor: &mut EditorState, _f: bool, _n: i32) -> Result<C
 
 /// Revert buffer to saved file contents (M-x revert-buffer)
 pub fn revert_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
-    use std::fs;
-
     let filename = match editor.current_buffer().filename() {
         Some(path) => path.clone(),
         None => {
@@ -175,10 +196,8 @@ pub fn revert_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Comm
         }
     };
 
-    match fs::read_to_string(&filename) {
-        Ok(content) => {
-            editor.current_buffer_mut().set_content(&content);
-            editor.current_buffer_mut().set_modified(false);
+    match editor.current_buffer_mut().reload() {
+        Ok(()) => {
             editor.current_window_mut().set_cursor(0, 0);
             editor.current_window_mut().set_top_line(0);
             editor.display.force_redraw();
@@ -192,6 +211,28 @@ pub fn revert_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Comm
     }
 }
 
+/// Convert the buffer's line endings (C-x RET)
+pub fn set_line_ending(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let current = editor.current_buffer().format().line_ending.name().to_lowercase();
+    editor.start_prompt("Line ending (lf/crlf/cr)", crate::editor::PromptAction::SetLineEnding, Some(current));
+    Ok(CommandStatus::Success)
+}
+
+/// Reopen the buffer's file in a chosen encoding (C-x C-e)
+pub fn reopen_with_encoding(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if editor.current_buffer().filename().is_none() {
+        editor.display.set_message("Buffer has no file");
+        return Ok(CommandStatus::Failure);
+    }
+    let current = editor.current_buffer().format().encoding.name().to_string();
+    editor.start_prompt(
+        "Reopen with encoding (auto, utf-8, utf-16le, latin-1, ...)",
+        crate::editor::PromptAction::ReopenWithEncoding,
+        Some(current),
+    );
+    Ok(CommandStatus::Success)
+}
+
 /// Go to line number
 pub fn goto_line(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
     editor.start_prompt("Goto line", crate::editor::PromptAction::GotoLine, None);
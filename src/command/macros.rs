@@ -100,8 +100,13 @@ pub fn undo(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus
     if let Some((line, col)) = editor.current_buffer_mut().undo() {
         // Move cursor to the undo position
         editor.current_window_mut().set_cursor(line, col);
+        editor.invalidate_syntax_from(0);
         editor.ensure_cursor_visible();
-        editor.display.set_message("Undo!");
+        if editor.current_buffer().can_undo() {
+            editor.display.set_message("Undo!");
+        } else {
+            editor.display.set_message("Undo! (back to original state)");
+        }
         Ok(CommandStatus::Success)
     } else {
         editor.display.set_message("Nothing to undo");
@@ -109,6 +114,71 @@ pub fn undo(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus
     }
 }
 
+/// Redo the last undone change (M-_)
+pub fn redo(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    editor.current_buffer_mut().add_undo_boundary();
+
+    if let Some((line, col)) = editor.current_buffer_mut().redo() {
+        editor.current_window_mut().set_cursor(line, col);
+        editor.invalidate_syntax_from(0);
+        editor.ensure_cursor_visible();
+        editor.display.set_message("Redo!");
+        Ok(CommandStatus::Success)
+    } else {
+        editor.display.set_message("Nothing to redo");
+        Ok(CommandStatus::Failure)
+    }
+}
+
+/// Show the undo tree of the current buffer (C-x u)
+pub fn undo_tree_visualize(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    editor.show_undo_tree();
+    Ok(CommandStatus::Success)
+}
+
+/// Jump to a state in the undo tree (C-x M-u)
+///
+/// In the *Undo Tree* buffer, jumps to the state on the cursor line and
+/// returns to the buffer the tree belongs to. Elsewhere, uses the numeric
+/// argument or prompts for a state number.
+pub fn undo_tree_goto_state(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    if editor.current_buffer().name() == "*Undo Tree*" {
+        let cursor_line = editor.current_window().cursor_line();
+        let state = editor
+            .current_buffer()
+            .line(cursor_line)
+            .and_then(|l| l.text().split_whitespace().next())
+            .and_then(|word| word.parse::<usize>().ok());
+        let source = editor.undo_tree_source.clone();
+        let source_idx = source.and_then(|name| editor.buffers.iter().position(|b| b.name() == name));
+
+        let (state, source_idx) = match (state, source_idx) {
+            (Some(state), Some(idx)) => (state, idx),
+            (None, _) => {
+                editor.display.set_message("No undo state on this line");
+                return Ok(CommandStatus::Failure);
+            }
+            (_, None) => {
+                editor.display.set_message("Undo tree buffer is gone");
+                return Ok(CommandStatus::Failure);
+            }
+        };
+        editor.current_window_mut().set_buffer_idx(source_idx);
+        editor.goto_undo_state(state);
+        editor.display.force_redraw();
+        return Ok(CommandStatus::Success);
+    }
+
+    if f {
+        editor.goto_undo_state(n.max(0) as usize);
+        return Ok(CommandStatus::Success);
+    }
+
+    let current = editor.current_buffer().undo_tree().current().to_string();
+    editor.start_prompt("Go to undo state", crate::editor::PromptAction::UndoTreeGoto, Some(current));
+    Ok(CommandStatus::Success)
+}
+
 /// Save all macros to disk (C-x M-S)
 pub fn save_macros_to_file(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
     match macro_store::save_macros(&editor.macro_state.slots) {
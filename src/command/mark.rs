@@ -4,18 +4,73 @@ use crate::editor::EditorState;
 use crate::error::Result;
 use super::CommandStatus;
 use super::navigation::forward_word;
-
-/// Set mark at current cursor position
-pub fn set_mark(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
-    editor.current_window_mut().set_mark();
+use super::rectangle::{copy_rectangle_as_kill, kill_rectangle};
+
+/// Set mark at current cursor position (C-SPC)
+///
+/// The previous mark goes on the buffer's mark ring. With C-u, jump to
+/// the mark instead and take the next mark off the ring, so repeating it
+/// cycles back through earlier marks.
+pub fn set_mark(editor: &mut EditorState, f: bool, _n: i32) -> Result<CommandStatus> {
+    if f {
+        return pop_to_mark(editor);
+    }
+    if let Some((line, col)) = editor.mark() {
+        editor.push_mark(line, col);
+    }
+    editor.set_mark();
     editor.display.set_message("Mark set");
     Ok(CommandStatus::Success)
 }
 
+/// Helper: jump to the mark, replacing it with the newest mark ring entry
+fn pop_to_mark(editor: &mut EditorState) -> Result<CommandStatus> {
+    let mark = match editor.mark() {
+        Some(mark) => Some(mark),
+        None => editor.current_buffer_mut().pop_mark(),
+    };
+    let (line, col) = match mark {
+        Some(mark) => mark,
+        None => {
+            editor.display.set_message("No mark set in this buffer");
+            return Ok(CommandStatus::Failure);
+        }
+    };
+    let next = editor.current_buffer_mut().rotate_mark_ring(line, col).unwrap_or((line, col));
+    editor.set_mark_at(next.0, next.1);
+    editor.current_window_mut().set_cursor(line, col);
+    editor.ensure_cursor_visible();
+    Ok(CommandStatus::Success)
+}
+
+/// Jump to the newest position on the global mark ring (C-x C-SPC)
+///
+/// The position moves to the oldest end of the ring, so repeating this
+/// cycles through the buffers visited. Positions in killed buffers are
+/// dropped.
+pub fn pop_global_mark(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    while let Some(id) = editor.global_mark_ring.pop() {
+        let (buf_idx, (line, col)) = match editor.find_marker(id) {
+            Some(found) => found,
+            None => continue,
+        };
+        editor.global_mark_ring.insert(0, id);
+        if editor.current_window().buffer_idx() != buf_idx {
+            editor.current_window_mut().set_buffer_idx(buf_idx);
+            editor.display.force_redraw();
+        }
+        editor.current_window_mut().set_cursor(line, col);
+        editor.ensure_cursor_visible();
+        return Ok(CommandStatus::Success);
+    }
+    editor.display.set_message("No global mark set");
+    Ok(CommandStatus::Failure)
+}
+
 /// Helper: get region bounds (start_line, start_col, end_line, end_col)
 /// Returns None if mark is not set
 pub(crate) fn get_region(editor: &EditorState) -> Option<(usize, usize, usize, usize)> {
-    let mark = editor.current_window().mark()?;
+    let mark = editor.mark()?;
     let cursor_line = editor.current_window().cursor_line();
     let cursor_col = editor.current_window().cursor_col();
 
@@ -50,7 +105,12 @@ pub(crate) fn collect_region_text(editor: &EditorState, start_line: usize, start
 }
 
 /// Kill region (text between mark and point)
-pub fn kill_region(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+///
+/// In rectangle mark mode this kills the rectangle instead.
+pub fn kill_region(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    if editor.current_window().rectangle_mode() {
+        return kill_rectangle(editor, f, n);
+    }
     let region = match get_region(editor) {
         Some(r) => r,
         None => {
@@ -72,58 +132,41 @@ pub fn kill_region(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Comman
     // Move cursor to start of region
     editor.current_window_mut().set_cursor(start_line, start_col);
 
-    // Delete the region
-    if start_line == end_line {
-        // Same line - simple case
-        if let Some(line) = editor.current_buffer_mut().line_mut(start_line) {
-            line.delete_range(start_col, end_col);
-        }
-        editor.current_buffer_mut().set_modified(true);
-    } else {
-        // Multi-line deletion
-        // Delete from start_col to end of start_line
-        if let Some(line) = editor.current_buffer_mut().line_mut(start_line) {
-            let line_len = line.len();
-            if start_col < line_len {
-                line.delete_range(start_col, line_len);
-            }
-        }
+    delete_region(editor, start_line, start_col, end_line, end_col);
 
-        // Delete intermediate lines (in reverse to avoid index shifting)
-        for line_idx in (start_line + 1..end_line).rev() {
-            editor.current_buffer_mut().delete_line(line_idx);
-        }
+    // Clear mark after kill
+    editor.clear_mark();
 
-        // Handle the end line - take remaining content after end_col
-        if end_line > start_line {
-            // The end line is now at start_line + 1 (after removing intermediates)
-            let remaining_line_idx = start_line + 1;
-            if let Some(end_line_content) = editor.current_buffer().line(remaining_line_idx) {
-                let remaining = if end_col < end_line_content.len() {
-                    end_line_content.text()[end_col..].to_string()
-                } else {
-                    String::new()
-                };
-
-                // Append remaining to start line and remove the end line
-                if let Some(start_line_ref) = editor.current_buffer_mut().line_mut(start_line) {
-                    start_line_ref.append_str(&remaining);
-                }
-                editor.current_buffer_mut().delete_line(remaining_line_idx);
-            }
-        }
+    Ok(CommandStatus::Success)
+}
 
-        editor.current_buffer_mut().set_modified(true);
+/// Helper: delete the text between two positions
+pub(crate) fn delete_region(editor: &mut EditorState, start_line: usize, start_col: usize, end_line: usize, end_col: usize) {
+    let buffer = editor.current_buffer_mut();
+    if start_line == end_line {
+        // Same line - simple case
+        buffer.delete_range(start_line, start_col, end_col);
+        return;
     }
 
-    // Clear mark after kill
-    editor.current_window_mut().clear_mark();
-
-    Ok(CommandStatus::Success)
+    // Multi-line deletion: the rest of the start line, the lines in
+    // between, the start of the end line, then join what is left
+    let start_len = buffer.line(start_line).map(|l| l.len()).unwrap_or(0);
+    buffer.delete_range(start_line, start_col, start_len);
+    for _ in start_line + 1..end_line {
+        buffer.delete_line(start_line + 1);
+    }
+    buffer.delete_range(start_line + 1, 0, end_col);
+    buffer.join_line(start_line);
 }
 
 /// Copy region (without deleting)
-pub fn copy_region(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+///
+/// In rectangle mark mode this copies the rectangle instead.
+pub fn copy_region(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    if editor.current_window().rectangle_mode() {
+        return copy_rectangle_as_kill(editor, f, n);
+    }
     let region = match get_region(editor) {
         Some(r) => r,
         None => {
@@ -142,7 +185,7 @@ pub fn copy_region(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Comman
     editor.kill_append(&copied_text);
 
     // Clear mark after copy
-    editor.current_window_mut().clear_mark();
+    editor.clear_mark();
     editor.display.set_message("Region copied");
 
     Ok(CommandStatus::Success)
@@ -154,13 +197,13 @@ pub fn exchange_point_and_mark(editor: &mut EditorState, _f: bool, _n: i32) -> R
     let cursor_line = window.cursor_line();
     let cursor_col = window.cursor_col();
 
-    if let Some(mark) = window.mark() {
+    if let Some(mark) = editor.mark() {
         let (mark_line, mark_col) = mark;
 
         // Set cursor to mark position
         editor.current_window_mut().set_cursor(mark_line, mark_col);
         // Set mark to old cursor position
-        editor.current_window_mut().set_mark_at(cursor_line, cursor_col);
+        editor.set_mark_at(cursor_line, cursor_col);
 
         editor.ensure_cursor_visible();
         Ok(CommandStatus::Success)
@@ -176,7 +219,7 @@ pub fn mark_paragraph(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Com
     editor.backward_paragraph();
 
     // Set mark at paragraph start
-    editor.current_window_mut().set_mark();
+    editor.set_mark();
 
     // Move to end of paragraph
     editor.forward_paragraph();
@@ -188,7 +231,7 @@ pub fn mark_paragraph(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Com
 /// Mark word (M-@)
 pub fn mark_word(editor: &mut EditorState, _f: bool, n: i32) -> Result<CommandStatus> {
     // Set mark at current position
-    editor.current_window_mut().set_mark();
+    editor.set_mark();
 
     // Move forward by n words using the forward_word command
     forward_word(editor, false, n)?;
@@ -197,24 +240,20 @@ pub fn mark_word(editor: &mut EditorState, _f: bool, n: i32) -> Result<CommandSt
     Ok(CommandStatus::Success)
 }
 
-/// Mark whole buffer (C-x h) - select entire buffer
+/// Mark whole buffer (C-x h) - select entire buffer (the narrowed part,
+/// if narrowed)
 pub fn mark_whole_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
     // Move to end of buffer
-    let line_count = editor.current_buffer().line_count();
-    if line_count > 0 {
-        let last_line = line_count - 1;
-        let last_col = editor.current_buffer().line(last_line)
-            .map(|l| l.len())
-            .unwrap_or(0);
-        editor.current_window_mut().set_cursor(last_line, last_col);
-    }
+    let (last_line, last_col) = editor.current_buffer().visible_end();
+    editor.current_window_mut().set_cursor(last_line, last_col);
 
     // Set mark at end
-    editor.current_window_mut().set_mark();
+    editor.set_mark();
 
     // Move to beginning of buffer
-    editor.current_window_mut().set_cursor(0, 0);
-    editor.current_window_mut().set_top_line(0);
+    let (first_line, first_col) = editor.current_buffer().visible_start();
+    editor.current_window_mut().set_cursor(first_line, first_col);
+    editor.current_window_mut().set_top_line(first_line);
 
     editor.display.set_message("Mark set (whole buffer)");
     Ok(CommandStatus::Success)
@@ -25,6 +25,23 @@ pub fn redraw_display(editor: &mut EditorState, _f: bool, n: i32) -> Result<Comm
     Ok(CommandStatus::Success)
 }
 
+/// Toggle visual-line mode for the current buffer (C-x M-v)
+///
+/// Long lines wrap onto more screen rows, and C-n/C-p move by screen row.
+pub fn visual_line_mode(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let on = !editor.current_buffer().modes().visual_line;
+    editor.current_buffer_mut().modes_mut().visual_line = on;
+    editor.ensure_cursor_visible();
+    editor.force_redraw();
+    let status = if on {
+        "Visual line mode enabled"
+    } else {
+        "Visual line mode disabled"
+    };
+    editor.display.set_message(status);
+    Ok(CommandStatus::Success)
+}
+
 /// Toggle line numbers display (C-x #)
 pub fn toggle_line_numbers(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
     editor.display.toggle_line_numbers();
@@ -82,7 +99,8 @@ pub fn toggle_warn_unsaved(editor: &mut EditorState, _f: bool, _n: i32) -> Resul
 }
 
 /// Abort current operation
-pub fn abort(_editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+pub fn abort(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    editor.clear_cursors();
     Ok(CommandStatus::Abort)
 }
 
@@ -351,7 +369,7 @@ pub fn delete_blank_lines(editor: &mut EditorState, _f: bool, _n: i32) -> Result
 
 /// Insert spaces to next tab stop (M-i)
 pub fn tab_to_tab_stop(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
-    let tab_width = 8; // Standard tab width
+    let tab_width = editor.tab_width();
     let count = if f { n.max(1) } else { 1 };
 
     for _ in 0..count {
@@ -362,7 +380,7 @@ pub fn tab_to_tab_stop(editor: &mut EditorState, f: bool, n: i32) -> Result<Comm
         let display_col = editor
             .current_buffer()
             .line(cursor_line)
-            .map(|l| l.byte_to_col(cursor_col))
+            .map(|l| l.byte_to_col(cursor_col, tab_width))
             .unwrap_or(0);
 
         // Calculate spaces needed to reach next tab stop
@@ -559,7 +577,21 @@ pub fn word_count(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Command
 /// Display current line number (C-x l)
 pub fn what_line(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
     let cursor_line = editor.current_window().cursor_line();
-    let total_lines = editor.current_buffer().line_count();
+    let buffer = editor.current_buffer();
+    let total_lines = buffer.line_count();
+
+    if buffer.is_narrowed() {
+        let first_line = buffer.visible_start().0;
+        let visible_lines = buffer.visible_end().0 - first_line + 1;
+        editor.display.set_message(format!(
+            "Line {} of {} (narrowed), line {} of {} in buffer",
+            cursor_line - first_line + 1,
+            visible_lines,
+            cursor_line + 1,
+            total_lines
+        ));
+        return Ok(CommandStatus::Success);
+    }
 
     editor.display.set_message(&format!(
         "Line {} of {}",
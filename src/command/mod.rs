@@ -12,6 +12,12 @@ mod windows;
 mod macros;
 mod case;
 mod misc;
+mod cursors;
+mod bookmarks;
+mod rectangle;
+mod registers;
+mod narrowing;
+mod desktop;
 
 use std::collections::HashMap;
 
@@ -19,6 +25,12 @@ use crate::editor::EditorState;
 use crate::error::Result;
 use crate::input::{Key, key_flags};
 
+pub use cursors::runs_at_every_cursor;
+pub(crate) use mark::delete_region;
+pub(crate) use rectangle::apply_string_rectangle;
+pub(crate) use registers::run_register_command;
+pub(crate) use bookmarks::{bookmark_names, delete_bookmark, jump_to_bookmark, rename_bookmark, set_bookmark};
+
 /// Command result status
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommandStatus {
@@ -33,7 +45,7 @@ pub enum CommandStatus {
 /// - n: numeric argument (repeat count, default 1)
 pub type CommandFn = fn(&mut EditorState, bool, i32) -> Result<CommandStatus>;
 
-/// A named command
+/// A named command, in the registry M-x runs commands from
 pub struct Command {
     pub name: &'static str,
     pub function: CommandFn,
@@ -45,9 +57,11 @@ struct BindingEntry {
     name: &'static str,
 }
 
-/// Key binding table
+/// Key binding table, with the registry of every command by name
 pub struct KeyTable {
     bindings: HashMap<u32, BindingEntry>,
+    /// Every command, bound to a key or not, sorted by name
+    commands: Vec<Command>,
 }
 
 impl KeyTable {
@@ -55,6 +69,7 @@ impl KeyTable {
     pub fn new() -> Self {
         Self {
             bindings: HashMap::new(),
+            commands: Vec::new(),
         }
     }
 
@@ -65,9 +80,18 @@ impl KeyTable {
         table
     }
 
-    /// Add a key binding with command name
+    /// Add a key binding with command name, registering the command
     pub fn bind_named(&mut self, key: Key, cmd: CommandFn, name: &'static str) {
         self.bindings.insert(key.code(), BindingEntry { function: cmd, name });
+        self.register(name, cmd);
+    }
+
+    /// Register a command by name without binding a key, so M-x can run it
+    pub fn register(&mut self, name: &'static str, cmd: CommandFn) {
+        match self.commands.binary_search_by(|c| c.name.cmp(name)) {
+            Ok(idx) => self.commands[idx].function = cmd,
+            Err(idx) => self.commands.insert(idx, Command { name, function: cmd }),
+        }
     }
 
     /// Add a key binding (uses function pointer as identifier)
@@ -100,23 +124,31 @@ impl KeyTable {
         bindings
     }
 
-    /// Look up a command by name (returns first matching binding)
+    /// Look up a registered command by name
     pub fn lookup_by_name(&self, name: &str) -> Option<CommandFn> {
-        self.bindings
-            .values()
-            .find(|entry| entry.name == name)
-            .map(|entry| entry.function)
+        self.commands
+            .binary_search_by(|c| c.name.cmp(name))
+            .ok()
+            .map(|idx| self.commands[idx].function)
     }
 
-    /// Get all unique command names (sorted)
+    /// Get the names of all registered commands (sorted)
     pub fn command_names(&self) -> Vec<&'static str> {
-        let mut names: Vec<_> = self.bindings
-            .values()
-            .map(|entry| entry.name)
+        self.commands.iter().map(|c| c.name).collect()
+    }
+
+    /// Keys bound to a command, shortest first
+    pub fn keys_for(&self, name: &str) -> Vec<Key> {
+        let mut keys: Vec<Key> = self.bindings
+            .iter()
+            .filter(|(_, entry)| entry.name == name)
+            .map(|(&code, _)| Key(code))
             .collect();
-        names.sort();
-        names.dedup();
-        names
+        keys.sort_by_key(|key| {
+            let shown = key.display_name();
+            (shown.len(), shown)
+        });
+        keys
     }
 
     /// Set up default key bindings
@@ -127,6 +159,12 @@ impl KeyTable {
         use mark::*;
         use search::*;
         use files::*;
+        use cursors::*;
+        use rectangle::*;
+        use registers::*;
+        use bookmarks::*;
+        use narrowing::*;
+        use desktop::*;
         use windows::*;
         use macros::*;
         use case::*;
@@ -160,6 +198,7 @@ impl KeyTable {
         // Screen refresh
         self.bind_named(Key::ctrl('l'), redraw_display, "redraw-display");
         self.bind_named(Key::ctlx('#'), toggle_line_numbers, "toggle-line-numbers");
+        self.bind_named(Key::ctlx_meta('v'), visual_line_mode, "visual-line-mode");
         self.bind_named(Key::ctlx('s'), toggle_syntax_highlighting, "toggle-syntax-highlighting");
 
         // Quit
@@ -196,6 +235,50 @@ impl KeyTable {
         self.bind_named(Key::ctlx_ctrl('r'), revert_buffer, "revert-buffer");
         self.bind_named(Key::ctlx('a'), toggle_auto_save, "auto-save-mode");
         self.bind_named(Key::ctlx('w'), toggle_warn_unsaved, "toggle-warn-unsaved");
+        self.bind_named(Key::ctlx_ctrl('m'), set_line_ending, "set-buffer-line-ending");
+        self.bind_named(Key::ctlx_ctrl('e'), reopen_with_encoding, "reopen-with-encoding");
+
+        // Multiple cursors
+        self.bind_named(Key::meta('n'), mark_next_like_this, "mc-mark-next-like-this");
+        self.bind_named(Key::ctlx_meta('n'), mark_next_lines, "mc-mark-next-lines");
+        self.bind_named(Key::ctlx_meta('e'), edit_lines, "mc-edit-lines");
+
+        // Rectangles
+        self.bind_named(Key::ctlx(' '), rectangle_mark_mode, "rectangle-mark-mode");
+        self.bind_named(Key::ctlx_r('k'), kill_rectangle, "kill-rectangle");
+        self.bind_named(Key(Key::ctlx_r('w').0 | key_flags::META), copy_rectangle_as_kill, "copy-rectangle-as-kill");
+        self.bind_named(Key::ctlx_r('y'), yank_rectangle, "yank-rectangle");
+        self.bind_named(Key::ctlx_r('o'), open_rectangle, "open-rectangle");
+        self.bind_named(Key::ctlx_r('c'), clear_rectangle, "clear-rectangle");
+        self.bind_named(Key::ctlx_r('t'), string_rectangle, "string-rectangle");
+        self.bind_named(Key::ctlx_r('N'), rectangle_number_lines, "rectangle-number-lines");
+
+        // Registers
+        self.bind_named(Key::ctlx_r('s'), copy_to_register, "copy-to-register");
+        self.bind_named(Key::ctlx_r('x'), copy_to_register, "copy-to-register");
+        self.bind_named(Key::ctlx_r('r'), copy_rectangle_to_register, "copy-rectangle-to-register");
+        self.bind_named(Key::ctlx_r('i'), insert_register, "insert-register");
+        self.bind_named(Key::ctlx_r('g'), insert_register, "insert-register");
+        self.bind_named(Key::ctlx_r(' '), point_to_register, "point-to-register");
+        self.bind_named(Key::ctlx_r('j'), jump_to_register, "jump-to-register");
+        self.bind_named(Key::ctlx_r('w'), window_configuration_to_register, "window-configuration-to-register");
+        self.bind_named(Key::ctlx_r('v'), list_registers, "list-registers");
+
+        // Bookmarks
+        self.bind_named(Key::ctlx_r('m'), bookmark_set, "bookmark-set");
+        self.bind_named(Key::ctlx_r('b'), bookmark_jump, "bookmark-jump");
+        self.bind_named(Key::ctlx_r('l'), bookmark_bmenu_list, "bookmark-bmenu-list");
+        self.bind_named(Key::ctlx_r('R'), bookmark_rename, "bookmark-rename");
+        self.bind_named(Key::ctlx_r('D'), bookmark_delete, "bookmark-delete");
+
+        // Narrowing (C-x n is next-buffer)
+        self.bind_named(Key::ctlx_ctrl('n'), narrow_to_region, "narrow-to-region");
+        self.bind_named(Key::ctlx_ctrl('p'), narrow_to_paragraph, "narrow-to-paragraph");
+        self.bind_named(Key::ctlx_meta('w'), widen, "widen");
+
+        // Desktop (saved session)
+        self.bind_named(Key::ctlx_meta('d'), desktop_save, "desktop-save");
+        self.bind_named(Key::ctlx_meta('r'), desktop_read, "desktop-read");
 
         // Line operations
         self.bind_named(Key::ctlx_ctrl('k'), copy_line, "copy-line");
@@ -214,6 +297,7 @@ impl KeyTable {
 
         // Mark/Region operations
         self.bind_named(Key::ctrl(' '), set_mark, "set-mark-command");
+        self.bind_named(Key::ctlx_ctrl(' '), pop_global_mark, "pop-global-mark");
         self.bind_named(Key::ctrl('w'), kill_region, "kill-region");
         self.bind_named(Key::meta('w'), copy_region, "kill-ring-save");
 
@@ -227,6 +311,7 @@ impl KeyTable {
 
         // Buffer operations
         self.bind_named(Key::ctlx_ctrl('f'), find_file, "find-file");
+        self.bind_named(Key::ctlx_meta('f'), recentf_open, "recentf-open");
         self.bind_named(Key::ctlx('b'), switch_buffer, "switch-to-buffer");
         self.bind_named(Key::ctlx_ctrl('b'), list_buffers, "list-buffers");
         self.bind_named(Key::ctlx('k'), kill_buffer, "kill-buffer");
@@ -238,15 +323,21 @@ impl KeyTable {
 
         // Window operations
         self.bind_named(Key::ctlx('2'), split_window, "split-window-below");
+        self.bind_named(Key::ctlx('3'), split_window_right, "split-window-right");
         self.bind_named(Key::ctlx('1'), delete_other_windows, "delete-other-windows");
         self.bind_named(Key::ctlx('0'), delete_window, "delete-window");
         self.bind_named(Key::ctlx('o'), other_window, "other-window");
         self.bind_named(Key::ctlx('^'), enlarge_window, "enlarge-window");
         self.bind_named(Key::ctlx('v'), shrink_window, "shrink-window");
+        self.bind_named(Key::ctlx('}'), enlarge_window_horizontally, "enlarge-window-horizontally");
+        self.bind_named(Key::ctlx('{'), shrink_window_horizontally, "shrink-window-horizontally");
 
         // Undo
         self.bind_named(Key::ctrl('/'), undo, "undo");
         self.bind_named(Key::ctrl('_'), undo, "undo");
+        self.bind_named(Key::meta('_'), redo, "redo");
+        self.bind_named(Key::ctlx('u'), undo_tree_visualize, "undo-tree-visualize");
+        self.bind_named(Key::ctlx_meta('u'), undo_tree_goto_state, "undo-tree-goto-state");
 
         // Shell
         self.bind_named(Key::meta('!'), shell_command, "shell-command");
@@ -305,6 +396,8 @@ impl KeyTable {
 
         // Scroll other window
         self.bind_named(Key(key_flags::META | key_flags::CONTROL | 'v' as u32), scroll_other_window, "scroll-other-window");
+        self.bind_named(Key::ctlx('<'), scroll_left, "scroll-left");
+        self.bind_named(Key::ctlx('>'), scroll_right, "scroll-right");
 
         // Information
         self.bind_named(Key::ctlx('l'), what_line, "what-line");
@@ -327,6 +420,9 @@ impl KeyTable {
 
         // Indentation
         self.bind_named(Key::ctlx_ctrl('i'), indent_rigidly, "indent-rigidly");
+
+        // Commands without a key, run with M-x
+        self.register("sort-lines", sort_lines);
     }
 }
 
@@ -0,0 +1,62 @@
+//! Narrowing commands - restrict a buffer to part of its text
+//!
+//! While a buffer is narrowed, movement, search, replace and filling only
+//! see the narrowed part, and the rest can't be edited. The buffer keeps
+//! the bounds itself, so every window showing it is narrowed.
+
+use crate::editor::EditorState;
+use crate::error::Result;
+use super::CommandStatus;
+use super::mark::get_region;
+
+/// Narrow the buffer to the region (C-x C-n)
+pub fn narrow_to_region(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let (start_line, start_col, end_line, end_col) = match get_region(editor) {
+        Some(region) => region,
+        None => {
+            editor.display.set_message("No mark set");
+            return Ok(CommandStatus::Failure);
+        }
+    };
+    editor.current_buffer_mut().narrow((start_line, start_col), (end_line, end_col));
+    editor.clear_mark();
+    editor.display.force_redraw();
+    Ok(CommandStatus::Success)
+}
+
+/// Narrow the buffer to the paragraph around point (C-x C-p)
+pub fn narrow_to_paragraph(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let buffer = editor.current_buffer();
+    let is_blank = |idx: usize| buffer.line(idx).map(|l| l.text().trim().is_empty()).unwrap_or(true);
+    let first_line = buffer.visible_start().0;
+    let last_line = buffer.visible_end().0;
+
+    let line = editor.current_window().cursor_line();
+    if is_blank(line) {
+        editor.display.set_message("Not in a paragraph");
+        return Ok(CommandStatus::Failure);
+    }
+    let mut start = line;
+    while start > first_line && !is_blank(start - 1) {
+        start -= 1;
+    }
+    let mut end = line;
+    while end < last_line && !is_blank(end + 1) {
+        end += 1;
+    }
+
+    let start = buffer.clamp_visible(start, 0);
+    let end_col = buffer.line(end).map(|l| l.len()).unwrap_or(0);
+    let end = buffer.clamp_visible(end, end_col);
+    editor.current_buffer_mut().narrow(start, end);
+    editor.display.force_redraw();
+    Ok(CommandStatus::Success)
+}
+
+/// Remove narrowing from the buffer (C-x M-w)
+pub fn widen(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    editor.current_buffer_mut().widen();
+    editor.ensure_cursor_visible();
+    editor.display.force_redraw();
+    Ok(CommandStatus::Success)
+}
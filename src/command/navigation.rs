@@ -118,14 +118,16 @@ pub fn scroll_up(editor: &mut EditorState, _f: bool, n: i32) -> Result<CommandSt
     Ok(CommandStatus::Success)
 }
 
-/// Move to beginning of buffer
+/// Move to beginning of buffer, saving point on the mark ring
 pub fn beginning_of_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    editor.push_point();
     editor.move_to_buffer_start();
     Ok(CommandStatus::Success)
 }
 
-/// Move to end of buffer
+/// Move to end of buffer, saving point on the mark ring
 pub fn end_of_buffer(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    editor.push_point();
     editor.move_to_buffer_end();
     Ok(CommandStatus::Success)
 }
@@ -273,15 +275,12 @@ pub fn forward_paragraph(editor: &mut EditorState, _f: bool, n: i32) -> Result<C
 
 /// Scroll the other window (M-C-v)
 pub fn scroll_other_window(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
-    if editor.windows.len() <= 1 {
-        editor.display.set_message("No other window");
-        return Ok(CommandStatus::Failure);
-    }
-
-    let other_idx = if editor.current_window + 1 < editor.windows.len() {
-        editor.current_window + 1
-    } else {
-        0
+    let other_idx = match editor.next_window() {
+        Some(idx) => idx,
+        None => {
+            editor.display.set_message("No other window");
+            return Ok(CommandStatus::Failure);
+        }
     };
 
     let buf_idx = editor.windows[other_idx].buffer_idx();
@@ -304,7 +303,36 @@ pub fn scroll_other_window(editor: &mut EditorState, f: bool, n: i32) -> Result<
     Ok(CommandStatus::Success)
 }
 
-/// Jump to matching fence character (bracket, paren, brace) (M-C-f)
+/// Scroll the window's text left, showing the columns further right (C-x <)
+///
+/// Scrolls by the window width less two columns, or by the argument.
+pub fn scroll_left(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    scroll_sideways(editor, f, n)
+}
+
+/// Scroll the window's text right, back towards the start of the lines (C-x >)
+pub fn scroll_right(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    scroll_sideways(editor, f, -n)
+}
+
+/// Helper: scroll horizontally by `n` columns (a window width without an
+/// argument), a positive count showing the columns further right
+fn scroll_sideways(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    if editor.current_buffer().modes().visual_line {
+        editor.display.set_message("Lines are wrapped in visual-line mode");
+        return Ok(CommandStatus::Failure);
+    }
+    let cols = if f {
+        n as isize
+    } else {
+        n.signum() as isize * editor.window_text_cols().saturating_sub(2) as isize
+    };
+    editor.current_window_mut().scroll_columns(cols);
+    Ok(CommandStatus::Success)
+}
+
+/// Jump to matching fence character (bracket, paren, brace) (M-C-f),
+/// saving point on the mark ring
 pub fn goto_matching_fence(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
     let cursor_line = editor.current_window().cursor_line();
     let cursor_col = editor.current_window().cursor_col();
@@ -360,6 +388,7 @@ pub fn goto_matching_fence(editor: &mut EditorState, _f: bool, _n: i32) -> Resul
                         depth -= 1;
                         if depth == 0 {
                             let match_col = col + pos;
+                            editor.push_mark(cursor_line, cursor_col);
                             editor.current_window_mut().set_cursor(line_idx, match_col);
                             editor.ensure_cursor_visible();
                             return Ok(CommandStatus::Success);
@@ -385,6 +414,7 @@ pub fn goto_matching_fence(editor: &mut EditorState, _f: bool, _n: i32) -> Resul
                     } else if c == target {
                         depth -= 1;
                         if depth == 0 {
+                            editor.push_mark(cursor_line, cursor_col);
                             editor.current_window_mut().set_cursor(line_idx, pos);
                             editor.ensure_cursor_visible();
                             return Ok(CommandStatus::Success);
@@ -0,0 +1,298 @@
+//! Rectangle commands - edit a column block between mark and point
+//!
+//! The rectangle's corners are the mark and point. Its edges are display
+//! columns rather than byte offsets, so tabs and wide characters line up
+//! the way they look on screen: a character that straddles the left edge
+//! is left outside the rectangle and one that straddles the right edge is
+//! taken in whole. Lines too short to reach the rectangle are padded with
+//! spaces where text has to go into them.
+
+use crate::editor::{EditorState, PromptAction};
+use crate::error::Result;
+use super::CommandStatus;
+use super::mark::get_region;
+
+/// Rectangle bounds: first and last line, left and right display column
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rectangle {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+/// Helper: get the rectangle between mark and point
+/// Returns None if mark is not set
+pub(crate) fn get_rectangle(editor: &EditorState) -> Option<Rectangle> {
+    let tab_width = editor.tab_width();
+    let (start_line, start_col, end_line, end_col) = get_region(editor)?;
+    let buffer = editor.current_buffer();
+    let start = buffer.line(start_line).map(|l| l.byte_to_col(start_col, tab_width)).unwrap_or(0);
+    let end = buffer.line(end_line).map(|l| l.byte_to_col(end_col, tab_width)).unwrap_or(0);
+    Some(Rectangle {
+        start_line,
+        end_line,
+        left: start.min(end),
+        right: start.max(end),
+    })
+}
+
+/// Helper: byte range of a rectangle's columns within a line
+///
+/// Columns past the end of the line map to the line's length.
+fn line_span(editor: &EditorState, line_idx: usize, left: usize, right: usize) -> (usize, usize) {
+    let tab_width = editor.tab_width();
+    match editor.current_buffer().line(line_idx) {
+        Some(line) => {
+            let start = line.col_to_byte(left, tab_width).unwrap_or(line.len());
+            let end = line.col_to_byte(right, tab_width).unwrap_or(line.len()).max(start);
+            (start, end)
+        }
+        None => (0, 0),
+    }
+}
+
+/// Helper: text of each line of the rectangle, padded to its width
+pub(crate) fn extract_rectangle(editor: &EditorState, rect: Rectangle) -> Vec<String> {
+    let tab_width = editor.tab_width();
+    let width = rect.right - rect.left;
+    (rect.start_line..=rect.end_line)
+        .map(|line_idx| {
+            let (start, end) = line_span(editor, line_idx, rect.left, rect.right);
+            let line = editor.current_buffer().line(line_idx);
+            let mut text = line.map(|l| l.safe_slice(start, end).to_string()).unwrap_or_default();
+            let text_width = line.map(|l| l.byte_to_col(end, tab_width) - l.byte_to_col(start, tab_width)).unwrap_or(0);
+            text.push_str(&" ".repeat(width.saturating_sub(text_width)));
+            text
+        })
+        .collect()
+}
+
+/// Helper: delete the rectangle's text, optionally leaving spaces in its place
+///
+/// Lines that end inside the rectangle are never padded with spaces.
+pub(crate) fn delete_rectangle_text(editor: &mut EditorState, rect: Rectangle, fill: bool) {
+    let tab_width = editor.tab_width();
+    for line_idx in rect.start_line..=rect.end_line {
+        let (start, end) = line_span(editor, line_idx, rect.left, rect.right);
+        let buffer = editor.current_buffer_mut();
+        let (len, deleted_width) = match buffer.line(line_idx) {
+            Some(line) => (line.len(), line.byte_to_col(end, tab_width) - line.byte_to_col(start, tab_width)),
+            None => continue,
+        };
+        buffer.delete_range(line_idx, start, end);
+        if fill && end < len {
+            buffer.insert_str(line_idx, start, &" ".repeat(deleted_width));
+        }
+    }
+}
+
+/// Helper: byte position of a display column, padding the line with spaces
+/// if it is too short to reach it
+fn pad_to_col(editor: &mut EditorState, line_idx: usize, col: usize) -> usize {
+    let tab_width = editor.tab_width();
+    let buffer = editor.current_buffer_mut();
+    match buffer.line(line_idx) {
+        Some(line) => match line.col_to_byte(col, tab_width) {
+            Some(byte) => byte,
+            None => {
+                let len = line.len();
+                let padding = col - line.display_width(tab_width);
+                buffer.insert_str(line_idx, len, &" ".repeat(padding));
+                len + padding
+            }
+        },
+        None => 0,
+    }
+}
+
+/// Helper: finish a rectangle edit starting at a line
+fn finish_rectangle_edit(editor: &mut EditorState, start_line: usize) {
+    editor.clear_mark();
+    editor.invalidate_syntax_from(start_line);
+    editor.display.force_redraw();
+}
+
+/// Helper: get the rectangle or report that there is no mark
+fn require_rectangle(editor: &mut EditorState) -> Option<Rectangle> {
+    let rect = get_rectangle(editor);
+    if rect.is_none() {
+        editor.display.set_message("No mark set");
+    }
+    rect
+}
+
+/// Helper: move point to the top-left corner of a rectangle
+fn goto_top_left(editor: &mut EditorState, rect: Rectangle) {
+    let tab_width = editor.tab_width();
+    let col = editor
+        .current_buffer()
+        .line(rect.start_line)
+        .map(|l| l.col_to_byte(rect.left, tab_width).unwrap_or(l.len()))
+        .unwrap_or(0);
+    editor.current_window_mut().set_cursor(rect.start_line, col);
+}
+
+/// Toggle treating the region as a rectangle (C-x SPC)
+///
+/// Sets the mark at point first if there is none.
+pub fn rectangle_mark_mode(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if editor.mark().is_none() {
+        editor.set_mark();
+    }
+    let window = editor.current_window_mut();
+    let on = !window.rectangle_mode();
+    window.set_rectangle_mode(on);
+    editor.display.force_redraw();
+    editor.display.set_message(if on { "Rectangle mark mode enabled" } else { "Rectangle mark mode disabled" });
+    Ok(CommandStatus::Success)
+}
+
+/// Kill the rectangle, saving it for yank-rectangle (C-x r k)
+pub fn kill_rectangle(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let rect = match require_rectangle(editor) {
+        Some(rect) => rect,
+        None => return Ok(CommandStatus::Failure),
+    };
+    editor.killed_rectangle = extract_rectangle(editor, rect);
+    delete_rectangle_text(editor, rect, false);
+    goto_top_left(editor, rect);
+    finish_rectangle_edit(editor, rect.start_line);
+    Ok(CommandStatus::Success)
+}
+
+/// Save the rectangle for yank-rectangle without deleting it (C-x r M-w)
+pub fn copy_rectangle_as_kill(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let rect = match require_rectangle(editor) {
+        Some(rect) => rect,
+        None => return Ok(CommandStatus::Failure),
+    };
+    editor.killed_rectangle = extract_rectangle(editor, rect);
+    editor.clear_mark();
+    editor.display.force_redraw();
+    editor.display.set_message("Rectangle copied");
+    Ok(CommandStatus::Success)
+}
+
+/// Insert the last killed rectangle with its top-left corner at point (C-x r y)
+///
+/// Lines are added at the end of the buffer if the rectangle needs them.
+pub fn yank_rectangle(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if editor.killed_rectangle.is_empty() {
+        editor.display.set_message("No rectangle to yank");
+        return Ok(CommandStatus::Failure);
+    }
+    let rows = editor.killed_rectangle.clone();
+    editor.set_mark();
+    let end = insert_rectangle(editor, &rows);
+    editor.current_window_mut().set_cursor(end.0, end.1);
+    Ok(CommandStatus::Success)
+}
+
+/// Insert rows of text as a rectangle with its top-left corner at point,
+/// returns the position after the last row
+pub(crate) fn insert_rectangle(editor: &mut EditorState, rows: &[String]) -> (usize, usize) {
+    let tab_width = editor.tab_width();
+    let line = editor.current_window().cursor_line();
+    let col = editor.current_window().cursor_col();
+    let left = editor.current_buffer().line(line).map(|l| l.byte_to_col(col, tab_width)).unwrap_or(0);
+
+    let mut end = (line, col);
+    for (i, row) in rows.iter().enumerate() {
+        let line_idx = line + i;
+        if line_idx >= editor.current_buffer().line_count() {
+            let last = editor.current_buffer().line_count() - 1;
+            let len = editor.current_buffer().line(last).map(|l| l.len()).unwrap_or(0);
+            editor.current_buffer_mut().insert_newline(last, len);
+        }
+        let byte = pad_to_col(editor, line_idx, left);
+        editor.current_buffer_mut().insert_str(line_idx, byte, row);
+        end = (line_idx, byte + row.len());
+    }
+
+    editor.invalidate_syntax_from(line);
+    editor.display.force_redraw();
+    end
+}
+
+/// Shift the rectangle's text right, leaving it blank (C-x r o)
+pub fn open_rectangle(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let tab_width = editor.tab_width();
+    let rect = match require_rectangle(editor) {
+        Some(rect) => rect,
+        None => return Ok(CommandStatus::Failure),
+    };
+    let blank = " ".repeat(rect.right - rect.left);
+    for line_idx in rect.start_line..=rect.end_line {
+        let line = match editor.current_buffer().line(line_idx) {
+            Some(line) => line,
+            None => continue,
+        };
+        // Lines that end before the rectangle are left alone
+        if let Some(byte) = line.col_to_byte(rect.left, tab_width).filter(|&b| b < line.len()) {
+            editor.current_buffer_mut().insert_str(line_idx, byte, &blank);
+        }
+    }
+    goto_top_left(editor, rect);
+    finish_rectangle_edit(editor, rect.start_line);
+    Ok(CommandStatus::Success)
+}
+
+/// Replace the rectangle's text with spaces (C-x r c)
+pub fn clear_rectangle(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let rect = match require_rectangle(editor) {
+        Some(rect) => rect,
+        None => return Ok(CommandStatus::Failure),
+    };
+    delete_rectangle_text(editor, rect, true);
+    goto_top_left(editor, rect);
+    finish_rectangle_edit(editor, rect.start_line);
+    Ok(CommandStatus::Success)
+}
+
+/// Replace each line of the rectangle with a string (C-x r t)
+pub fn string_rectangle(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    if require_rectangle(editor).is_none() {
+        return Ok(CommandStatus::Failure);
+    }
+    editor.start_prompt("String rectangle", PromptAction::StringRectangle, None);
+    Ok(CommandStatus::Success)
+}
+
+/// Replace each line of the rectangle with `text` (the string-rectangle prompt)
+pub(crate) fn apply_string_rectangle(editor: &mut EditorState, text: &str) {
+    let rect = match require_rectangle(editor) {
+        Some(rect) => rect,
+        None => return,
+    };
+    delete_rectangle_text(editor, rect, false);
+    let mut end = (rect.start_line, 0);
+    for line_idx in rect.start_line..=rect.end_line {
+        let byte = pad_to_col(editor, line_idx, rect.left);
+        editor.current_buffer_mut().insert_str(line_idx, byte, text);
+        end = (line_idx, byte + text.len());
+    }
+    editor.current_window_mut().set_cursor(end.0, end.1);
+    finish_rectangle_edit(editor, rect.start_line);
+}
+
+/// Number the lines of the rectangle at its left edge (C-x r N)
+///
+/// Numbering starts at 1, or at the prefix argument if one is given.
+pub fn rectangle_number_lines(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    let rect = match require_rectangle(editor) {
+        Some(rect) => rect,
+        None => return Ok(CommandStatus::Failure),
+    };
+    let first = if f { n as i64 } else { 1 };
+    let last = first + (rect.end_line - rect.start_line) as i64;
+    let width = first.to_string().len().max(last.to_string().len());
+    for (i, line_idx) in (rect.start_line..=rect.end_line).enumerate() {
+        let number = format!("{:>width$} ", first + i as i64, width = width);
+        let byte = pad_to_col(editor, line_idx, rect.left);
+        editor.current_buffer_mut().insert_str(line_idx, byte, &number);
+    }
+    goto_top_left(editor, rect);
+    finish_rectangle_edit(editor, rect.start_line);
+    Ok(CommandStatus::Success)
+}
@@ -0,0 +1,214 @@
+//! Register commands (C-x r)
+//!
+//! Each command here only records what it is going to do and asks for a
+//! register name; the editor passes the next key to `run_register_command`,
+//! which does the work.
+
+use crate::editor::EditorState;
+use crate::error::Result;
+use crate::register::{preview, Register, RegisterCommand};
+use super::CommandStatus;
+use super::mark::{collect_region_text, delete_region, get_region};
+use super::rectangle::{delete_rectangle_text, extract_rectangle, get_rectangle, insert_rectangle};
+
+/// Helper: wait for a register name
+fn read_register(editor: &mut EditorState, command: RegisterCommand) -> Result<CommandStatus> {
+    editor.register_pending = Some(command);
+    editor.display.set_message(command.prompt());
+    Ok(CommandStatus::Success)
+}
+
+/// Helper: fail unless the mark is set
+fn require_mark(editor: &mut EditorState) -> bool {
+    if editor.mark().is_none() {
+        editor.display.set_message("No mark set");
+        return false;
+    }
+    true
+}
+
+/// Copy the region into a register (C-x r s); with an argument, delete it too
+pub fn copy_to_register(editor: &mut EditorState, f: bool, _n: i32) -> Result<CommandStatus> {
+    if !require_mark(editor) {
+        return Ok(CommandStatus::Failure);
+    }
+    read_register(editor, RegisterCommand::CopyText { delete: f })
+}
+
+/// Copy the rectangle into a register (C-x r r); with an argument, delete it too
+pub fn copy_rectangle_to_register(editor: &mut EditorState, f: bool, _n: i32) -> Result<CommandStatus> {
+    if !require_mark(editor) {
+        return Ok(CommandStatus::Failure);
+    }
+    read_register(editor, RegisterCommand::CopyRectangle { delete: f })
+}
+
+/// Insert a register's text or rectangle (C-x r i)
+///
+/// Point is left before the text and the mark after it; with an argument
+/// it is the other way round.
+pub fn insert_register(editor: &mut EditorState, f: bool, _n: i32) -> Result<CommandStatus> {
+    read_register(editor, RegisterCommand::Insert { after: f })
+}
+
+/// Save point in a register (C-x r SPC)
+pub fn point_to_register(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    read_register(editor, RegisterCommand::Point)
+}
+
+/// Jump to a register's position or restore its window layout (C-x r j)
+pub fn jump_to_register(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    read_register(editor, RegisterCommand::Jump)
+}
+
+/// Save the window layout in a register (C-x r w)
+pub fn window_configuration_to_register(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    read_register(editor, RegisterCommand::WindowLayout)
+}
+
+/// Show all registers in the *Registers* buffer (C-x r v)
+pub fn list_registers(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    let mut content = String::new();
+    if editor.registers.is_empty() {
+        content.push_str("No registers\n");
+    }
+    for (name, register) in &editor.registers {
+        let description = match register {
+            Register::Text(text) => format!("text: {}", preview(text, 60)),
+            Register::Rectangle(rows) => {
+                let first = rows.first().map(|r| preview(r, 40)).unwrap_or_default();
+                format!("rectangle, {} lines: {}", rows.len(), first)
+            }
+            Register::Position(id) => match editor.find_marker(*id) {
+                Some((idx, (line, col))) => {
+                    let buffer = &editor.buffers[idx];
+                    let column = buffer.line(line).map(|l| l.byte_to_col(col, editor.tab_width())).unwrap_or(0);
+                    format!("position in {}, line {} column {}", buffer.name(), line + 1, column)
+                }
+                None => "position in a killed buffer".to_string(),
+            },
+            Register::Windows(layout) => {
+                let names: Vec<&str> = layout.windows.iter().map(|w| w.buffer.as_str()).collect();
+                format!("window layout, {} windows: {}", names.len(), names.join(", "))
+            }
+        };
+        content.push_str(&format!("{:<4} {}\n", name, description));
+    }
+    editor.show_generated_buffer("*Registers*", &content);
+    Ok(CommandStatus::Success)
+}
+
+/// Run a register command once its register name has been typed
+pub(crate) fn run_register_command(editor: &mut EditorState, command: RegisterCommand, name: char) {
+    match command {
+        RegisterCommand::CopyText { delete } => {
+            let (start_line, start_col, end_line, end_col) = match get_region(editor) {
+                Some(region) => region,
+                None => {
+                    editor.display.set_message("No mark set");
+                    return;
+                }
+            };
+            let text = collect_region_text(editor, start_line, start_col, end_line, end_col);
+            editor.set_register(name, Register::Text(text));
+            if delete {
+                editor.current_window_mut().set_cursor(start_line, start_col);
+                delete_region(editor, start_line, start_col, end_line, end_col);
+                editor.invalidate_syntax_from(start_line);
+                editor.display.force_redraw();
+            }
+            editor.clear_mark();
+            editor.display.set_message(format!("Copied to register {}", name));
+        }
+        RegisterCommand::CopyRectangle { delete } => {
+            let rect = match get_rectangle(editor) {
+                Some(rect) => rect,
+                None => {
+                    editor.display.set_message("No mark set");
+                    return;
+                }
+            };
+            let rows = extract_rectangle(editor, rect);
+            editor.set_register(name, Register::Rectangle(rows));
+            if delete {
+                delete_rectangle_text(editor, rect, false);
+                editor.invalidate_syntax_from(rect.start_line);
+            }
+            editor.clear_mark();
+            editor.display.force_redraw();
+            editor.display.set_message(format!("Copied rectangle to register {}", name));
+        }
+        RegisterCommand::Insert { after } => {
+            let register = editor.registers.get(&name).cloned();
+            let start = (editor.current_window().cursor_line(), editor.current_window().cursor_col());
+            let end = match register {
+                Some(Register::Text(text)) => {
+                    insert_text(editor, &text);
+                    (editor.current_window().cursor_line(), editor.current_window().cursor_col())
+                }
+                Some(Register::Rectangle(rows)) => insert_rectangle(editor, &rows),
+                Some(_) => {
+                    editor.display.set_message(format!("Register {} does not contain text", name));
+                    return;
+                }
+                None => {
+                    editor.display.set_message(format!("Register {} is empty", name));
+                    return;
+                }
+            };
+            let (point, mark) = if after { (end, start) } else { (start, end) };
+            editor.set_mark_at(mark.0, mark.1);
+            editor.current_window_mut().set_cursor(point.0, point.1);
+            editor.ensure_cursor_visible();
+        }
+        RegisterCommand::Point => {
+            let line = editor.current_window().cursor_line();
+            let col = editor.current_window().cursor_col();
+            let id = editor.current_buffer_mut().add_marker(line, col);
+            editor.set_register(name, Register::Position(id));
+            editor.display.set_message(format!("Point saved to register {}", name));
+        }
+        RegisterCommand::Jump => match editor.registers.get(&name).cloned() {
+            Some(Register::Position(id)) => match editor.find_marker(id) {
+                Some((idx, (line, col))) => {
+                    if editor.current_window().buffer_idx() != idx {
+                        editor.current_window_mut().set_buffer_idx(idx);
+                        editor.display.force_redraw();
+                    }
+                    editor.current_window_mut().set_cursor(line, col);
+                    editor.ensure_cursor_visible();
+                }
+                None => editor.display.set_message(format!("Register {}'s buffer no longer exists", name)),
+            },
+            Some(Register::Windows(layout)) => {
+                if let Err(message) = editor.restore_window_layout(&layout) {
+                    editor.display.set_message(message);
+                }
+            }
+            Some(_) => editor.display.set_message(format!("Register {} does not contain a position", name)),
+            None => editor.display.set_message(format!("Register {} is empty", name)),
+        },
+        RegisterCommand::WindowLayout => {
+            let layout = editor.save_window_layout();
+            editor.set_register(name, Register::Windows(layout));
+            editor.display.set_message(format!("Window layout saved to register {}", name));
+        }
+    }
+}
+
+/// Helper: insert text at point, leaving point after it
+fn insert_text(editor: &mut EditorState, text: &str) {
+    let start_line = editor.current_window().cursor_line();
+    for ch in text.chars() {
+        if ch == '\n' {
+            let line = editor.current_window().cursor_line();
+            let col = editor.current_window().cursor_col();
+            editor.current_buffer_mut().insert_newline(line, col);
+            editor.current_window_mut().set_cursor(line + 1, 0);
+        } else {
+            editor.insert_char(ch);
+        }
+    }
+    editor.invalidate_syntax_from(start_line);
+    editor.display.force_redraw();
+}
@@ -2,11 +2,22 @@
 
 use crate::editor::EditorState;
 use crate::error::Result;
+use crate::layout::SplitKind;
 use super::CommandStatus;
 
-/// Split current window horizontally
+/// Split current window into two, one above the other
 pub fn split_window(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
-    if editor.split_window() {
+    split(editor, SplitKind::Stacked)
+}
+
+/// Split current window into two, side by side
+pub fn split_window_right(editor: &mut EditorState, _f: bool, _n: i32) -> Result<CommandStatus> {
+    split(editor, SplitKind::SideBySide)
+}
+
+/// Helper: split the current window
+fn split(editor: &mut EditorState, kind: SplitKind) -> Result<CommandStatus> {
+    if editor.split_window(kind) {
         Ok(CommandStatus::Success)
     } else {
         editor.display.set_message("Window too small to split");
@@ -36,24 +47,42 @@ pub fn other_window(editor: &mut EditorState, _f: bool, _n: i32) -> Result<Comma
     Ok(CommandStatus::Success)
 }
 
-/// Enlarge current window
+/// Enlarge current window by n lines
 pub fn enlarge_window(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
-    let lines = if f { n.max(1) as u16 } else { 1 };
-    if editor.enlarge_window(lines) {
-        Ok(CommandStatus::Success)
-    } else {
-        editor.display.set_message("Can't enlarge window");
-        Ok(CommandStatus::Failure)
-    }
+    resize(editor, SplitKind::Stacked, if f { n } else { 1 })
 }
 
-/// Shrink current window
+/// Shrink current window by n lines
 pub fn shrink_window(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
-    let lines = if f { n.max(1) as u16 } else { 1 };
-    if editor.shrink_window(lines) {
-        Ok(CommandStatus::Success)
+    resize(editor, SplitKind::Stacked, if f { -n } else { -1 })
+}
+
+/// Widen current window by n columns
+pub fn enlarge_window_horizontally(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    resize(editor, SplitKind::SideBySide, if f { n } else { 1 })
+}
+
+/// Narrow current window by n columns
+pub fn shrink_window_horizontally(editor: &mut EditorState, f: bool, n: i32) -> Result<CommandStatus> {
+    resize(editor, SplitKind::SideBySide, if f { -n } else { -1 })
+}
+
+/// Helper: grow the current window by `n` rows or columns (shrink it if
+/// negative)
+fn resize(editor: &mut EditorState, kind: SplitKind, n: i32) -> Result<CommandStatus> {
+    let amount = n.unsigned_abs().max(1) as u16;
+    let resized = if n < 0 {
+        editor.shrink_window(kind, amount)
     } else {
+        editor.enlarge_window(kind, amount)
+    };
+    if resized {
+        Ok(CommandStatus::Success)
+    } else if n < 0 {
         editor.display.set_message("Can't shrink window");
         Ok(CommandStatus::Failure)
+    } else {
+        editor.display.set_message("Can't enlarge window");
+        Ok(CommandStatus::Failure)
     }
 }
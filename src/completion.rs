@@ -0,0 +1,248 @@
+//! Minibuffer completion
+//!
+//! TAB in a prompt that has candidates extends the input to the longest
+//! prefix shared by every candidate it matches. File name prompts complete
+//! against the entries of the directory typed so far.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Candidates starting with `input`
+pub fn matches<'a>(input: &str, candidates: &'a [String]) -> Vec<&'a str> {
+    candidates
+        .iter()
+        .map(|c| c.as_str())
+        .filter(|c| c.starts_with(input))
+        .collect()
+}
+
+/// Candidates with the chars of `input` in order, best first: those
+/// starting with `input`, then those containing it, then the rest, each
+/// closest matched and shortest first
+pub fn fuzzy_matches<'a>(input: &str, candidates: &'a [String]) -> Vec<&'a str> {
+    // Span of the chars matched, leftmost first, if they are all there
+    let span = |candidate: &str| {
+        let mut chars = candidate.char_indices();
+        let mut first = None;
+        let mut last = 0;
+        for ch in input.chars() {
+            let (idx, _) = chars.find(|&(_, c)| c == ch)?;
+            first.get_or_insert(idx);
+            last = idx;
+        }
+        Some(last - first.unwrap_or(0))
+    };
+    let mut found: Vec<(usize, usize, &str)> = candidates
+        .iter()
+        .filter_map(|c| {
+            let class = if c.starts_with(input) {
+                0
+            } else if c.contains(input) {
+                1
+            } else {
+                2
+            };
+            span(c).map(|span| (class, span, c.as_str()))
+        })
+        .collect();
+    found.sort_by_key(|&(class, span, c)| (class, span, c.len()));
+    found.into_iter().map(|(_, _, c)| c).collect()
+}
+
+/// Longest common prefix of some strings (on char boundaries)
+pub fn common_prefix(strings: &[&str]) -> String {
+    let first = match strings.first() {
+        Some(first) => *first,
+        None => return String::new(),
+    };
+    let mut end = first.len();
+    for s in &strings[1..] {
+        end = first[..end]
+            .char_indices()
+            .zip(s.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| end.min(s.len()));
+        while !first.is_char_boundary(end) {
+            end -= 1;
+        }
+    }
+    first[..end].to_string()
+}
+
+/// The part of a file name prompt's input that counts
+///
+/// As in Emacs, a `//` starts the name again at the root and a `/~` at
+/// the home directory, so a prefilled directory needn't be deleted first.
+pub fn file_name_tail(input: &str) -> &str {
+    let bytes = input.as_bytes();
+    let mut start = 0;
+    for i in 1..bytes.len() {
+        if bytes[i - 1] == b'/' && (bytes[i] == b'/' || bytes[i] == b'~') {
+            start = i;
+        }
+    }
+    &input[start..]
+}
+
+/// Split a file name after its last `/`: (directory part, name part)
+pub fn split_file_name(name: &str) -> (&str, &str) {
+    match name.rfind('/') {
+        Some(i) => (&name[..=i], &name[i + 1..]),
+        None => ("", name),
+    }
+}
+
+/// Expand a leading `~` to `home`, and take a relative name as relative
+/// to `dir`
+pub fn expand_file_name(name: &str, home: Option<&Path>, dir: &Path) -> PathBuf {
+    let path = match (name.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(name),
+    };
+    dir.join(path)
+}
+
+/// Write a path for a prompt, with the home directory as `~`
+pub fn abbreviate_path(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Write a directory for a prompt: with a trailing `/`, and the home
+/// directory as `~`
+pub fn abbreviate_directory(dir: &Path, home: Option<&Path>) -> String {
+    let shown = abbreviate_path(dir, home);
+    if shown.ends_with('/') {
+        shown
+    } else {
+        format!("{}/", shown)
+    }
+}
+
+/// Names of the entries in a directory, sorted, with a `/` after each
+/// directory (empty if it can't be read)
+pub fn directory_entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.path().is_dir() {
+                    format!("{}/", name)
+                } else {
+                    name
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+/// Directory entries starting with `input`; hidden entries only match
+/// once the input starts with a `.`
+pub fn file_matches<'a>(input: &str, entries: &'a [String]) -> Vec<&'a str> {
+    matches(input, entries)
+        .into_iter()
+        .filter(|name| input.starts_with('.') || !name.starts_with('.'))
+        .collect()
+}
+
+/// Get the home directory
+pub fn home_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var("USERPROFILE").ok().map(PathBuf::from)
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("HOME").ok().map(PathBuf::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion() {
+        let names: Vec<String> = ["main", "mainline", "maison", "other"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(matches("mai", &names), vec!["main", "mainline", "maison"]);
+        assert_eq!(common_prefix(&matches("mai", &names)), "mai");
+        assert_eq!(common_prefix(&matches("main", &names)), "main");
+        assert_eq!(common_prefix(&matches("o", &names)), "other");
+        assert_eq!(common_prefix(&matches("x", &names)), "");
+        assert_eq!(common_prefix(&["héllo", "hélp"]), "hél");
+    }
+
+    #[test]
+    fn test_fuzzy_matches() {
+        let names: Vec<String> = ["backward-sort", "sort-lines", "resort", "save-buffer", "kill-line"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(fuzzy_matches("sort", &names), vec!["sort-lines", "resort", "backward-sort"]);
+        assert_eq!(fuzzy_matches("sl", &names), vec!["sort-lines"]);
+        assert_eq!(fuzzy_matches("kln", &names), vec!["kill-line"]);
+        // Closer matches first
+        let names = vec!["append-next-kill".to_string(), "kill-region".to_string(), "kill-line".to_string(), "backward-kill-word".to_string()];
+        assert_eq!(fuzzy_matches("kl", &names), vec!["kill-line", "kill-region", "append-next-kill", "backward-kill-word"]);
+        assert!(fuzzy_matches("xyz", &names).is_empty());
+        assert_eq!(fuzzy_matches("", &names).len(), names.len());
+    }
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(file_name_tail("~/src/main.rs"), "~/src/main.rs");
+        assert_eq!(file_name_tail("/home/me/src//etc/hosts"), "/etc/hosts");
+        assert_eq!(file_name_tail("/home/me/src/~/notes"), "~/notes");
+        assert_eq!(file_name_tail("/a///b"), "/b");
+        assert_eq!(split_file_name("~/src/ma"), ("~/src/", "ma"));
+        assert_eq!(split_file_name("main"), ("", "main"));
+
+        let home = Path::new("/home/me");
+        let dir = Path::new("/work");
+        assert_eq!(expand_file_name("~/notes", Some(home), dir), PathBuf::from("/home/me/notes"));
+        assert_eq!(expand_file_name("~", Some(home), dir), PathBuf::from("/home/me/"));
+        assert_eq!(expand_file_name("~x", Some(home), dir), PathBuf::from("/work/~x"));
+        assert_eq!(expand_file_name("src/a.rs", Some(home), dir), PathBuf::from("/work/src/a.rs"));
+        assert_eq!(expand_file_name("/etc", Some(home), dir), PathBuf::from("/etc"));
+        assert_eq!(abbreviate_directory(Path::new("/home/me/src"), Some(home)), "~/src/");
+        assert_eq!(abbreviate_directory(home, Some(home)), "~/");
+        assert_eq!(abbreviate_directory(Path::new("/"), Some(home)), "/");
+        assert_eq!(abbreviate_directory(dir, None), "/work/");
+        assert_eq!(abbreviate_path(Path::new("/home/me/src/a.rs"), Some(home)), "~/src/a.rs");
+        assert_eq!(abbreviate_path(Path::new("/work/a.rs"), Some(home)), "/work/a.rs");
+    }
+
+    #[test]
+    fn test_fuzzy_matches_file_names() {
+        // A file name finds the paths of files with that name
+        let paths: Vec<String> = ["~/src/main.rs", "~/notes/todo.txt", "/etc/hosts"].iter().map(|s| s.to_string()).collect();
+        assert!(matches("main", &paths).is_empty());
+        assert_eq!(fuzzy_matches("main", &paths), vec!["~/src/main.rs"]);
+        assert_eq!(fuzzy_matches("todo", &paths), vec!["~/notes/todo.txt"]);
+    }
+
+    #[test]
+    fn test_directory_entries() {
+        let dir = std::env::temp_dir().join(format!("uemacs-completion-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("main.rs"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let entries = directory_entries(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(entries, vec![".hidden", "main.rs", "sub/"]);
+        assert_eq!(file_matches("", &entries), vec!["main.rs", "sub/"]);
+        assert_eq!(file_matches(".", &entries), vec![".hidden"]);
+        assert!(directory_entries(&dir).is_empty());
+    }
+}
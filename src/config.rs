@@ -12,6 +12,11 @@
 //! auto-save = true
 //! auto-save-interval = 60
 //! tab-width = 4
+//! persistent-undo = true
+//! osc52-clipboard = true
+//! mouse = true
+//! desktop = true
+//! startup-recent-files = true
 //! ```
 
 use std::collections::HashMap;
@@ -33,6 +38,16 @@ pub struct Config {
     pub warn_unsaved: bool,
     /// Whether syntax highlighting is enabled
     pub syntax_highlighting: bool,
+    /// Whether undo history is kept across sessions
+    pub persistent_undo: bool,
+    /// Whether kills are copied to the system clipboard with OSC 52
+    pub osc52_clipboard: bool,
+    /// Whether the mouse is captured (click, drag and wheel)
+    pub mouse: bool,
+    /// Whether the desktop is saved on exit and restored on startup
+    pub desktop: bool,
+    /// Whether starting without a file offers the recent files
+    pub startup_recent_files: bool,
 }
 
 impl Default for Config {
@@ -44,6 +59,11 @@ impl Default for Config {
             tab_width: 8,
             warn_unsaved: true,
             syntax_highlighting: true,
+            persistent_undo: false,
+            osc52_clipboard: true,
+            mouse: false,
+            desktop: false,
+            startup_recent_files: true,
         }
     }
 }
@@ -132,6 +152,26 @@ impl Config {
         if let Some(value) = settings.get("syntax-highlighting") {
             self.syntax_highlighting = parse_bool(value);
         }
+
+        if let Some(value) = settings.get("persistent-undo") {
+            self.persistent_undo = parse_bool(value);
+        }
+
+        if let Some(value) = settings.get("osc52-clipboard") {
+            self.osc52_clipboard = parse_bool(value);
+        }
+
+        if let Some(value) = settings.get("mouse") {
+            self.mouse = parse_bool(value);
+        }
+
+        if let Some(value) = settings.get("desktop") {
+            self.desktop = parse_bool(value);
+        }
+
+        if let Some(value) = settings.get("startup-recent-files") {
+            self.startup_recent_files = parse_bool(value);
+        }
     }
 
     /// Save current configuration to file
@@ -145,13 +185,23 @@ impl Config {
                  auto-save-interval = {}\n\
                  tab-width = {}\n\
                  warn-unsaved = {}\n\
-                 syntax-highlighting = {}\n",
+                 syntax-highlighting = {}\n\
+                 persistent-undo = {}\n\
+                 osc52-clipboard = {}\n\
+                 mouse = {}\n\
+                 desktop = {}\n\
+                 startup-recent-files = {}\n",
                 self.show_line_numbers,
                 self.auto_save,
                 self.auto_save_interval,
                 self.tab_width,
                 self.warn_unsaved,
-                self.syntax_highlighting
+                self.syntax_highlighting,
+                self.persistent_undo,
+                self.osc52_clipboard,
+                self.mouse,
+                self.desktop,
+                self.startup_recent_files
             );
             fs::write(path, contents)?;
         }
@@ -194,6 +244,11 @@ tab-width = 4
         settings.insert("auto-save".to_string(), "false".to_string());
         settings.insert("auto-save-interval".to_string(), "120".to_string());
         settings.insert("tab-width".to_string(), "2".to_string());
+        settings.insert("persistent-undo".to_string(), "yes".to_string());
+        settings.insert("osc52-clipboard".to_string(), "off".to_string());
+        settings.insert("mouse".to_string(), "on".to_string());
+        settings.insert("desktop".to_string(), "true".to_string());
+        settings.insert("startup-recent-files".to_string(), "off".to_string());
 
         config.apply(&settings);
 
@@ -201,6 +256,11 @@ tab-width = 4
         assert!(!config.auto_save);
         assert_eq!(config.auto_save_interval, 120);
         assert_eq!(config.tab_width, 2);
+        assert!(config.persistent_undo);
+        assert!(!config.osc52_clipboard);
+        assert!(config.mouse);
+        assert!(config.desktop);
+        assert!(!config.startup_recent_files);
     }
 
     #[test]
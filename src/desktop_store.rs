@@ -0,0 +1,309 @@
+//! Desktop - the editing session saved between runs
+//!
+//! The desktop is saved to `.uemacs-desktop` in the current directory if
+//! there is one there (a project desktop), and otherwise to
+//! `~/.uemacs-desktop` (or `%USERPROFILE%\.uemacs-desktop` on Windows). It
+//! records the buffers visiting files, the windows and a little editor
+//! state:
+//!
+//! ```text
+//! [buffers]
+//! file = /home/me/src/main.rs
+//! file = /home/me/src/lib.rs
+//!
+//! [layout]
+//! rows = 23
+//! columns = 80
+//! tree = below 12 window window
+//! current = 0
+//!
+//! [window]
+//! file = /home/me/src/main.rs
+//! top = 1
+//! line = 14
+//! column = 4
+//! mark-line = 10
+//! mark-column = 0
+//!
+//! [window]
+//! buffer = *scratch*
+//! top = 1
+//! line = 1
+//! column = 0
+//!
+//! [state]
+//! kill = first line\nsecond line
+//! search = fn main
+//! ```
+//!
+//! Lines are 1-based. A window shows a file, or a buffer without a file
+//! by name. In `kill` and `search`, newlines are written as `\n` and
+//! backslashes as `\\`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::layout::Layout;
+
+/// Name of the desktop file
+const DESKTOP_FILE: &str = ".uemacs-desktop";
+
+/// A window of a saved desktop
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DesktopWindow {
+    /// File shown, if the buffer is visiting one
+    pub file: Option<PathBuf>,
+    /// Name of the buffer shown, for a buffer without a file
+    pub buffer: String,
+    /// First line shown (0-indexed)
+    pub top_line: usize,
+    /// Cursor (line, byte offset), 0-indexed
+    pub point: (usize, usize),
+    /// Mark (line, byte offset), 0-indexed
+    pub mark: Option<(usize, usize)>,
+}
+
+/// A saved editing session
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Desktop {
+    /// Files visited by buffers, in buffer order
+    pub files: Vec<PathBuf>,
+    /// Windows in screen order
+    pub windows: Vec<DesktopWindow>,
+    /// How the windows divided the screen
+    pub layout: Layout,
+    /// Rows the windows shared
+    pub rows: u16,
+    /// Columns the windows shared
+    pub cols: u16,
+    /// Index of the selected window
+    pub current: usize,
+    /// Newest kill
+    pub kill: Option<String>,
+    /// Last search string
+    pub search: Option<String>,
+}
+
+/// Get the path to the desktop file
+pub fn desktop_file_path() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::current_dir() {
+        let local = dir.join(DESKTOP_FILE);
+        if local.is_file() {
+            return Some(local);
+        }
+    }
+    #[cfg(windows)]
+    {
+        std::env::var("USERPROFILE")
+            .ok()
+            .map(|p| PathBuf::from(p).join(DESKTOP_FILE))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("HOME")
+            .ok()
+            .map(|p| PathBuf::from(p).join(DESKTOP_FILE))
+    }
+}
+
+/// Escape newlines and backslashes for a one-line value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// Undo `escape`
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Parse a desktop from the file format
+pub fn parse_desktop(content: &str) -> Desktop {
+    let mut desktop = Desktop::default();
+    let mut section = String::new();
+    let line_number = |value: &str| value.parse::<usize>().unwrap_or(1).saturating_sub(1);
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        // Skip empty lines and comments
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+
+        // Section header; each [window] starts a new window
+        if trimmed.starts_with('[') && trimmed.ends_with(']') && trimmed.len() >= 2 {
+            section = trimmed[1..trimmed.len() - 1].to_string();
+            if section == "window" {
+                desktop.windows.push(DesktopWindow::default());
+            }
+            continue;
+        }
+
+        // key = value (kill and search keep their inner spacing)
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.strip_prefix(' ').unwrap_or(value)),
+            None => continue,
+        };
+        match (section.as_str(), key) {
+            ("buffers", "file") => desktop.files.push(PathBuf::from(value.trim())),
+            ("layout", "rows") => desktop.rows = value.trim().parse().unwrap_or(0),
+            ("layout", "columns") => desktop.cols = value.trim().parse().unwrap_or(0),
+            ("layout", "tree") => desktop.layout = Layout::from_spec(value).unwrap_or_default(),
+            ("layout", "current") => desktop.current = value.trim().parse().unwrap_or(0),
+            ("window", _) => {
+                let window = match desktop.windows.last_mut() {
+                    Some(window) => window,
+                    None => continue,
+                };
+                match key {
+                    "file" => window.file = Some(PathBuf::from(value.trim())),
+                    "buffer" => window.buffer = value.trim().to_string(),
+                    "top" => window.top_line = line_number(value.trim()),
+                    "line" => window.point.0 = line_number(value.trim()),
+                    "column" => window.point.1 = value.trim().parse().unwrap_or(0),
+                    "mark-line" => window.mark = Some((line_number(value.trim()), window.mark.map_or(0, |m| m.1))),
+                    "mark-column" => {
+                        let col = value.trim().parse().unwrap_or(0);
+                        window.mark = Some((window.mark.map_or(0, |m| m.0), col));
+                    }
+                    _ => {}
+                }
+            }
+            ("state", "kill") => desktop.kill = Some(unescape(value)),
+            ("state", "search") => desktop.search = Some(unescape(value)),
+            _ => {}
+        }
+    }
+    desktop
+}
+
+/// Format a desktop for the desktop file
+pub fn format_desktop(desktop: &Desktop) -> String {
+    let mut out = String::new();
+    out.push_str("# uEmacs.rs desktop\n");
+    out.push_str("# Buffers visiting files, then the windows; lines are 1-based\n");
+
+    out.push_str("\n[buffers]\n");
+    for file in &desktop.files {
+        out.push_str(&format!("file = {}\n", file.display()));
+    }
+
+    out.push_str("\n[layout]\n");
+    out.push_str(&format!("rows = {}\n", desktop.rows));
+    out.push_str(&format!("columns = {}\n", desktop.cols));
+    out.push_str(&format!("tree = {}\n", desktop.layout.to_spec()));
+    out.push_str(&format!("current = {}\n", desktop.current));
+
+    for window in &desktop.windows {
+        out.push_str("\n[window]\n");
+        match &window.file {
+            Some(file) => out.push_str(&format!("file = {}\n", file.display())),
+            None => out.push_str(&format!("buffer = {}\n", window.buffer)),
+        }
+        out.push_str(&format!("top = {}\n", window.top_line + 1));
+        out.push_str(&format!("line = {}\n", window.point.0 + 1));
+        out.push_str(&format!("column = {}\n", window.point.1));
+        if let Some((line, col)) = window.mark {
+            out.push_str(&format!("mark-line = {}\n", line + 1));
+            out.push_str(&format!("mark-column = {}\n", col));
+        }
+    }
+
+    if desktop.kill.is_some() || desktop.search.is_some() {
+        out.push_str("\n[state]\n");
+    }
+    if let Some(kill) = &desktop.kill {
+        out.push_str(&format!("kill = {}\n", escape(kill)));
+    }
+    if let Some(search) = &desktop.search {
+        out.push_str(&format!("search = {}\n", escape(search)));
+    }
+    out
+}
+
+/// Load the desktop from the desktop file, None if there is none
+pub fn load_desktop() -> Option<Desktop> {
+    desktop_file_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_desktop(&content))
+}
+
+/// Save the desktop to the desktop file, returns the file's path
+pub fn save_desktop(desktop: &Desktop) -> io::Result<PathBuf> {
+    let path = match desktop_file_path() {
+        Some(p) => p,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Could not determine desktop file path",
+            ))
+        }
+    };
+    let mut file = fs::File::create(&path)?;
+    file.write_all(format_desktop(desktop).as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Rect, SplitKind};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut layout = Layout::default();
+        let area = Rect { top: 0, left: 0, height: 23, width: 80 };
+        assert!(layout.split(0, SplitKind::SideBySide, area));
+        let desktop = Desktop {
+            files: vec![PathBuf::from("/tmp/a.rs"), PathBuf::from("/tmp/b c.txt")],
+            windows: vec![
+                DesktopWindow {
+                    file: Some(PathBuf::from("/tmp/a.rs")),
+                    buffer: String::new(),
+                    top_line: 3,
+                    point: (9, 4),
+                    mark: Some((2, 0)),
+                },
+                DesktopWindow {
+                    file: None,
+                    buffer: "*scratch*".to_string(),
+                    top_line: 0,
+                    point: (0, 0),
+                    mark: None,
+                },
+            ],
+            layout,
+            rows: 23,
+            cols: 80,
+            current: 1,
+            kill: Some("two\nlines \\n ".to_string()),
+            search: Some(" fn main".to_string()),
+        };
+        assert_eq!(parse_desktop(&format_desktop(&desktop)), desktop);
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let desktop = parse_desktop("[layout]\ntree = nonsense\n\n[window]\nfile = /x\nmark-column = 3\n");
+        assert_eq!(desktop.layout, Layout::Window(0));
+        assert_eq!(desktop.windows.len(), 1);
+        assert_eq!(desktop.windows[0].point, (0, 0));
+        assert_eq!(desktop.windows[0].mark, Some((0, 3)));
+        assert_eq!(desktop.kill, None);
+    }
+}
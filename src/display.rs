@@ -1,7 +1,10 @@
 //! Display rendering
 
+use std::ops::Range;
+
 use crate::buffer::Buffer;
 use crate::error::Result;
+use crate::line::{char_width, wrap_rows, Line, TAB_WIDTH};
 use crate::syntax::{Span, Style, SyntaxManager};
 use crate::terminal::Terminal;
 use crate::window::Window;
@@ -69,8 +72,31 @@ impl Region {
             Some((start, end.max(start)))
         }
     }
+
+    /// Get the portion of a line inside the rectangle spanning display
+    /// columns `left..right` of the region's lines (as byte offsets), with
+    /// tab stops `tab_width` apart
+    fn rectangle_intersection(&self, line_idx: usize, line: &Line, left: usize, right: usize, tab_width: usize) -> Option<(usize, usize)> {
+        if line_idx < self.start_line || line_idx > self.end_line {
+            return None;
+        }
+        let start = line.col_to_byte(left, tab_width)?;
+        let end = line.col_to_byte(right, tab_width).unwrap_or(line.len()).max(start);
+        Some((start, end))
+    }
 }
 
+/// Continuation glyph at the end of a screen row that a wrapped line
+/// goes on from
+const WRAP_GLYPH: char = '\\';
+
+/// Marker at the edge of a row whose line goes on past that edge (when
+/// scrolled horizontally)
+const TRUNCATION_GLYPH: char = '$';
+
+/// Column between side-by-side windows
+const DIVIDER_GLYPH: char = '|';
+
 /// Display state
 pub struct Display {
     /// Whether a full redraw is needed
@@ -79,6 +105,12 @@ pub struct Display {
     message: Option<String>,
     /// Whether to show line numbers
     pub show_line_numbers: bool,
+    /// Columns between tab stops
+    pub tab_width: usize,
+    /// Extra cursor positions in the current window, for multiple cursors
+    pub extra_cursors: Vec<(usize, usize)>,
+    /// Column of the cursor in the minibuffer while a prompt reads input
+    pub minibuffer_cursor: Option<u16>,
 }
 
 impl Display {
@@ -87,6 +119,9 @@ impl Display {
             needs_redraw: true,
             message: None,
             show_line_numbers: false,
+            tab_width: TAB_WIDTH,
+            extra_cursors: Vec::new(),
+            minibuffer_cursor: None,
         }
     }
 
@@ -106,6 +141,57 @@ impl Display {
         digits.max(3) + 1 // minimum 3 digits + space
     }
 
+    /// Columns left for text in a window `cols` wide showing a buffer,
+    /// after the line numbers
+    pub fn text_cols(&self, buffer: &Buffer, cols: usize) -> usize {
+        let first_line = buffer.visible_start().0;
+        cols.saturating_sub(self.line_number_width(buffer.visible_end().0 - first_line + 1))
+    }
+
+    /// Columns text can wrap at in a window `cols` wide, or None if the
+    /// buffer's lines don't wrap (one column is kept for the continuation
+    /// glyph)
+    fn wrap_width(&self, buffer: &Buffer, cols: usize) -> Option<usize> {
+        if !buffer.modes().visual_line {
+            return None;
+        }
+        Some(self.text_cols(buffer, cols).saturating_sub(1)).filter(|&width| width > 0)
+    }
+
+    /// Screen row within its line, and display column within that row, of
+    /// a buffer position in a window `cols` wide
+    ///
+    /// Columns count from the start of the row, or of the visible part of
+    /// a line cut by narrowing.
+    pub fn row_and_column(&self, buffer: &Buffer, line_idx: usize, col: usize, cols: usize) -> (usize, usize) {
+        let starts = self.line_rows(buffer, line_idx, cols);
+        let row = row_of(&starts, col);
+        let display_col = buffer
+            .line(line_idx)
+            .map(|line| Line::from(line.safe_slice(starts[row], col.max(starts[row]))).display_width(self.tab_width))
+            .unwrap_or(0);
+        (row, display_col)
+    }
+
+    /// Byte offsets where the screen rows of a line start, in a window
+    /// `cols` wide
+    ///
+    /// Only a buffer in visual-line mode has lines taking more than one
+    /// row. The first row starts where the visible part of the line does.
+    pub fn line_rows(&self, buffer: &Buffer, line_idx: usize, cols: usize) -> Vec<usize> {
+        let (from, to) = match buffer.visible_span(line_idx) {
+            Some(span) => span,
+            None => return vec![0],
+        };
+        match (buffer.line(line_idx), self.wrap_width(buffer, cols)) {
+            (Some(line), Some(width)) => wrap_rows(line.safe_slice(from, to), width, self.tab_width)
+                .into_iter()
+                .map(|start| start + from)
+                .collect(),
+            _ => vec![from],
+        }
+    }
+
     /// Mark that a full redraw is needed
     pub fn force_redraw(&mut self) {
         self.needs_redraw = true;
@@ -116,6 +202,11 @@ impl Display {
         self.message = Some(msg.into());
     }
 
+    /// Get the message, if one is shown
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
     /// Clear the message
     pub fn clear_message(&mut self) {
         self.message = None;
@@ -137,14 +228,15 @@ impl Display {
             terminal.clear_screen()?;
         }
 
-        // Render each window
-        for (i, window) in windows.iter().enumerate() {
+        // Render each window, in screen order so a window's rows are
+        // drawn after those of any window left of it
+        for (i, window) in windows.iter().enumerate().filter(|(_, w)| !w.is_hidden()) {
             let is_current = i == current_window;
-            self.render_window(terminal, window, buffers, cols, is_current, syntax)?;
+            self.render_window(terminal, window, buffers, is_current, syntax)?;
         }
 
         // Render minibuffer (message line) at bottom
-        self.render_minibuffer(terminal, rows - 1, cols)?;
+        self.render_minibuffer(terminal, rows.saturating_sub(1), cols)?;
 
         // Position cursor in current window
         if let Some(window) = windows.get(current_window) {
@@ -158,13 +250,13 @@ impl Display {
         Ok(())
     }
 
-    /// Render a single window
+    /// Render a single window, and the divider right of it if there is a
+    /// window beside it
     fn render_window(
         &self,
         terminal: &mut Terminal,
         window: &Window,
         buffers: &[Buffer],
-        cols: usize,
         is_current: bool,
         syntax: &mut SyntaxManager,
     ) -> Result<()> {
@@ -176,16 +268,23 @@ impl Display {
         let buf_idx = window.buffer_idx();
         let top_row = window.top_row();
         let height = window.height() as usize;
+        let left = window.screen_col();
+        let cols = window.width() as usize;
         let top_line = window.top_line();
         let line_count = buffer.line_count();
 
+        // Only the narrowed part of a narrowed buffer is shown, numbered
+        // from its first line
+        let first_line = buffer.visible_start().0;
+        let visible_lines = buffer.visible_end().0 - first_line + 1;
+
         // Calculate line number width
-        let lnum_width = self.line_number_width(line_count);
+        let lnum_width = self.line_number_width(visible_lines);
         let text_cols = cols.saturating_sub(lnum_width);
 
         // Get region if mark is set (only for current window)
         let region = if is_current {
-            window.mark().map(|(mark_line, mark_col)| {
+            window.mark_marker().and_then(|id| buffer.marker(id)).map(|(mark_line, mark_col)| {
                 Region::from_mark_and_cursor(
                     mark_line,
                     mark_col,
@@ -197,28 +296,112 @@ impl Display {
             None
         };
 
-        // Render each line in the window
-        for row_offset in 0..height {
+        // Display columns of a rectangle region (C-x SPC)
+        let rectangle = region.filter(|_| window.rectangle_mode()).map(|r| {
+            let col = |line: usize, byte: usize| buffer.line(line).map(|l| l.byte_to_col(byte, self.tab_width)).unwrap_or(0);
+            let start = col(r.start_line, r.start_col);
+            let end = col(r.end_line, r.end_col);
+            (start.min(end), start.max(end))
+        });
+
+        // Render each line in the window; a wrapped line takes several rows
+        let mut row_offset = 0;
+        let mut line_idx = top_line;
+        while row_offset < height {
             let screen_row = top_row + row_offset as u16;
-            let line_idx = top_line + row_offset;
-
-            terminal.move_cursor(screen_row, 0)?;
 
-            if let Some(line) = buffer.line(line_idx) {
-                // Render line number if enabled
-                if self.show_line_numbers {
-                    let lnum_str = format!("{:>width$} ", line_idx + 1, width = lnum_width - 1);
-                    terminal.set_dim(true)?;
-                    terminal.write_str(&lnum_str)?;
-                    terminal.set_dim(false)?;
-                }
+            terminal.move_cursor(screen_row, left)?;
 
+            if let (Some(line), Some((_, to))) = (buffer.line(line_idx), buffer.visible_span(line_idx)) {
                 // Get syntax highlighting spans for this line
                 let text = line.text();
                 let syntax_spans = syntax.highlight_line(buf_idx, line_idx, text, line_count);
 
+                // Extra cursors on this line (current window only)
+                let cursors: Vec<usize> = if is_current {
+                    self.extra_cursors
+                        .iter()
+                        .filter(|(l, _)| *l == line_idx)
+                        .map(|(_, c)| *c)
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
                 // Render line content with syntax and region highlighting
-                self.render_line_with_highlighting(terminal, text, line_idx, text_cols, &region, &syntax_spans)?;
+                let selected = match (&region, rectangle) {
+                    (Some(r), Some((left, right))) => r.rectangle_intersection(line_idx, line, left, right, self.tab_width),
+                    (Some(r), None) => r.line_intersection(line_idx, text.len()),
+                    (None, _) => None,
+                };
+
+                let starts = self.line_rows(buffer, line_idx, cols);
+                // Wrapped lines never need scrolling sideways
+                let left_col = if starts.len() > 1 { 0 } else { window.left_col() };
+                for (row, &from) in starts.iter().enumerate() {
+                    if row_offset >= height {
+                        break;
+                    }
+                    terminal.move_cursor(top_row + row_offset as u16, left)?;
+
+                    // Line number on the first row only
+                    if self.show_line_numbers {
+                        let lnum_str = if row == 0 {
+                            format!("{:>width$} ", line_idx - first_line + 1, width = lnum_width - 1)
+                        } else {
+                            " ".repeat(lnum_width)
+                        };
+                        terminal.set_dim(true)?;
+                        terminal.write_str(&lnum_str)?;
+                        terminal.set_dim(false)?;
+                    }
+
+                    let last_row = row + 1 == starts.len();
+                    let end = if last_row { to } else { starts[row + 1] };
+                    let shown = line.safe_slice(from, end);
+                    if (from, end) == (0, text.len()) {
+                        self.render_line_with_highlighting(terminal, text, left_col..left_col + text_cols, selected, &syntax_spans, &cursors)?;
+                    } else {
+                        // A wrapped row, or a line cut by the narrowing:
+                        // shift everything to the part shown
+                        let shift = |pos: usize| pos.clamp(from, end) - from;
+                        let spans: Vec<Span> = syntax_spans
+                            .iter()
+                            .filter(|span| span.end > from && span.start < end)
+                            .map(|span| Span { start: shift(span.start), end: shift(span.end), style: span.style })
+                            .collect();
+                        let selected = selected
+                            .filter(|&(start, stop)| start < end && stop > from)
+                            .map(|(start, stop)| (shift(start), shift(stop)));
+                        let cursors: Vec<usize> = cursors
+                            .iter()
+                            .filter(|&&c| c >= from && (c < end || (last_row && c == end)))
+                            .map(|&c| c - from)
+                            .collect();
+                        let max_cols = if last_row { text_cols } else { text_cols.saturating_sub(1) };
+                        self.render_line_with_highlighting(terminal, shown, left_col..left_col + max_cols, selected, &spans, &cursors)?;
+                    }
+
+                    terminal.clear_to_eol()?;
+                    // Show where a line goes on past either edge
+                    if left_col > 0 && !shown.is_empty() {
+                        terminal.move_cursor(top_row + row_offset as u16, left + lnum_width as u16)?;
+                        terminal.write_char(TRUNCATION_GLYPH)?;
+                    }
+                    if last_row && Line::from(shown).display_width(self.tab_width) > left_col + text_cols {
+                        terminal.move_cursor(top_row + row_offset as u16, left + (lnum_width + text_cols - 1) as u16)?;
+                        terminal.write_char(TRUNCATION_GLYPH)?;
+                    }
+                    if !last_row {
+                        terminal.move_cursor(top_row + row_offset as u16, left + (lnum_width + text_cols - 1) as u16)?;
+                        terminal.set_dim(true)?;
+                        terminal.write_char(WRAP_GLYPH)?;
+                        terminal.set_dim(false)?;
+                    }
+                    row_offset += 1;
+                }
+                line_idx += 1;
+                continue;
             } else {
                 // Empty line indicator (like vim's ~)
                 if self.show_line_numbers {
@@ -230,12 +413,26 @@ impl Display {
             }
 
             terminal.clear_to_eol()?;
+            row_offset += 1;
+            line_idx += 1;
         }
 
         // Render mode line
         let mode_line_row = top_row + height as u16;
         self.render_mode_line(terminal, buffer, window, mode_line_row, cols, is_current)?;
 
+        // Divider between this window and the one right of it, drawn
+        // over the rows cleared to the end of the line
+        let divider_col = left + cols as u16;
+        if divider_col < terminal.cols() {
+            terminal.set_reverse(true)?;
+            for row in top_row..=mode_line_row {
+                terminal.move_cursor(row, divider_col)?;
+                terminal.write_char(DIVIDER_GLYPH)?;
+            }
+            terminal.set_reverse(false)?;
+        }
+
         Ok(())
     }
 
@@ -254,7 +451,7 @@ impl Display {
         match intersection {
             None => {
                 // No region on this line, render normally
-                let display_text = truncate_to_width(text, max_cols);
+                let display_text = truncate_to_width(text, max_cols, self.tab_width);
                 terminal.write_str(&display_text)?;
             }
             Some((start_byte, end_byte)) => {
@@ -290,7 +487,7 @@ impl Display {
                 // Part 1: Before selection
                 if start_byte > 0 {
                     let before = safe_slice_to(text, start_byte);
-                    let display_before = truncate_to_width(before, max_cols);
+                    let display_before = truncate_to_width(before, max_cols, self.tab_width);
                     terminal.write_str(&display_before)?;
                 }
 
@@ -299,7 +496,7 @@ impl Display {
                     let selected = safe_slice(text, start_byte, end_byte);
                     let remaining_cols = max_cols.saturating_sub(before_end_col);
                     if remaining_cols > 0 {
-                        let display_selected = truncate_to_width(selected, remaining_cols);
+                        let display_selected = truncate_to_width(selected, remaining_cols, self.tab_width);
                         terminal.apply_style(&selection_style)?;
                         terminal.write_str(&display_selected)?;
                         terminal.reset_attributes()?;
@@ -311,7 +508,7 @@ impl Display {
                     let after = safe_slice_from(text, end_byte);
                     let remaining_cols = max_cols.saturating_sub(selection_end_col);
                     if remaining_cols > 0 {
-                        let display_after = truncate_to_width(after, remaining_cols);
+                        let display_after = truncate_to_width(after, remaining_cols, self.tab_width);
                         terminal.write_str(&display_after)?;
                     }
                 }
@@ -321,22 +518,23 @@ impl Display {
         Ok(())
     }
 
-    /// Render a line with syntax highlighting and optional region highlighting
+    /// Render a line with syntax highlighting, the part of it in the region
+    /// and extra cursors (all given as byte offsets), showing the display
+    /// columns `columns` of the line
     fn render_line_with_highlighting(
         &self,
         terminal: &mut Terminal,
         text: &str,
-        line_idx: usize,
-        max_cols: usize,
-        region: &Option<Region>,
+        columns: Range<usize>,
+        region_intersection: Option<(usize, usize)>,
         syntax_spans: &[Span],
+        cursors: &[usize],
     ) -> Result<()> {
-        // Get region intersection if any
-        let region_intersection = region.as_ref().and_then(|r| r.line_intersection(line_idx, text.len()));
+        let Range { start: left_col, end: max_cols } = columns;
 
-        // If no syntax spans and no region, render plain
-        if syntax_spans.is_empty() && region_intersection.is_none() {
-            let display_text = truncate_to_width(text, max_cols);
+        // If no syntax spans, region or cursors, render plain
+        if left_col == 0 && syntax_spans.is_empty() && region_intersection.is_none() && cursors.is_empty() {
+            let display_text = truncate_to_width(text, max_cols, self.tab_width);
             terminal.write_str(&display_text)?;
             return Ok(());
         }
@@ -350,12 +548,24 @@ impl Display {
                 break;
             }
 
-            let ch_width = unicode_width::UnicodeWidthChar::width(ch).unwrap_or(1);
+            let ch_width = char_width(ch, display_col, self.tab_width);
+
+            // Characters scrolled off the left edge (the visible part of
+            // one straddling it shows as spaces)
+            if display_col < left_col {
+                if display_col + ch_width > left_col {
+                    terminal.write_str(&" ".repeat(display_col + ch_width - left_col))?;
+                }
+                display_col += ch_width;
+                continue;
+            }
 
             // Check if in region (region takes precedence, uses reverse video)
+            // or under an extra cursor
             let in_region = region_intersection
                 .map(|(start, end)| byte_pos >= start && byte_pos < end)
-                .unwrap_or(false);
+                .unwrap_or(false)
+                || cursors.contains(&byte_pos);
 
             // Find syntax style for this position
             let syntax_style = syntax_spans
@@ -376,8 +586,12 @@ impl Display {
                 terminal.apply_style(&style)?;
             }
 
-            // Write the character
-            terminal.write_char(ch)?;
+            // Write the character (tabs as spaces up to the next tab stop)
+            if ch == '\t' {
+                terminal.write_str(&" ".repeat(ch_width.min(max_cols - display_col)))?;
+            } else {
+                terminal.write_char(ch)?;
+            }
             display_col += ch_width;
 
             // Reset if we applied any style
@@ -386,6 +600,13 @@ impl Display {
             }
         }
 
+        // An extra cursor at end of line is shown on a blank cell
+        if cursors.contains(&text.len()) && display_col >= left_col && display_col < max_cols {
+            terminal.apply_style(&selection_style)?;
+            terminal.write_char(' ')?;
+            terminal.reset_attributes()?;
+        }
+
         Ok(())
     }
 
@@ -399,7 +620,7 @@ impl Display {
         cols: usize,
         is_current: bool,
     ) -> Result<()> {
-        terminal.move_cursor(row, 0)?;
+        terminal.move_cursor(row, window.screen_col())?;
         terminal.set_reverse(true)?;
 
         // Build mode line content
@@ -410,9 +631,12 @@ impl Display {
             .map(|p| p.to_string_lossy().into_owned())
             .unwrap_or_else(|| "".to_string());
 
-        // Calculate position percentage
-        let line_count = buffer.line_count();
-        let cursor_line = window.cursor_line() + 1;
+        // Calculate position percentage (within the narrowing, if narrowed)
+        let first_line = buffer.visible_start().0;
+        let line_count = buffer.visible_end().0 - first_line + 1;
+        let cursor_line = window.cursor_line().saturating_sub(first_line) + 1;
+        let narrowed = if buffer.is_narrowed() { " Narrow" } else { "" };
+        let wrapped = if buffer.modes().visual_line { " Wrap" } else { "" };
         let percent = if line_count <= 1 {
             "All".to_string()
         } else if cursor_line == 1 {
@@ -423,14 +647,17 @@ impl Display {
             format!("{}%", cursor_line * 100 / line_count)
         };
 
-        // Format: -- uEmacs.rs: buffername (filename) --line-- percent --
+        // Format: -- uEmacs.rs: buffername (filename) [format] --line-- percent --
         let indicator = if is_current { "=" } else { "-" };
         let mode_line = format!(
-            "{}{} uEmacs.rs: {} ({}) L{} {} {}",
+            "{}{} uEmacs.rs: {} ({}) [{}]{}{} L{} {} {}",
             modified,
             indicator,
             name,
             if filename.is_empty() { "no file" } else { &filename },
+            buffer.format().indicator(),
+            narrowed,
+            wrapped,
             cursor_line,
             percent,
             indicator.repeat(10)
@@ -440,7 +667,7 @@ impl Display {
         let padded = if mode_line.len() < cols {
             format!("{}{}", mode_line, "-".repeat(cols - mode_line.len()))
         } else {
-            truncate_to_width(&mode_line, cols)
+            truncate_to_width(&mode_line, cols, self.tab_width)
         };
 
         terminal.write_str(&padded)?;
@@ -459,7 +686,7 @@ impl Display {
         terminal.move_cursor(row, 0)?;
 
         if let Some(ref msg) = self.message {
-            let truncated = truncate_to_width(msg, cols);
+            let truncated = truncate_to_width(msg, cols, self.tab_width);
             terminal.write_str(&truncated)?;
         }
 
@@ -467,6 +694,58 @@ impl Display {
         Ok(())
     }
 
+    /// Buffer position (line, byte offset) shown at a screen cell of a
+    /// window
+    ///
+    /// Rows outside the window's text area are clamped to it, cells past
+    /// the end of a line give the end of the line, and rows past the end
+    /// of the buffer give the end of its last visible line.
+    pub fn screen_to_buffer(&self, window: &Window, buffer: &Buffer, cols: usize, row: u16, col: u16) -> (usize, usize) {
+        let first_line = buffer.visible_start().0;
+        let last_line = buffer.visible_end().0;
+        let lnum_width = self.line_number_width(last_line - first_line + 1);
+
+        // Find the line and the row of it shown on the screen row
+        let mut row_offset = row.saturating_sub(window.top_row()).min(window.height().saturating_sub(1)) as usize;
+        let mut line_idx = window.top_line();
+        let mut starts = self.line_rows(buffer, line_idx, cols);
+        while row_offset >= starts.len() && line_idx < last_line {
+            row_offset -= starts.len();
+            line_idx += 1;
+            starts = self.line_rows(buffer, line_idx, cols);
+        }
+        if line_idx > last_line || row_offset >= starts.len() {
+            return buffer.visible_end();
+        }
+
+        let (line, to) = match (buffer.line(line_idx), buffer.visible_span(line_idx)) {
+            (Some(line), Some((_, to))) => (line, to),
+            _ => return buffer.visible_end(),
+        };
+        let from = starts[row_offset];
+        let last_row = row_offset + 1 == starts.len();
+        let text = line.safe_slice(from, if last_row { to } else { starts[row_offset + 1] });
+        let left_col = if starts.len() > 1 { 0 } else { window.left_col() };
+        let target = (col.saturating_sub(window.screen_col()) as usize).saturating_sub(lnum_width) + left_col;
+
+        // The character whose cells include the target column (tabs and
+        // wide characters cover several)
+        let mut display_col = 0;
+        for (byte_pos, ch) in text.char_indices() {
+            let width = char_width(ch, display_col, self.tab_width);
+            if display_col + width > target {
+                return (line_idx, from + byte_pos);
+            }
+            display_col += width;
+        }
+        // Past the end of a wrapped row: its last character, since the
+        // end is the start of the next row
+        match text.char_indices().last() {
+            Some((byte_pos, _)) if !last_row => (line_idx, from + byte_pos),
+            _ => (line_idx, from + text.len()),
+        }
+    }
+
     /// Position the hardware cursor at the correct location
     fn position_cursor(
         &self,
@@ -474,32 +753,44 @@ impl Display {
         window: &Window,
         buffers: &[Buffer],
     ) -> Result<()> {
+        // A prompt's cursor is in the minibuffer
+        if let Some(col) = self.minibuffer_cursor {
+            terminal.move_cursor(terminal.rows().saturating_sub(1), col)?;
+            return Ok(());
+        }
+
         let buffer = match buffers.get(window.buffer_idx()) {
             Some(b) => b,
             None => return Ok(()),
         };
 
+        let cols = window.width() as usize;
         let cursor_line = window.cursor_line();
         let cursor_col = window.cursor_col();
 
-        // Convert byte offset to display column
-        let display_col = if let Some(line) = buffer.line(cursor_line) {
-            line.byte_to_col(cursor_col)
-        } else {
-            0
-        };
+        // Convert byte offset to display column, counted from the start of
+        // the screen row (for wrapped lines) or of the visible part of the
+        // line
+        let (row, display_col) = self.row_and_column(buffer, cursor_line, cursor_col, cols);
+        let display_col = display_col.saturating_sub(window.left_col());
 
         // Account for line number width
-        let lnum_width = self.line_number_width(buffer.line_count());
-
-        // Calculate screen position
+        let first_line = buffer.visible_start().0;
+        let lnum_width = self.line_number_width(buffer.visible_end().0 - first_line + 1);
+
+        // Calculate screen position, counting the rows of wrapped lines
+        // above the cursor
+        let rows_above: usize = (window.top_line()..cursor_line)
+            .take(window.height() as usize)
+            .map(|line| self.line_rows(buffer, line, cols).len())
+            .sum();
         let screen_row = if cursor_line >= window.top_line() {
-            window.top_row() + (cursor_line - window.top_line()) as u16
+            window.top_row() + (rows_above + row).min(window.height().saturating_sub(1) as usize) as u16
         } else {
             window.top_row()
         };
 
-        let screen_col = (lnum_width + display_col).min(terminal.cols() as usize - 1) as u16;
+        let screen_col = window.screen_col() + (lnum_width + display_col).min(cols.saturating_sub(1)) as u16;
 
         terminal.move_cursor(screen_row, screen_col)?;
         Ok(())
@@ -512,17 +803,27 @@ impl Default for Display {
     }
 }
 
-/// Truncate a string to fit within a display width
-fn truncate_to_width(s: &str, max_width: usize) -> String {
+/// Index of the screen row, in a line's row starts, that holds byte `col`
+fn row_of(starts: &[usize], col: usize) -> usize {
+    starts.partition_point(|&start| start <= col).saturating_sub(1)
+}
+
+/// Truncate a string to fit within a display width, expanding tabs (with
+/// stops `tab_width` apart) to spaces
+fn truncate_to_width(s: &str, max_width: usize, tab_width: usize) -> String {
     let mut result = String::new();
     let mut width = 0;
 
     for ch in s.chars() {
-        let ch_width = unicode_width::UnicodeWidthChar::width(ch).unwrap_or(1);
+        let ch_width = char_width(ch, width, tab_width);
         if width + ch_width > max_width {
             break;
         }
-        result.push(ch);
+        if ch == '\t' {
+            result.push_str(&" ".repeat(ch_width));
+        } else {
+            result.push(ch);
+        }
         width += ch_width;
     }
 
@@ -580,3 +881,87 @@ fn find_safe_boundary(s: &str, offset: usize, forward: bool) -> usize {
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 20-column window with five text rows, starting at screen row 2
+    fn test_window() -> Window {
+        let mut window = Window::new(0, 2, 5);
+        window.set_width(20);
+        window
+    }
+
+    #[test]
+    fn test_screen_to_buffer() {
+        let display = Display::new();
+        let window = test_window();
+        let buffer = Buffer::from_content("click", "hello\nworld");
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 0), (0, 0));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 3, 2), (1, 2));
+        // Past the end of a line, and below the last line
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 15), (0, 5));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 5, 1), (1, 5));
+    }
+
+    #[test]
+    fn test_screen_to_buffer_mode_line() {
+        // The mode line row is clamped to the last text row
+        let display = Display::new();
+        let window = test_window();
+        let buffer = Buffer::from_content("click", "a\nb\nc\nd\ne\nf");
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 7, 0), (4, 0));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 0, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_screen_to_buffer_line_numbers() {
+        let mut display = Display::new();
+        display.show_line_numbers = true;
+        let window = test_window();
+        let buffer = Buffer::from_content("click", "hello\nworld");
+        // The gutter is four columns wide
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 3, 2), (1, 0));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 3, 6), (1, 2));
+    }
+
+    #[test]
+    fn test_screen_to_buffer_tabs_and_wide_chars() {
+        let display = Display::new();
+        let window = test_window();
+        let buffer = Buffer::from_content("click", "\tx\na漢b");
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 3), (0, 0));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 7), (0, 0));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 8), (0, 1));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 3, 1), (1, 1));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 3, 2), (1, 1));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 3, 3), (1, 4));
+    }
+
+    #[test]
+    fn test_screen_to_buffer_wrapped_rows() {
+        let display = Display::new();
+        let mut window = Window::new(0, 2, 5);
+        window.set_width(10);
+        let mut buffer = Buffer::from_content("click", "abcdefghijklm\nz");
+        buffer.modes_mut().visual_line = true;
+        // Rows are nine columns wide, leaving one for the wrap glyph
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 10, 3, 2), (0, 11));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 10, 2, 9), (0, 8));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 10, 3, 8), (0, 13));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 10, 4, 0), (1, 0));
+    }
+
+    #[test]
+    fn test_screen_to_buffer_scrolled_columns() {
+        let display = Display::new();
+        let mut window = test_window();
+        window.set_screen_col(30);
+        window.scroll_columns(3);
+        let buffer = Buffer::from_content("click", "abcdefgh");
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 30), (0, 3));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 32), (0, 5));
+        assert_eq!(display.screen_to_buffer(&window, &buffer, 20, 2, 45), (0, 8));
+    }
+}
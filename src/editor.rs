@@ -3,23 +3,39 @@
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+
 use crate::buffer::Buffer;
 use crate::command::{CommandStatus, KeyTable};
+use crate::desktop_store::{Desktop, DesktopWindow};
 use crate::display::Display;
 use crate::error::Result;
+use crate::history_store::{History, HistoryKind};
 use crate::input::{InputState, Key};
+use crate::layout::{Layout, Rect, SplitKind};
+use crate::line::char_width;
+use crate::marker::MarkerId;
+use crate::recent_store::RecentFile;
+use crate::register::{Register, RegisterCommand, SavedWindow, WindowLayout};
 use crate::syntax::SyntaxManager;
-use crate::terminal::Terminal;
+use crate::terminal::{InputEvent, Terminal};
+use crate::timer::{Schedule, TimerId, Timers};
 use crate::window::Window;
 
 /// Main editor state - consolidates all globals from C version
 pub struct EditorState {
     /// All open buffers
     pub buffers: Vec<Buffer>,
-    /// All windows
+    /// All windows, in screen order
     pub windows: Vec<Window>,
+    /// How the screen is divided between the windows
+    pub layout: Layout,
+    /// Screen area the layout was last placed in
+    layout_area: Rect,
     /// Index of current window
     pub current_window: usize,
+    /// Window that was current when the window points were last synced
+    synced_window: usize,
     /// Terminal interface
     pub terminal: Terminal,
     /// Display state
@@ -36,16 +52,45 @@ pub struct EditorState {
     pub kill_ring_idx: usize,
     /// Track consecutive kills for appending
     pub last_was_kill: bool,
-    /// Track last yank position for yank-pop
-    pub last_yank_start: Option<(usize, usize)>,
-    pub last_yank_end: Option<(usize, usize)>,
+    /// Whether the newest kill changed since it was last copied to the
+    /// system clipboard
+    pub kill_changed: bool,
+    /// What the held mouse button is dragging, if anything
+    mouse_drag: Option<MouseDrag>,
+    /// Track last yank position for yank-pop (markers in the yanked-into buffer)
+    pub last_yank_start: Option<MarkerId>,
+    pub last_yank_end: Option<MarkerId>,
     pub last_was_yank: bool,
+    /// Last rectangle killed or copied, one string per line
+    pub killed_rectangle: Vec<String>,
+    /// Track runs of typed characters so they undo as one group
+    pub last_was_self_insert: bool,
+    /// Buffer whose history is shown in *Undo Tree*
+    pub undo_tree_source: Option<String>,
+    /// Extra cursors for multiple-cursor editing (the window cursor is the main one)
+    pub cursors: Vec<ExtraCursor>,
     /// Waiting for literal character (C-q)
     pub quote_pending: bool,
+    /// Registers by name (C-x r)
+    pub registers: std::collections::BTreeMap<char, Register>,
+    /// Register command waiting for the register name
+    pub register_pending: Option<RegisterCommand>,
+    /// Bookmarks, sorted by name (saved to ~/.uemacs-bookmarks)
+    pub bookmarks: Vec<crate::bookmark_store::Bookmark>,
+    /// Files opened lately and the cursor's place in each, most recent
+    /// first (saved to ~/.uemacs-recent)
+    pub recent_files: Vec<RecentFile>,
+    /// Earlier prompt inputs, by kind of prompt (saved to ~/.uemacs-history)
+    pub history: History,
+    /// Positions in different buffers to return to (C-x C-SPC), oldest first
+    pub global_mark_ring: Vec<MarkerId>,
     /// Incremental search state
     pub search: SearchState,
     /// Minibuffer prompt state
     pub prompt: PromptState,
+    /// Window split off to list a prompt's completions, removed when the
+    /// prompt ends
+    completions_window: Option<usize>,
     /// Query-replace state
     pub query_replace: QueryReplaceState,
     /// Keyboard macro state
@@ -54,18 +99,28 @@ pub struct EditorState {
     pub prefix_arg: PrefixArg,
     /// Stored region for filter operation
     pub filter_region: Option<(usize, usize, usize, usize)>,
-    /// Auto-save: last time we auto-saved
-    pub last_auto_save: Instant,
     /// Auto-save: interval between saves (default 30 seconds)
     pub auto_save_interval: Duration,
     /// Auto-save: whether enabled
     pub auto_save_enabled: bool,
+    /// Tasks run between keys (auto-save, message timeout)
+    pub timers: Timers<TimerFn>,
+    /// Timer running the auto-save check
+    auto_save_timer: TimerId,
+    /// Timer clearing the message
+    message_timer: TimerId,
     /// Whether to warn before closing unsaved buffers
     pub warn_unsaved: bool,
     /// Pending quit (waiting for confirmation after unsaved warning)
     pub pending_quit: bool,
     /// Syntax highlighting manager
     pub syntax: SyntaxManager,
+    /// Whether undo history is kept across sessions
+    pub persistent_undo: bool,
+    /// Whether the desktop is saved on exit
+    pub desktop: bool,
+    /// Whether kills are copied to the system clipboard (OSC 52)
+    pub osc52_clipboard: bool,
 }
 
 /// Universal argument state for C-u prefix
@@ -100,6 +155,43 @@ pub enum PromptAction {
     ExtendedCommand,      // M-x: execute command by name
     ConfirmQuit,          // Confirm quit with unsaved buffers
     ConfirmKillBuffer,    // Confirm kill buffer with unsaved changes
+    SetLineEnding,        // Convert buffer line endings (lf/crlf/cr)
+    UndoTreeGoto,         // Jump to a state number in the undo tree
+    ReopenWithEncoding,   // Reload buffer's file in a chosen encoding
+    StringRectangle,      // Replace each line of the rectangle with a string
+    BookmarkSet,          // Set a bookmark at point
+    BookmarkJump,         // Jump to a bookmark
+    BookmarkRename,       // First prompt: bookmark to rename
+    BookmarkRenameTo(String), // Second prompt: new name for the bookmark
+    BookmarkDelete,       // Delete a bookmark
+    RecentFile,           // Open a recently opened file
+}
+
+impl PromptAction {
+    /// Whether the prompt reads a file name, completed against the
+    /// filesystem
+    pub fn reads_file_name(&self) -> bool {
+        matches!(self, PromptAction::FindFile | PromptAction::InsertFile | PromptAction::WriteFile)
+    }
+
+    /// History list the prompt's inputs are kept in, if any
+    pub fn history(&self) -> Option<HistoryKind> {
+        match self {
+            PromptAction::FindFile
+            | PromptAction::InsertFile
+            | PromptAction::WriteFile
+            | PromptAction::RecentFile => Some(HistoryKind::File),
+            PromptAction::SwitchBuffer | PromptAction::KillBuffer => Some(HistoryKind::Buffer),
+            PromptAction::ShellCommand
+            | PromptAction::FilterBuffer
+            | PromptAction::FilterRegion
+            | PromptAction::FilterRegionReplace => Some(HistoryKind::Shell),
+            PromptAction::ExtendedCommand => Some(HistoryKind::Command),
+            PromptAction::QueryReplaceSearch | PromptAction::ReplaceStringSearch => Some(HistoryKind::Search),
+            PromptAction::QueryReplaceReplace | PromptAction::ReplaceStringReplace => Some(HistoryKind::Replace),
+            _ => None,
+        }
+    }
 }
 
 /// Minibuffer prompt state
@@ -111,10 +203,19 @@ pub struct PromptState {
     pub prompt: String,
     /// Current input
     pub input: String,
+    /// Cursor in the input (byte offset)
+    pub cursor: usize,
+    /// Waiting for a character to insert literally (C-q)
+    pub quoting: bool,
     /// What to do when complete
     pub action: PromptAction,
     /// Default value (shown in prompt)
     pub default: Option<String>,
+    /// Entry of the prompt's history shown, while stepping through it
+    pub history_pos: Option<usize>,
+    /// Input typed before stepping into the history, shown again after
+    /// stepping past the newest entry
+    pub typed: String,
 }
 
 impl Default for PromptState {
@@ -123,8 +224,12 @@ impl Default for PromptState {
             active: false,
             prompt: String::new(),
             input: String::new(),
+            cursor: 0,
+            quoting: false,
             action: PromptAction::None,
             default: None,
+            history_pos: None,
+            typed: String::new(),
         }
     }
 }
@@ -207,6 +312,41 @@ pub struct MacroState {
     pub slots: [Vec<Key>; 10],
 }
 
+/// An additional cursor for multiple-cursor editing
+#[derive(Debug, Clone)]
+pub struct ExtraCursor {
+    /// Position, as a marker in the current buffer
+    pub marker: MarkerId,
+    /// Goal column for vertical movement
+    pub goal_col: usize,
+    /// Kill ring of this cursor, so each one yanks what it killed; it
+    /// starts with just the newest kill
+    pub kill_ring: Vec<String>,
+}
+
+/// What a mouse drag acts on
+#[derive(Debug, Clone, Copy)]
+enum MouseDrag {
+    /// The region, from the position where the button went down
+    Region(usize, usize),
+    /// The mode line of a window, resizing it and the windows below
+    ModeLine(usize),
+    /// The divider right of a window, resizing it and the windows right
+    Divider(usize),
+}
+
+/// Lines scrolled by one turn of the mouse wheel
+const MOUSE_SCROLL_LINES: usize = 3;
+
+/// Callback run by a timer
+pub type TimerFn = fn(&mut EditorState);
+
+/// How long a message stays in the minibuffer while no keys are typed
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Buffer listing a prompt's possible completions
+const COMPLETIONS_BUFFER: &str = "*Completions*";
+
 impl EditorState {
     /// Create a new editor state
     pub fn new(terminal: Terminal) -> Self {
@@ -217,12 +357,24 @@ impl EditorState {
         let height = terminal.rows().saturating_sub(2);
 
         // Create initial window
-        let window = Window::new(0, 0, height);
+        let mut window = Window::new(0, 0, height);
+        window.set_width(terminal.cols());
+        let layout_area = Rect { top: 0, left: 0, height: height + 1, width: terminal.cols() };
+
+        // Background tasks
+        let now = Instant::now();
+        let auto_save_interval = Duration::from_secs(30);
+        let mut timers: Timers<TimerFn> = Timers::new();
+        let auto_save_timer = timers.add(Schedule::Every(auto_save_interval), Self::check_auto_save, now);
+        let message_timer = timers.add(Schedule::Idle(MESSAGE_TIMEOUT), Self::expire_message, now);
 
         Self {
             buffers: vec![buffer],
             windows: vec![window],
+            layout: Layout::default(),
+            layout_area,
             current_window: 0,
+            synced_window: 0,
             terminal,
             display: Display::new(),
             input: InputState::new(),
@@ -231,22 +383,40 @@ impl EditorState {
             kill_ring: Vec::new(),
             kill_ring_idx: 0,
             last_was_kill: false,
+            kill_changed: false,
+            mouse_drag: None,
             last_yank_start: None,
             last_yank_end: None,
             last_was_yank: false,
+            killed_rectangle: Vec::new(),
+            last_was_self_insert: false,
+            undo_tree_source: None,
+            cursors: Vec::new(),
             quote_pending: false,
+            registers: std::collections::BTreeMap::new(),
+            register_pending: None,
+            bookmarks: Vec::new(),
+            recent_files: Vec::new(),
+            history: History::default(),
+            global_mark_ring: Vec::new(),
             search: SearchState::default(),
             prompt: PromptState::default(),
+            completions_window: None,
             query_replace: QueryReplaceState::default(),
             macro_state: MacroState::default(),
             prefix_arg: PrefixArg::default(),
             filter_region: None,
-            last_auto_save: Instant::now(),
-            auto_save_interval: Duration::from_secs(30),
+            auto_save_interval,
             auto_save_enabled: true,
+            timers,
+            auto_save_timer,
+            message_timer,
             warn_unsaved: true,
             pending_quit: false,
             syntax: SyntaxManager::new(),
+            persistent_undo: false,
+            desktop: false,
+            osc52_clipboard: true,
         }
     }
 
@@ -254,10 +424,17 @@ impl EditorState {
     pub fn apply_config(&mut self, config: &crate::config::Config) {
         // Display settings
         self.display.show_line_numbers = config.show_line_numbers;
+        self.display.tab_width = config.tab_width;
 
         // Auto-save settings
         self.auto_save_enabled = config.auto_save;
         self.auto_save_interval = std::time::Duration::from_secs(config.auto_save_interval);
+        self.timers.remove(self.auto_save_timer);
+        self.auto_save_timer = self.timers.add(
+            Schedule::Every(self.auto_save_interval),
+            Self::check_auto_save,
+            Instant::now(),
+        );
 
         // Warning settings
         self.warn_unsaved = config.warn_unsaved;
@@ -265,11 +442,24 @@ impl EditorState {
         // Syntax highlighting settings
         self.syntax.enabled = config.syntax_highlighting;
 
+        // Undo history settings
+        self.persistent_undo = config.persistent_undo;
+
+        // Desktop settings
+        self.desktop = config.desktop;
+
+        // Clipboard settings
+        self.osc52_clipboard = config.osc52_clipboard;
+
+        // Mouse settings (a terminal that can't capture the mouse just
+        // never sends mouse events)
+        let _ = self.terminal.set_mouse_capture(config.mouse);
+
         // Load saved macros from disk
         self.load_macros_on_startup();
-
-        // Tab width is stored in Line, but we don't have a global tab width setting yet
-        // This could be added in the future
+        self.bookmarks = crate::bookmark_store::load_bookmarks();
+        self.recent_files = crate::recent_store::load_recent();
+        self.history = crate::history_store::load_history();
     }
 
     /// Load macros from the macros file at startup
@@ -281,6 +471,26 @@ impl EditorState {
         }
     }
 
+    /// Show a file in the current window, reusing a buffer already visiting it
+    pub fn visit_file(&mut self, path: &PathBuf) -> Result<()> {
+        let canonical = |p: &PathBuf| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone());
+        let target = canonical(path);
+        let existing = self
+            .buffers
+            .iter()
+            .position(|b| b.filename().map(canonical).as_ref() == Some(&target));
+        match existing {
+            Some(idx) => {
+                if self.current_window().buffer_idx() != idx {
+                    self.current_window_mut().set_buffer_idx(idx);
+                    self.display.force_redraw();
+                }
+                Ok(())
+            }
+            None => self.open_file(path),
+        }
+    }
+
     /// Open a file in a new buffer
     pub fn open_file(&mut self, path: &PathBuf) -> Result<()> {
         let buffer = Buffer::from_file(path)?;
@@ -296,9 +506,114 @@ impl EditorState {
         }
 
         self.display.force_redraw();
+        self.restore_undo_history(buf_idx);
+        self.restore_place(buf_idx);
         Ok(())
     }
 
+    /// Put the cursor back where it was when a freshly opened buffer's
+    /// file was last closed, and move the file to the front of the recent
+    /// files list
+    fn restore_place(&mut self, buf_idx: usize) {
+        let file = match self.buffers[buf_idx].filename() {
+            Some(path) => std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()),
+            None => return,
+        };
+        let entry = match crate::recent_store::find(&self.recent_files, &file) {
+            Some(saved) => {
+                let (line, col) = self.buffers[buf_idx].clamp_position(saved.line, saved.col);
+                if self.current_window().buffer_idx() == buf_idx {
+                    self.current_window_mut().set_cursor(line, col);
+                    self.ensure_cursor_visible();
+                }
+                RecentFile { file, line, col }
+            }
+            None => RecentFile { file, line: 0, col: 0 },
+        };
+        crate::recent_store::record(&mut self.recent_files, entry);
+        let _ = crate::recent_store::save_recent(&self.recent_files);
+    }
+
+    /// Record the cursor's place in a buffer's file in the recent files
+    /// list, from the current window if it shows the buffer, otherwise the
+    /// first window that does
+    ///
+    /// Returns false if the buffer has no file or no window shows it. The
+    /// list isn't saved.
+    fn remember_place(&mut self, buf_idx: usize) -> bool {
+        let file = match self.buffers.get(buf_idx).and_then(|b| b.filename()) {
+            Some(path) => std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()),
+            None => return false,
+        };
+        let window = Some(self.current_window())
+            .filter(|w| w.buffer_idx() == buf_idx)
+            .or_else(|| self.windows.iter().find(|w| w.buffer_idx() == buf_idx));
+        let (line, col) = match window {
+            Some(window) => (window.cursor_line(), window.cursor_col()),
+            None => return false,
+        };
+        crate::recent_store::record(&mut self.recent_files, RecentFile { file, line, col });
+        true
+    }
+
+    /// Restore a freshly opened buffer's undo history from disk
+    ///
+    /// If there is no matching history, the buffer's content hash is
+    /// recorded so the history can be written later.
+    fn restore_undo_history(&mut self, buf_idx: usize) {
+        if !self.persistent_undo {
+            return;
+        }
+        let buffer = &mut self.buffers[buf_idx];
+        let path = match buffer.filename() {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let hash = crate::undo_store::content_hash(buffer.format(), buffer.terminated_lines());
+        match crate::undo_store::load_history(&path, hash) {
+            Some(tree) => {
+                buffer.set_undo_tree(tree);
+                self.display.set_message("Restored undo history");
+            }
+            None => buffer.undo_tree_mut().mark_saved(hash),
+        }
+    }
+
+    /// Record that a buffer was just written to its file
+    ///
+    /// Marks the current undo state as matching the file and writes the
+    /// history to disk.
+    pub fn note_buffer_saved(&mut self, buf_idx: usize) {
+        if !self.persistent_undo {
+            return;
+        }
+        let buffer = &mut self.buffers[buf_idx];
+        let hash = crate::undo_store::content_hash(buffer.format(), buffer.terminated_lines());
+        buffer.undo_tree_mut().mark_saved(hash);
+        self.persist_undo_history(buf_idx);
+    }
+
+    /// Write a buffer's undo history to disk
+    ///
+    /// Only buffers whose saved state is known are written, so the history
+    /// always matches what is on disk.
+    fn persist_undo_history(&mut self, buf_idx: usize) {
+        if !self.persistent_undo {
+            return;
+        }
+        let buffer = &mut self.buffers[buf_idx];
+        let path = match buffer.filename() {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        if buffer.undo_tree().saved_hash().is_none() {
+            return;
+        }
+        if let Err(e) = crate::undo_store::save_history(&path, buffer.undo_tree_mut()) {
+            self.display.set_message(format!("Error saving undo history: {}", e));
+        }
+    }
+
     /// Create a new buffer for a file that doesn't exist yet
     pub fn open_new_file(&mut self, path: &PathBuf) {
         let name = path
@@ -333,6 +648,11 @@ impl EditorState {
         &mut self.windows[self.current_window]
     }
 
+    /// Columns between tab stops
+    pub fn tab_width(&self) -> usize {
+        self.display.tab_width
+    }
+
     /// Get current buffer
     pub fn current_buffer(&self) -> &Buffer {
         let buf_idx = self.windows[self.current_window].buffer_idx();
@@ -358,7 +678,14 @@ impl EditorState {
         let mut input_state = InputState::new();
 
         loop {
-            let key_event = self.terminal.read_key()?;
+            let key_event = match self.terminal.read_key()? {
+                InputEvent::Key(key_event) => key_event,
+                InputEvent::Resize => {
+                    self.apply_layout();
+                    continue;
+                }
+                InputEvent::Paste(_) | InputEvent::Mouse(_) => continue,
+            };
 
             // Check for C-g (abort)
             if let crossterm::event::KeyCode::Char('g') = key_event.code {
@@ -373,7 +700,9 @@ impl EditorState {
 
             // Show visual feedback for pending prefix
             if input_state.is_pending() {
-                if input_state.is_ctlx_pending() {
+                if input_state.is_ctlx_r_pending() {
+                    self.display.set_message("Describe key: C-x r -");
+                } else if input_state.is_ctlx_pending() {
                     self.display.set_message("Describe key: C-x -");
                 } else if input_state.is_meta_pending() {
                     self.display.set_message("Describe key: ESC -");
@@ -394,6 +723,16 @@ impl EditorState {
         self.display.force_redraw();
 
         while self.running {
+            self.display.extra_cursors = self.cursor_positions();
+            if !self.prompt.active {
+                self.display.minibuffer_cursor = None;
+            }
+            self.sync_window_points();
+            self.clamp_to_narrowing();
+            for idx in 0..self.windows.len() {
+                self.ensure_window_cursor_visible(idx);
+            }
+
             // Render display
             self.display.render(
                 &mut self.terminal,
@@ -403,17 +742,43 @@ impl EditorState {
                 &mut self.syntax,
             )?;
 
+            // Wait for input, waking up when a timer is due
+            if let Some(deadline) = self.timers.next_deadline() {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if !self.terminal.poll_key(timeout)? {
+                    self.run_timers();
+                    continue;
+                }
+            }
+
             // Read and handle input
-            let key_event = self.terminal.read_key()?;
+            let event = self.terminal.read_key()?;
+            self.timers.input(Instant::now());
+            let key_event = match event {
+                InputEvent::Key(key_event) => key_event,
+                InputEvent::Paste(text) => {
+                    self.handle_paste(&text)?;
+                    continue;
+                }
+                InputEvent::Mouse(mouse_event) => {
+                    self.handle_mouse(mouse_event);
+                    continue;
+                }
+                InputEvent::Resize => {
+                    self.apply_layout();
+                    continue;
+                }
+            };
 
             // Translate key event
             if let Some(key) = self.input.translate_key(key_event) {
                 self.handle_key(key)?;
-                // Check if it's time to auto-save
-                self.check_auto_save();
+                self.sync_clipboard()?;
             } else if self.input.is_pending() {
                 // Show visual feedback that we're waiting for continuation key
-                if self.input.is_ctlx_pending() {
+                if self.input.is_ctlx_r_pending() {
+                    self.display.set_message("C-x r -");
+                } else if self.input.is_ctlx_pending() {
                     self.display.set_message("C-x -");
                 } else if self.input.is_meta_pending() {
                     self.display.set_message("ESC -");
@@ -422,10 +787,328 @@ impl EditorState {
             // Debug: if key event was ignored (not Press), we just continue the loop
         }
 
+        for idx in 0..self.buffers.len() {
+            self.persist_undo_history(idx);
+        }
+        self.sync_window_points();
+        // Record places with the current buffer last, so it's the most recent
+        let current = self.current_window().buffer_idx();
+        for idx in (0..self.buffers.len()).filter(|&idx| idx != current).chain([current]) {
+            self.remember_place(idx);
+        }
+        if !self.recent_files.is_empty() {
+            let _ = crate::recent_store::save_recent(&self.recent_files);
+        }
+        if !self.history.is_empty() {
+            let _ = crate::history_store::save_history(&self.history);
+        }
+        if self.desktop {
+            let _ = crate::desktop_store::save_desktop(&self.desktop_state());
+        }
+
+        Ok(())
+    }
+
+    /// Bring each window's cursor and point marker into step
+    ///
+    /// Edits move the point markers of every window showing a buffer,
+    /// while commands move the cursors. A cursor that moved since the last
+    /// sync was placed by a command, so its marker follows it; otherwise
+    /// the cursor goes where edits carried the marker. The current window
+    /// is the one edited through, so its cursor always wins.
+    fn sync_window_points(&mut self) {
+        for idx in 0..self.windows.len() {
+            let window = &mut self.windows[idx];
+            for id in window.take_released() {
+                for buffer in &mut self.buffers {
+                    buffer.remove_marker(id);
+                }
+            }
+            let buffer = &mut self.buffers[window.buffer_idx()];
+            let cursor = (window.cursor_line(), window.cursor_col());
+            let edited_through = idx == self.current_window && idx == self.synced_window;
+            match window.point_marker().and_then(|id| buffer.marker(id).map(|pos| (id, pos))) {
+                Some((id, pos)) if !edited_through && cursor == window.point_synced() => {
+                    window.set_cursor(pos.0, pos.1);
+                    window.set_point_marker(id, pos);
+                }
+                Some((id, _)) => {
+                    buffer.set_marker(id, cursor.0, cursor.1);
+                    window.set_point_marker(id, cursor);
+                }
+                None => {
+                    let id = buffer.add_marker(cursor.0, cursor.1);
+                    window.set_point_marker(id, cursor);
+                }
+            }
+        }
+        self.synced_window = self.current_window;
+    }
+
+    /// Make a window the current one, with its cursor where edits made
+    /// through the previous one left it
+    pub fn select_window(&mut self, idx: usize) {
+        self.sync_window_points();
+        self.current_window = idx;
+    }
+
+    /// Unregister the markers of a window that is going away
+    fn release_window(&mut self, mut window: Window) {
+        for id in window.take_markers() {
+            for buffer in &mut self.buffers {
+                buffer.remove_marker(id);
+            }
+        }
+    }
+
+    /// Mark of a window, where edits have carried it
+    pub fn window_mark(&self, idx: usize) -> Option<(usize, usize)> {
+        let window = self.windows.get(idx)?;
+        self.buffers[window.buffer_idx()].marker(window.mark_marker()?)
+    }
+
+    /// Set a window's mark
+    pub fn set_window_mark(&mut self, idx: usize, line: usize, col: usize) {
+        let window = &mut self.windows[idx];
+        let buffer = &mut self.buffers[window.buffer_idx()];
+        match window.mark_marker() {
+            Some(id) if buffer.set_marker(id, line, col) => {}
+            _ => window.set_mark_marker(buffer.add_marker(line, col)),
+        }
+    }
+
+    /// Clear a window's mark
+    pub fn clear_window_mark(&mut self, idx: usize) {
+        let window = &mut self.windows[idx];
+        if let Some(id) = window.take_mark() {
+            self.buffers[window.buffer_idx()].remove_marker(id);
+        }
+    }
+
+    /// Mark of the current window
+    pub fn mark(&self) -> Option<(usize, usize)> {
+        self.window_mark(self.current_window)
+    }
+
+    /// Set the current window's mark at a position
+    pub fn set_mark_at(&mut self, line: usize, col: usize) {
+        self.set_window_mark(self.current_window, line, col);
+    }
+
+    /// Set the current window's mark at the cursor
+    pub fn set_mark(&mut self) {
+        let (line, col) = (self.current_window().cursor_line(), self.current_window().cursor_col());
+        self.set_mark_at(line, col);
+    }
+
+    /// Clear the current window's mark
+    pub fn clear_mark(&mut self) {
+        self.clear_window_mark(self.current_window);
+    }
+
+    /// Move window cursors, marks and views into their buffers' narrowings
+    fn clamp_to_narrowing(&mut self) {
+        for idx in 0..self.windows.len() {
+            if !self.buffers[self.windows[idx].buffer_idx()].is_narrowed() {
+                continue;
+            }
+            if let Some(mark) = self.window_mark(idx) {
+                let buffer = &self.buffers[self.windows[idx].buffer_idx()];
+                let clamped = buffer.clamp_visible(mark.0, mark.1);
+                if clamped != mark {
+                    self.set_window_mark(idx, clamped.0, clamped.1);
+                }
+            }
+            let window = &mut self.windows[idx];
+            let buffer = &self.buffers[window.buffer_idx()];
+            let cursor = (window.cursor_line(), window.cursor_col());
+            let clamped = buffer.clamp_visible(cursor.0, cursor.1);
+            if clamped != cursor {
+                window.set_cursor(clamped.0, clamped.1);
+            }
+            let first_line = buffer.visible_start().0;
+            if window.top_line() < first_line {
+                window.set_top_line(first_line);
+            }
+            window.ensure_cursor_visible();
+        }
+    }
+
+    /// Handle text pasted into the terminal
+    ///
+    /// In the buffer the text goes in as one block and one undo step,
+    /// without the auto-indent or per-key work that typing it would do.
+    /// The minibuffer, search and other modes reading single keys get the
+    /// first line typed in.
+    fn handle_paste(&mut self, text: &str) -> Result<()> {
+        // Terminals send line ends as CR
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.input.reset();
+        let reads_keys = self.prompt.active
+            || self.search.active
+            || self.query_replace.active
+            || self.quote_pending
+            || self.register_pending.is_some();
+        if reads_keys {
+            for ch in text.chars().take_while(|&ch| ch != '\n') {
+                self.handle_key(Key::char(ch))?;
+            }
+            return Ok(());
+        }
+
+        self.prefix_arg = PrefixArg::default();
+        self.display.clear_message();
+        self.reset_command_flags();
+        self.current_buffer_mut().add_undo_boundary();
+
+        let line = self.current_window().cursor_line();
+        let col = self.current_window().cursor_col();
+        let end = self.insert_text_at(line, col, &text);
+        self.current_window_mut().set_cursor(end.0, end.1);
+        self.invalidate_syntax_from(line);
+        self.ensure_cursor_visible();
+        self.display.force_redraw();
         Ok(())
     }
 
-    /// Handle a key press
+    /// Handle a mouse event
+    ///
+    /// A click places point in the window under the pointer and selects
+    /// that window, dragging sets the region, and dragging a mode line or
+    /// divider resizes the windows on either side of it. The wheel scrolls
+    /// the window under the pointer. The mouse is ignored while the
+    /// minibuffer or a search is reading keys.
+    fn handle_mouse(&mut self, event: MouseEvent) {
+        if self.prompt.active || self.search.active || self.query_replace.active {
+            return;
+        }
+        let (row, col) = (event.row, event.column);
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.mouse_drag = None;
+                let idx = match self.window_at(row, col) {
+                    Some(idx) => idx,
+                    None => return,
+                };
+                let window = &self.windows[idx];
+                if col == window.screen_col() + window.width() {
+                    self.mouse_drag = Some(MouseDrag::Divider(idx));
+                    return;
+                }
+                if row == window.top_row() + window.height() {
+                    self.mouse_drag = Some(MouseDrag::ModeLine(idx));
+                    return;
+                }
+                self.select_window(idx);
+                let (line, col) = self.mouse_position(row, col);
+                self.current_window_mut().set_cursor(line, col);
+                self.reset_command_flags();
+                self.mouse_drag = Some(MouseDrag::Region(line, col));
+            }
+            MouseEventKind::Drag(MouseButton::Left) => match self.mouse_drag {
+                Some(MouseDrag::Region(start_line, start_col)) => {
+                    if self.mark() != Some((start_line, start_col)) {
+                        if let Some((mark_line, mark_col)) = self.mark() {
+                            self.push_mark(mark_line, mark_col);
+                        }
+                        self.set_mark_at(start_line, start_col);
+                    }
+                    let (line, col) = self.mouse_position(row, col);
+                    self.current_window_mut().set_cursor(line, col);
+                }
+                Some(MouseDrag::ModeLine(idx)) => self.drag_edge(idx, SplitKind::Stacked, row),
+                Some(MouseDrag::Divider(idx)) => self.drag_edge(idx, SplitKind::SideBySide, col),
+                None => {}
+            },
+            MouseEventKind::Up(_) => self.mouse_drag = None,
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                if let Some(idx) = self.window_at(row, col) {
+                    self.scroll_window(idx, event.kind == MouseEventKind::ScrollDown);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Helper: window whose text, mode line or right divider includes a
+    /// screen cell
+    fn window_at(&self, row: u16, col: u16) -> Option<usize> {
+        self.windows.iter().position(|w| {
+            !w.is_hidden()
+                && row >= w.top_row()
+                && row <= w.top_row() + w.height()
+                && col >= w.screen_col()
+                && col <= w.screen_col() + w.width()
+        })
+    }
+
+    /// Helper: buffer position under a screen cell of the current window
+    fn mouse_position(&self, row: u16, col: u16) -> (usize, usize) {
+        let window = self.current_window();
+        let buffer = &self.buffers[window.buffer_idx()];
+        let cols = window.width() as usize;
+        self.display.screen_to_buffer(window, buffer, cols, row, col)
+    }
+
+    /// Helper: forget the command history that links one command to the
+    /// next (kill appending, yank-pop, undo grouping of typed text)
+    fn reset_command_flags(&mut self) {
+        self.last_was_kill = false;
+        self.last_was_yank = false;
+        self.last_was_self_insert = false;
+    }
+
+    /// Helper: move the mode line (stacked) or divider (side by side) of
+    /// window `idx` to a screen row or column, resizing the windows on
+    /// either side
+    fn drag_edge(&mut self, idx: usize, kind: SplitKind, pos: u16) {
+        let window = &self.windows[idx];
+        let edge = match kind {
+            SplitKind::Stacked => window.top_row() + window.height(),
+            SplitKind::SideBySide => window.screen_col() + window.width(),
+        };
+        let delta = i32::from(pos) - i32::from(edge);
+        let area = self.layout_area;
+        if delta != 0 && self.layout.move_edge(idx, kind, delta, area) {
+            self.apply_layout();
+        }
+    }
+
+    /// Helper: scroll window `idx` a few lines, keeping its cursor in view
+    fn scroll_window(&mut self, idx: usize, down: bool) {
+        let cols = self.windows[idx].width() as usize;
+        let window = &mut self.windows[idx];
+        let buffer = &self.buffers[window.buffer_idx()];
+        let display = &self.display;
+        let rows = |line| display.line_rows(buffer, line, cols).len();
+        let first_line = buffer.visible_start().0;
+        let last_line = buffer.visible_end().0;
+
+        let top = if down {
+            (window.top_line() + MOUSE_SCROLL_LINES).min(last_line)
+        } else {
+            window.top_line().saturating_sub(MOUSE_SCROLL_LINES).max(first_line)
+        };
+        window.set_top_line(top);
+
+        // Keep the cursor's display column when it has to move
+        let bottom = window.bottom_line(rows).min(last_line);
+        let line = window.cursor_line().clamp(top, bottom);
+        if line != window.cursor_line() {
+            let goal = buffer
+                .line(window.cursor_line())
+                .map(|l| l.byte_to_col(window.cursor_col(), display.tab_width))
+                .unwrap_or(0);
+            let col = buffer
+                .line(line)
+                .map(|l| l.col_to_byte(goal, display.tab_width).unwrap_or(l.len()))
+                .unwrap_or(0);
+            let (line, col) = buffer.clamp_visible(line, col);
+            window.set_cursor(line, col);
+        }
+    }
+
+    /// Process a key: prompts, prefix arguments, then the bound command
     fn handle_key(&mut self, key: Key) -> Result<()> {
         // Handle prompt mode
         if self.prompt.active {
@@ -466,6 +1149,23 @@ impl EditorState {
             return Ok(());
         }
 
+        // Handle register commands - the next key names the register
+        if let Some(command) = self.register_pending.take() {
+            self.prefix_arg = PrefixArg::default();
+            if key == Key::ctrl('g') {
+                self.display.set_message("Quit");
+            } else if let Some(name) = key.base_char().filter(|_| key.is_self_insert()) {
+                if should_record {
+                    self.macro_state.keys.push(key);
+                }
+                crate::command::run_register_command(self, command, name);
+            } else {
+                self.terminal.beep()?;
+                self.display.set_message("Invalid register");
+            }
+            return Ok(());
+        }
+
         // Handle C-u (universal argument)
         if key == Key::ctrl('u') {
             if self.prefix_arg.active {
@@ -562,10 +1262,29 @@ impl EditorState {
         self.last_was_kill = false;
         self.last_was_yank = false;
 
+        // Each command is its own undo group; a run of typed characters is one
+        let cmd = self.keytab.lookup(key);
+        let is_self_insert = cmd.is_none() && key.is_self_insert();
+        if !(is_self_insert && self.last_was_self_insert) {
+            self.current_buffer_mut().add_undo_boundary();
+        }
+        self.last_was_self_insert = is_self_insert;
+
+        // Commands that edit or move run at every cursor
+        self.prune_cursors();
+        let at_every_cursor = !self.cursors.is_empty()
+            && (is_self_insert
+                || self.keytab.lookup_name(key).is_some_and(crate::command::runs_at_every_cursor));
+
         // Look up command
-        if let Some(cmd) = self.keytab.lookup(key) {
+        if let Some(cmd) = cmd {
             // Execute command with prefix argument
-            match cmd(self, has_arg, arg_value)? {
+            let status = if at_every_cursor {
+                self.run_at_cursors(|editor| cmd(editor, has_arg, arg_value))?
+            } else {
+                cmd(self, has_arg, arg_value)?
+            };
+            match status {
                 CommandStatus::Success => {
                     // Record successful command keys for macro
                     // (macro control commands will clear this themselves)
@@ -585,8 +1304,16 @@ impl EditorState {
             // Self-insert character (possibly multiple times with prefix)
             if let Some(ch) = key.base_char() {
                 let count = if has_arg { arg_value.max(1) } else { 1 };
-                for _ in 0..count {
-                    self.insert_char(ch);
+                let insert = |editor: &mut Self| {
+                    for _ in 0..count {
+                        editor.insert_char(ch);
+                    }
+                    Ok(CommandStatus::Success)
+                };
+                if at_every_cursor {
+                    self.run_at_cursors(insert)?;
+                } else {
+                    insert(self)?;
                 }
             }
             // Record self-insert keys for macro
@@ -602,6 +1329,128 @@ impl EditorState {
         Ok(())
     }
 
+    /// Add an extra cursor, returns false if there is already one there
+    pub fn add_cursor(&mut self, line: usize, col: usize) -> bool {
+        let window = self.current_window();
+        if (window.cursor_line(), window.cursor_col()) == (line, col)
+            || self.cursor_positions().contains(&(line, col))
+        {
+            return false;
+        }
+        let kill_ring = self.yank_text().map(str::to_string).into_iter().collect();
+        let marker = self.current_buffer_mut().add_marker(line, col);
+        self.cursors.push(ExtraCursor {
+            marker,
+            goal_col: col,
+            kill_ring,
+        });
+        true
+    }
+
+    /// Remove all extra cursors
+    pub fn clear_cursors(&mut self) {
+        for cursor in std::mem::take(&mut self.cursors) {
+            for buffer in &mut self.buffers {
+                buffer.remove_marker(cursor.marker);
+            }
+        }
+    }
+
+    /// Positions of the extra cursors in the current buffer
+    pub fn cursor_positions(&self) -> Vec<(usize, usize)> {
+        let buffer = self.current_buffer();
+        self.cursors
+            .iter()
+            .filter_map(|cursor| buffer.marker(cursor.marker))
+            .collect()
+    }
+
+    /// Drop extra cursors that aren't in the current buffer, that are
+    /// outside its narrowing or that landed on the main cursor or on each
+    /// other
+    fn prune_cursors(&mut self) {
+        if self.cursors.is_empty() {
+            return;
+        }
+        let buf_idx = self.current_window().buffer_idx();
+        let main = (self.current_window().cursor_line(), self.current_window().cursor_col());
+        let mut seen = vec![main];
+        let mut dropped = Vec::new();
+        self.cursors.retain(|cursor| match self.buffers[buf_idx].marker(cursor.marker) {
+            Some(pos) if !seen.contains(&pos) && self.buffers[buf_idx].clamp_visible(pos.0, pos.1) == pos => {
+                seen.push(pos);
+                true
+            }
+            _ => {
+                dropped.push(cursor.marker);
+                false
+            }
+        });
+        for id in dropped {
+            for buffer in &mut self.buffers {
+                buffer.remove_marker(id);
+            }
+        }
+    }
+
+    /// Run an edit at every extra cursor, then at the main cursor
+    ///
+    /// All positions are markers while this runs, so an edit at one cursor
+    /// moves the others along with the text. Each cursor has its own kill
+    /// ring and goal column; the main cursor's view is left as it was.
+    fn run_at_cursors(
+        &mut self,
+        mut command: impl FnMut(&mut Self) -> Result<CommandStatus>,
+    ) -> Result<CommandStatus> {
+        let window = self.current_window();
+        let (line, col) = (window.cursor_line(), window.cursor_col());
+        let goal_col = window.goal_col();
+        let top_line = window.top_line();
+        let last_was_kill = self.last_was_kill;
+        let kill_ring_idx = self.kill_ring_idx;
+        let main = self.current_buffer_mut().add_marker(line, col);
+
+        let mut cursors = std::mem::take(&mut self.cursors);
+        let mut result = Ok(CommandStatus::Success);
+        for cursor in &mut cursors {
+            let (line, col) = match self.current_buffer().marker(cursor.marker) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            self.current_window_mut().set_cursor(line, col);
+            self.current_window_mut().set_goal_col(cursor.goal_col);
+            std::mem::swap(&mut self.kill_ring, &mut cursor.kill_ring);
+            self.kill_ring_idx = 0;
+            self.last_was_kill = last_was_kill;
+
+            result = command(self);
+
+            std::mem::swap(&mut self.kill_ring, &mut cursor.kill_ring);
+            let window = self.current_window();
+            let (line, col) = (window.cursor_line(), window.cursor_col());
+            cursor.goal_col = window.goal_col();
+            self.current_buffer_mut().set_marker(cursor.marker, line, col);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.cursors = cursors;
+
+        let (line, col) = self.current_buffer().marker(main).unwrap_or((line, col));
+        self.current_buffer_mut().remove_marker(main);
+        let window = self.current_window_mut();
+        window.set_cursor(line, col);
+        window.set_goal_col(goal_col);
+        window.set_top_line(top_line);
+        self.kill_ring_idx = kill_ring_idx;
+        self.last_was_kill = last_was_kill;
+        result?;
+
+        let status = command(self)?;
+        self.prune_cursors();
+        Ok(status)
+    }
+
     /// Insert a character at cursor
     pub fn insert_char(&mut self, ch: char) {
         let cursor_line = self.current_window().cursor_line();
@@ -678,6 +1527,9 @@ impl EditorState {
 
     /// Move cursor down
     pub fn move_cursor_down(&mut self) {
+        if self.move_cursor_by_row(true) {
+            return;
+        }
         let cursor_line = self.current_window().cursor_line();
         let goal_col = self.current_window().goal_col();
 
@@ -692,6 +1544,9 @@ impl EditorState {
 
     /// Move cursor up
     pub fn move_cursor_up(&mut self) {
+        if self.move_cursor_by_row(false) {
+            return;
+        }
         let cursor_line = self.current_window().cursor_line();
         let goal_col = self.current_window().goal_col();
 
@@ -722,21 +1577,25 @@ impl EditorState {
         self.current_window_mut().set_cursor(cursor_line, line_len);
 
         // Update goal column
+        let tab_width = self.tab_width();
         let display_col = self
             .current_buffer()
             .line(cursor_line)
-            .map(|l| l.display_width())
+            .map(|l| l.display_width(tab_width))
             .unwrap_or(0);
         self.current_window_mut().set_goal_col(display_col);
     }
 
     /// Page down
     pub fn page_down(&mut self) {
+        let cols = self.current_window().width() as usize;
         let height = self.current_window().height() as usize;
         let max_line = self.current_buffer().line_count();
-        let window = self.current_window_mut();
+        let window = &mut self.windows[self.current_window];
+        let buffer = &self.buffers[window.buffer_idx()];
+        let display = &self.display;
 
-        window.scroll_down(height.saturating_sub(2), max_line);
+        window.scroll_down_rows(height.saturating_sub(2), max_line, |line| display.line_rows(buffer, line, cols).len());
 
         // Move cursor to top of new view
         let new_cursor = window.top_line();
@@ -747,10 +1606,13 @@ impl EditorState {
 
     /// Page up
     pub fn page_up(&mut self) {
+        let cols = self.current_window().width() as usize;
         let height = self.current_window().height() as usize;
-        let window = self.current_window_mut();
+        let window = &mut self.windows[self.current_window];
+        let buffer = &self.buffers[window.buffer_idx()];
+        let display = &self.display;
 
-        window.scroll_up(height.saturating_sub(2));
+        window.scroll_up_rows(height.saturating_sub(2), |line| display.line_rows(buffer, line, cols).len());
 
         // Move cursor to top of new view
         let new_cursor = window.top_line();
@@ -759,21 +1621,17 @@ impl EditorState {
         self.current_window_mut().set_cursor(new_cursor, new_col);
     }
 
-    /// Move to beginning of buffer
+    /// Move to beginning of buffer (of the narrowed part, if narrowed)
     pub fn move_to_buffer_start(&mut self) {
-        self.current_window_mut().set_cursor(0, 0);
-        self.current_window_mut().set_top_line(0);
+        let (line, col) = self.current_buffer().visible_start();
+        self.current_window_mut().set_cursor(line, col);
+        self.current_window_mut().set_top_line(line);
         self.current_window_mut().set_goal_col(0);
     }
 
-    /// Move to end of buffer
+    /// Move to end of buffer (of the narrowed part, if narrowed)
     pub fn move_to_buffer_end(&mut self) {
-        let last_line = self.current_buffer().line_count().saturating_sub(1);
-        let last_col = self
-            .current_buffer()
-            .line(last_line)
-            .map(|l| l.len())
-            .unwrap_or(0);
+        let (last_line, last_col) = self.current_buffer().visible_end();
         self.current_window_mut().set_cursor(last_line, last_col);
         self.ensure_cursor_visible();
     }
@@ -845,14 +1703,15 @@ impl EditorState {
     /// Fill (reflow) the current paragraph to fill_column width
     pub fn fill_paragraph(&mut self, fill_column: usize) {
         let start_line = self.current_window().cursor_line();
-        let line_count = self.current_buffer().line_count();
+        let first_line = self.current_buffer().visible_start().0;
+        let last_line = self.current_buffer().visible_end().0;
 
-        // Find paragraph boundaries
+        // Find paragraph boundaries (inside the narrowing, if narrowed)
         let mut para_start = start_line;
         let mut para_end = start_line;
 
         // Find start of paragraph
-        while para_start > 0 && !self.is_blank_line(para_start - 1) {
+        while para_start > first_line && !self.is_blank_line(para_start - 1) {
             para_start -= 1;
         }
         // Skip if we're on a blank line
@@ -862,15 +1721,16 @@ impl EditorState {
         }
 
         // Find end of paragraph
-        while para_end < line_count && !self.is_blank_line(para_end) {
+        while para_end <= last_line && !self.is_blank_line(para_end) {
             para_end += 1;
         }
 
         // Collect all words from the paragraph
         let mut words: Vec<String> = Vec::new();
         for line_idx in para_start..para_end {
-            if let Some(line) = self.current_buffer().line(line_idx) {
-                for word in line.text().split_whitespace() {
+            let buffer = self.current_buffer();
+            if let (Some(line), Some((from, to))) = (buffer.line(line_idx), buffer.visible_span(line_idx)) {
+                for word in line.safe_slice(from, to).split_whitespace() {
                     words.push(word.to_string());
                 }
             }
@@ -899,41 +1759,34 @@ impl EditorState {
             new_lines.push(current_line);
         }
 
-        // Replace the paragraph lines
-        // First, delete the old paragraph lines (deleting from para_start repeatedly)
-        let lines_to_delete = para_end - para_start;
-        for _ in 0..lines_to_delete {
-            if para_start < self.current_buffer().line_count() {
-                self.current_buffer_mut().delete_line(para_start);
-            }
-        }
-
-        // Insert new lines at para_start
-        for (i, line_content) in new_lines.iter().enumerate() {
-            let line_idx = para_start + i;
-            // Always insert a new line (except if we're past the end and need to append)
-            if line_idx >= self.current_buffer().line_count() {
-                // Append at end
-                self.current_buffer_mut().append_line();
-            } else {
-                // Insert line at this position
-                self.current_buffer_mut().insert_line_at(line_idx);
-            }
-            // Set line content
-            if let Some(line) = self.current_buffer_mut().line_mut(line_idx) {
-                line.clear();
-                for ch in line_content.chars() {
-                    line.insert_char(line.len(), ch);
-                }
-            }
-        }
+        // Replace the paragraph's text with the new lines
+        let start_col = self.current_buffer().visible_span(para_start).map(|(from, _)| from).unwrap_or(0);
+        let end_line = para_end - 1;
+        let end_col = self.current_buffer().visible_span(end_line).map(|(_, to)| to).unwrap_or(0);
+        crate::command::delete_region(self, para_start, start_col, end_line, end_col);
+        self.insert_text_at(para_start, start_col, &new_lines.join("\n"));
 
-        self.current_buffer_mut().set_modified(true);
-        self.current_window_mut().set_cursor(para_start, 0);
+        self.current_window_mut().set_cursor(para_start, start_col);
         self.ensure_cursor_visible();
         self.display.set_message(&format!("Filled paragraph ({} lines)", new_lines.len()));
     }
 
+    /// Insert text that may span lines at a position in the current buffer,
+    /// returns the position after it
+    fn insert_text_at(&mut self, line: usize, col: usize, text: &str) -> (usize, usize) {
+        let (mut line, mut col) = (line, col);
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.current_buffer_mut().insert_newline(line, col);
+                line += 1;
+                col = 0;
+            }
+            self.current_buffer_mut().insert_str(line, col, part);
+            col += part.len();
+        }
+        (line, col)
+    }
+
     /// Show the current prefix argument in the message line
     fn show_prefix_arg(&mut self) {
         let msg = if let Some(value) = self.prefix_arg.value {
@@ -992,6 +1845,10 @@ impl EditorState {
                 self.display.set_message("Can't kill the only buffer");
                 return;
             }
+            self.persist_undo_history(idx);
+            if self.remember_place(idx) {
+                let _ = crate::recent_store::save_recent(&self.recent_files);
+            }
             self.buffers.remove(idx);
             // Update window buffer indices
             for window in &mut self.windows {
@@ -1022,6 +1879,7 @@ impl EditorState {
     pub fn kill_append(&mut self, text: &str) {
         if let Some(entry) = self.kill_ring.last_mut() {
             entry.push_str(text);
+            self.kill_changed = true;
         }
     }
 
@@ -1029,6 +1887,20 @@ impl EditorState {
     pub fn kill_prepend(&mut self, text: &str) {
         if let Some(entry) = self.kill_ring.last_mut() {
             entry.insert_str(0, text);
+            self.kill_changed = true;
+        }
+    }
+
+    /// Copy the newest kill to the system clipboard if it changed
+    fn sync_clipboard(&mut self) -> Result<()> {
+        if !std::mem::take(&mut self.kill_changed) || !self.osc52_clipboard {
+            return Ok(());
+        }
+        match self.kill_ring.last() {
+            Some(text) if !text.is_empty() && text.len() <= crate::clipboard::CLIPBOARD_MAX => {
+                self.terminal.set_clipboard(text)
+            }
+            _ => Ok(()),
         }
     }
 
@@ -1061,6 +1933,35 @@ impl EditorState {
         self.kill_ring_idx = 0;
     }
 
+    /// Remember the extent of yanked text in the current buffer
+    pub fn set_yank_region(&mut self, start: (usize, usize), end: (usize, usize)) {
+        // Reuse the markers if the last yank was in this buffer too
+        if let (Some(start_id), Some(end_id)) = (self.last_yank_start, self.last_yank_end) {
+            let buffer = self.current_buffer_mut();
+            if buffer.set_marker(start_id, start.0, start.1) && buffer.set_marker(end_id, end.0, end.1) {
+                return;
+            }
+        }
+        for id in self.last_yank_start.take().into_iter().chain(self.last_yank_end.take()) {
+            for buffer in &mut self.buffers {
+                buffer.remove_marker(id);
+            }
+        }
+        let buffer = self.current_buffer_mut();
+        let start = buffer.add_marker(start.0, start.1);
+        let end = buffer.add_marker(end.0, end.1);
+        self.last_yank_start = Some(start);
+        self.last_yank_end = Some(end);
+    }
+
+    /// Get the extent of the last yank, if it was in the current buffer
+    pub fn yank_region(&self) -> Option<((usize, usize), (usize, usize))> {
+        let buffer = self.current_buffer();
+        let start = buffer.marker(self.last_yank_start?)?;
+        let end = buffer.marker(self.last_yank_end?)?;
+        Some((start, end))
+    }
+
     /// Clear the kill flag (called after non-kill commands)
     pub fn clear_kill_flag(&mut self) {
         self.last_was_kill = false;
@@ -1088,76 +1989,62 @@ impl EditorState {
         let pattern = self.search.pattern.clone();
         let start_line = self.current_window().cursor_line();
         let start_col = self.current_window().cursor_col();
-        let line_count = self.current_buffer().line_count();
 
-        match direction {
-            SearchDirection::Forward => {
-                // Search forward from current position (skip current match)
-                let search_start_col = start_col + 1;
-                for line_idx in start_line..line_count {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let col_start = if line_idx == start_line { search_start_col } else { 0 };
-                        if col_start < text.len() {
-                            if let Some(pos) = text[col_start..].find(&pattern) {
-                                let match_col = col_start + pos;
-                                self.current_window_mut().set_cursor(line_idx, match_col);
-                                self.ensure_cursor_visible();
-                                self.display.set_message(&format!("Found: {}", pattern));
-                                return true;
-                            }
-                        }
-                    }
-                }
-                // Wrap around to beginning
-                for line_idx in 0..=start_line {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let col_end = if line_idx == start_line { start_col } else { text.len() };
-                        if let Some(pos) = text[..col_end].find(&pattern) {
-                            self.current_window_mut().set_cursor(line_idx, pos);
-                            self.ensure_cursor_visible();
-                            self.display.set_message(&format!("Wrapped: {}", pattern));
-                            return true;
-                        }
-                    }
-                }
+        match self.find_match(&pattern, start_line, start_col, direction) {
+            Some((line, col, wrapped)) => {
+                self.current_window_mut().set_cursor(line, col);
+                self.ensure_cursor_visible();
+                let what = if wrapped { "Wrapped" } else { "Found" };
+                self.display.set_message(format!("{}: {}", what, pattern));
+                true
             }
-            SearchDirection::Backward => {
-                // Search backward from current position
-                for line_idx in (0..=start_line).rev() {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let col_end = if line_idx == start_line { start_col } else { text.len() };
-                        if let Some(pos) = text[..col_end].rfind(&pattern) {
-                            self.current_window_mut().set_cursor(line_idx, pos);
-                            self.ensure_cursor_visible();
-                            self.display.set_message(&format!("Found: {}", pattern));
-                            return true;
-                        }
-                    }
-                }
-                // Wrap around to end
-                for line_idx in (start_line..line_count).rev() {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let col_start = if line_idx == start_line { start_col + 1 } else { 0 };
-                        if col_start < text.len() {
-                            if let Some(pos) = text[col_start..].rfind(&pattern) {
-                                let match_col = col_start + pos;
-                                self.current_window_mut().set_cursor(line_idx, match_col);
-                                self.ensure_cursor_visible();
-                                self.display.set_message(&format!("Wrapped: {}", pattern));
-                                return true;
-                            }
-                        }
-                    }
-                }
+            None => {
+                self.display.set_message(format!("Not found: {}", pattern));
+                false
             }
         }
+    }
 
-        self.display.set_message(&format!("Not found: {}", pattern));
-        false
+    /// Byte offsets of the matches of `pattern` in the visible part of a line
+    fn visible_matches(&self, line_idx: usize, pattern: &str) -> Vec<usize> {
+        let buffer = self.current_buffer();
+        match (buffer.line(line_idx), buffer.visible_span(line_idx)) {
+            (Some(line), Some((from, to))) => line
+                .safe_slice(from, to)
+                .match_indices(pattern)
+                .map(|(pos, _)| from + pos)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Find the next match of `pattern` in the visible part of the buffer
+    ///
+    /// Forward finds the first match starting after (line, col) and backward
+    /// the last one starting before it; if there is none the search wraps
+    /// around. Returns (line, col, wrapped).
+    fn find_match(&self, pattern: &str, line: usize, col: usize, direction: SearchDirection) -> Option<(usize, usize, bool)> {
+        let first = self.current_buffer().visible_start().0;
+        let last = self.current_buffer().visible_end().0;
+        let matches = |line_idx: usize| {
+            self.visible_matches(line_idx, pattern)
+                .into_iter()
+                .map(move |pos| (line_idx, pos))
+        };
+        let found = match direction {
+            SearchDirection::Forward => (line..=last)
+                .flat_map(matches)
+                .find(|&pos| pos > (line, col))
+                .map(|pos| (pos, false))
+                .or_else(|| (first..=last).flat_map(matches).next().map(|pos| (pos, true))),
+            SearchDirection::Backward => (first..=line.min(last))
+                .rev()
+                .flat_map(|l| matches(l).rev())
+                .find(|&pos| pos < (line, col))
+                .map(|pos| (pos, false))
+                .or_else(|| (first..=last).rev().flat_map(|l| matches(l).rev()).next().map(|pos| (pos, true))),
+        };
+        found.map(|((line, col), wrapped)| (line, col, wrapped))
     }
 
     /// Update search prompt in minibuffer
@@ -1177,112 +2064,46 @@ impl EditorState {
 
         let start_line = self.current_window().cursor_line();
         let start_col = self.current_window().cursor_col();
-        let line_count = self.current_buffer().line_count();
-
-        match self.search.direction {
-            SearchDirection::Forward => {
-                // Search forward from current position
-                for line_idx in start_line..line_count {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let search_start = if line_idx == start_line {
-                            // Skip past current position to find next match
-                            start_col + 1
-                        } else {
-                            0
-                        };
-
-                        if search_start < text.len() {
-                            if let Some(pos) = text[search_start..].find(&self.search.pattern) {
-                                let match_col = search_start + pos;
-                                self.current_window_mut().set_cursor(line_idx, match_col);
-                                self.search.last_match_line = Some(line_idx);
-                                self.search.last_match_col = Some(match_col);
-                                self.ensure_cursor_visible();
-                                return true;
-                            }
-                        }
-                    }
-                }
-                // Wrap around from beginning
-                for line_idx in 0..=start_line {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let search_end = if line_idx == start_line { start_col } else { text.len() };
-
-                        if let Some(pos) = text[..search_end].find(&self.search.pattern) {
-                            self.current_window_mut().set_cursor(line_idx, pos);
-                            self.search.last_match_line = Some(line_idx);
-                            self.search.last_match_col = Some(pos);
-                            self.ensure_cursor_visible();
-                            self.display.set_message(&format!("Wrapped: {}{}",
-                                if self.search.direction == SearchDirection::Forward { "I-search: " } else { "I-search backward: " },
-                                self.search.pattern));
-                            return true;
-                        }
-                    }
-                }
-            }
-            SearchDirection::Backward => {
-                // Search backward from current position
-                for line_idx in (0..=start_line).rev() {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let search_end = if line_idx == start_line {
-                            start_col
-                        } else {
-                            text.len()
-                        };
+        let pattern = self.search.pattern.clone();
 
-                        if search_end > 0 {
-                            // Find last occurrence before search_end
-                            if let Some(pos) = text[..search_end].rfind(&self.search.pattern) {
-                                self.current_window_mut().set_cursor(line_idx, pos);
-                                self.search.last_match_line = Some(line_idx);
-                                self.search.last_match_col = Some(pos);
-                                self.ensure_cursor_visible();
-                                return true;
-                            }
-                        }
-                    }
-                }
-                // Wrap around from end
-                for line_idx in (start_line..line_count).rev() {
-                    if let Some(line) = self.current_buffer().line(line_idx) {
-                        let text = line.text();
-                        let search_start = if line_idx == start_line { start_col + 1 } else { 0 };
-
-                        if search_start < text.len() {
-                            if let Some(pos) = text[search_start..].rfind(&self.search.pattern) {
-                                let match_col = search_start + pos;
-                                self.current_window_mut().set_cursor(line_idx, match_col);
-                                self.search.last_match_line = Some(line_idx);
-                                self.search.last_match_col = Some(match_col);
-                                self.ensure_cursor_visible();
-                                self.display.set_message(&format!("Wrapped: {}{}",
-                                    if self.search.direction == SearchDirection::Forward { "I-search: " } else { "I-search backward: " },
-                                    self.search.pattern));
-                                return true;
-                            }
-                        }
-                    }
+        match self.find_match(&pattern, start_line, start_col, self.search.direction) {
+            Some((line, col, wrapped)) => {
+                self.current_window_mut().set_cursor(line, col);
+                self.search.last_match_line = Some(line);
+                self.search.last_match_col = Some(col);
+                self.ensure_cursor_visible();
+                if wrapped {
+                    let prompt = match self.search.direction {
+                        SearchDirection::Forward => "I-search: ",
+                        SearchDirection::Backward => "I-search backward: ",
+                    };
+                    self.display.set_message(format!("Wrapped: {}{}", prompt, pattern));
                 }
+                true
             }
+            None => false,
         }
-
-        false
     }
 
     /// End search mode
+    ///
+    /// A search that moved point leaves where it started on the mark ring.
     pub fn end_search(&mut self, abort: bool) {
+        let origin_line = self.search.origin_line;
+        let origin_col = self.search.origin_col;
+        let moved = (self.current_window().cursor_line(), self.current_window().cursor_col()) != (origin_line, origin_col);
+        self.search.active = false;
+        self.display.clear_message();
+        if !abort {
+            self.history.add(HistoryKind::Search, &self.search.pattern);
+        }
         if abort {
             // Restore original position
-            let origin_line = self.search.origin_line;
-            let origin_col = self.search.origin_col;
             self.current_window_mut().set_cursor(origin_line, origin_col);
+        } else if moved {
+            self.push_mark(origin_line, origin_col);
+            self.display.set_message("Mark saved where search started");
         }
-        self.search.active = false;
-        self.display.clear_message();
     }
 
     /// Handle key press during search mode
@@ -1371,13 +2192,102 @@ impl EditorState {
 
     /// Ensure cursor is visible, scrolling if needed
     pub fn ensure_cursor_visible(&mut self) {
-        self.current_window_mut().ensure_cursor_visible();
+        self.ensure_window_cursor_visible(self.current_window);
+    }
+
+    /// Scroll window `idx` so its cursor is on screen, counting the screen
+    /// rows of wrapped lines and scrolling sideways for long ones
+    pub fn ensure_window_cursor_visible(&mut self, idx: usize) {
+        let cols = self.windows[idx].width() as usize;
+        let window = &mut self.windows[idx];
+        let buffer = &self.buffers[window.buffer_idx()];
+        let display = &self.display;
+        let (cursor_row, cursor_col) = display.row_and_column(buffer, window.cursor_line(), window.cursor_col(), cols);
+        if buffer.modes().visual_line {
+            window.clear_left_col();
+            window.ensure_cursor_visible_rows(cursor_row, |line| display.line_rows(buffer, line, cols).len());
+        } else {
+            window.ensure_cursor_visible();
+            window.ensure_column_visible(cursor_col, display.text_cols(buffer, cols));
+        }
+    }
+
+    /// Columns the current window has for text, after the line numbers
+    pub fn window_text_cols(&self) -> usize {
+        let cols = self.current_window().width() as usize;
+        self.display.text_cols(self.current_buffer(), cols)
+    }
+
+    /// Move the cursor one screen row down or up in a buffer in
+    /// visual-line mode, keeping its column within the row; returns false
+    /// (without moving) if the buffer's lines don't wrap
+    fn move_cursor_by_row(&mut self, down: bool) -> bool {
+        if !self.current_buffer().modes().visual_line {
+            return false;
+        }
+        let cols = self.current_window().width() as usize;
+        let buffer = self.current_buffer();
+        let (line_idx, col) = (self.current_window().cursor_line(), self.current_window().cursor_col());
+        if buffer.line(line_idx).is_none() {
+            return true;
+        }
+        let starts = self.display.line_rows(buffer, line_idx, cols);
+        let (row, goal) = self.display.row_and_column(buffer, line_idx, col, cols);
+
+        // The line and row to move to
+        let (target_line, target_row) = if down {
+            if row + 1 < starts.len() {
+                (line_idx, row + 1)
+            } else if line_idx + 1 < buffer.line_count() {
+                (line_idx + 1, 0)
+            } else {
+                return true;
+            }
+        } else if row > 0 {
+            (line_idx, row - 1)
+        } else if line_idx > 0 {
+            (line_idx - 1, self.display.line_rows(buffer, line_idx - 1, cols).len() - 1)
+        } else {
+            return true;
+        };
+
+        let starts = self.display.line_rows(buffer, target_line, cols);
+        let target_text = buffer.line(target_line).map(|l| l.text()).unwrap_or("");
+        let from = starts[target_row];
+        let last_row = target_row + 1 == starts.len();
+        let end = if last_row {
+            buffer.visible_span(target_line).map(|(_, to)| to).unwrap_or(target_text.len())
+        } else {
+            starts[target_row + 1]
+        };
+        let row_text = &target_text[from..end.max(from)];
+
+        // The character under the goal column, or the end of the row (the
+        // last character of a row that continues on the next one)
+        let mut display_col = 0;
+        let mut new_col = None;
+        for (byte_pos, ch) in row_text.char_indices() {
+            let width = char_width(ch, display_col, self.tab_width());
+            if display_col + width > goal {
+                new_col = Some(from + byte_pos);
+                break;
+            }
+            display_col += width;
+        }
+        let new_col = new_col.unwrap_or_else(|| match row_text.char_indices().last() {
+            Some((byte_pos, _)) if !last_row => from + byte_pos,
+            _ => from + row_text.len(),
+        });
+
+        self.current_window_mut().set_cursor(target_line, new_col);
+        self.ensure_cursor_visible();
+        true
     }
 
     /// Convert display column to byte offset in a line
     fn col_to_byte_in_line(&self, line_idx: usize, display_col: usize) -> usize {
         if let Some(line) = self.current_buffer().line(line_idx) {
-            line.col_to_byte(display_col).unwrap_or(line.len())
+            line.col_to_byte(display_col, self.tab_width()).unwrap_or(line.len())
         } else {
             0
         }
@@ -1388,31 +2298,156 @@ impl EditorState {
         self.prompt.active = true;
         self.prompt.prompt = prompt.to_string();
         self.prompt.input.clear();
+        self.prompt.cursor = 0;
+        self.prompt.quoting = false;
         self.prompt.action = action;
         self.prompt.default = default;
+        self.prompt.history_pos = None;
+        self.prompt.typed.clear();
+        self.update_prompt_display();
+    }
+
+    /// Step through the prompt's history (M-p/M-n): to older inputs, or
+    /// to newer ones and then back to the input typed
+    fn prompt_history(&mut self, older: bool) {
+        let entries = match self.prompt.action.history() {
+            Some(kind) => self.history.entries(kind),
+            None => &[],
+        };
+        let pos = match (self.prompt.history_pos, older) {
+            (None, true) if !entries.is_empty() => Some(0),
+            (Some(pos), true) if pos + 1 < entries.len() => Some(pos + 1),
+            (Some(0), false) => None,
+            (Some(pos), false) => Some(pos - 1),
+            _ => {
+                let _ = self.terminal.beep();
+                let edge = if older { "No older input" } else { "No newer input" };
+                self.display.set_message(format!("{}: {} [{}]", self.prompt.prompt, self.prompt.input, edge));
+                return;
+            }
+        };
+        let input = match pos {
+            Some(pos) => entries[pos].clone(),
+            None => std::mem::take(&mut self.prompt.typed),
+        };
+        if self.prompt.history_pos.is_none() {
+            self.prompt.typed = std::mem::take(&mut self.prompt.input);
+        }
+        self.set_prompt_input(input);
+        self.prompt.history_pos = pos;
         self.update_prompt_display();
     }
 
-    /// Update the prompt display
+    /// Replace the prompt's input, with the cursor at its end
+    fn set_prompt_input(&mut self, input: String) {
+        self.prompt.cursor = input.len();
+        self.prompt.input = input;
+    }
+
+    /// Update the prompt display, scrolled sideways if need be to show
+    /// the cursor
     fn update_prompt_display(&mut self) {
-        let display = if let Some(ref def) = self.prompt.default {
-            if self.prompt.input.is_empty() {
-                format!("{} (default {}): ", self.prompt.prompt, def)
-            } else {
-                format!("{}: {}", self.prompt.prompt, self.prompt.input)
+        let (line, cursor) = match self.prompt.default {
+            Some(ref def) if self.prompt.input.is_empty() => {
+                let line = format!("{} (default {}): ", self.prompt.prompt, def);
+                let cursor = line.len();
+                (line, cursor)
+            }
+            _ => {
+                let line = format!("{}: {}", self.prompt.prompt, self.prompt.input);
+                let cursor = line.len() - self.prompt.input.len() + self.prompt.cursor;
+                (line, cursor)
             }
-        } else {
-            format!("{}: {}", self.prompt.prompt, self.prompt.input)
         };
-        self.display.set_message(&display);
+        let (shown, col) = crate::minibuffer::fit_line(&line, cursor, self.terminal.cols() as usize, self.tab_width());
+        self.display.set_message(shown);
+        self.display.minibuffer_cursor = Some(col as u16);
+    }
+
+    /// Kill text from the prompt's input, as a new kill ring entry
+    fn prompt_kill(&mut self, text: &str) {
+        self.last_was_kill = false;
+        self.start_kill();
+        self.kill_append(text);
+        self.last_was_kill = false;
+    }
+
+    /// Handle the line editing keys of a prompt, false for other keys
+    fn edit_prompt_input(&mut self, key: Key) -> bool {
+        use crate::minibuffer::{backward_word, forward_word, next_char, prev_char};
+        let input = &self.prompt.input;
+        let cursor = self.prompt.cursor;
+        if key == Key::ctrl('a') || key == Key::special(0x47) {
+            self.prompt.cursor = 0;
+        } else if key == Key::ctrl('e') || key == Key::special(0x4f) {
+            self.prompt.cursor = input.len();
+        } else if key == Key::ctrl('f') || key == Key::special(0x4d) {
+            self.prompt.cursor = next_char(input, cursor);
+        } else if key == Key::ctrl('b') || key == Key::special(0x4b) {
+            self.prompt.cursor = prev_char(input, cursor);
+        } else if key == Key::meta('f') {
+            self.prompt.cursor = forward_word(input, cursor);
+        } else if key == Key::meta('b') {
+            self.prompt.cursor = backward_word(input, cursor);
+        } else if key == Key::ctrl('d') || key == Key::special(0x53) {
+            let end = next_char(input, cursor);
+            self.prompt.input.replace_range(cursor..end, "");
+        } else if key == Key(0x7f) || key == Key::ctrl('h') {
+            let start = prev_char(input, cursor);
+            self.prompt.input.replace_range(start..cursor, "");
+            self.prompt.cursor = start;
+        } else if key == Key::ctrl('k') {
+            let killed = self.prompt.input.split_off(cursor);
+            self.prompt_kill(&killed);
+        } else if key == Key(crate::input::key_flags::META | 0x7f) {
+            let start = backward_word(input, cursor);
+            let killed: String = self.prompt.input.drain(start..cursor).collect();
+            self.prompt.cursor = start;
+            self.prompt_kill(&killed);
+        } else if key == Key::ctrl('y') {
+            // Only the first line: the input is one line
+            let text = self.yank_text().unwrap_or("").lines().next().unwrap_or("").to_string();
+            self.prompt.input.insert_str(cursor, &text);
+            self.prompt.cursor += text.len();
+        } else if key == Key::ctrl('q') {
+            self.prompt.quoting = true;
+        } else if key.is_self_insert() {
+            match key.base_char() {
+                Some(ch) => {
+                    self.prompt.input.insert(cursor, ch);
+                    self.prompt.cursor += ch.len_utf8();
+                }
+                None => return false,
+            }
+        } else {
+            return false;
+        }
+        true
     }
 
     /// Handle key press during prompt mode
     fn handle_prompt_key(&mut self, key: Key) -> Result<()> {
+        // After C-q, the key is inserted as a character
+        if self.prompt.quoting {
+            self.prompt.quoting = false;
+            match crate::minibuffer::quoted_char(key) {
+                Some(ch) => {
+                    self.prompt.input.insert(self.prompt.cursor, ch);
+                    self.prompt.cursor += ch.len_utf8();
+                    self.update_prompt_display();
+                }
+                None => {
+                    let _ = self.terminal.beep();
+                }
+            }
+            return Ok(());
+        }
+
         // C-g aborts
         if key == Key::ctrl('g') {
             self.prompt.active = false;
             self.prompt.action = PromptAction::None;
+            self.close_completions();
             self.display.set_message("Quit");
             return Ok(());
         }
@@ -1425,24 +2460,74 @@ impl EditorState {
                 self.prompt.input.clone()
             };
             let action = self.prompt.action.clone();
+            if let Some(kind) = action.history() {
+                let entry = if action.reads_file_name() {
+                    crate::completion::file_name_tail(&input)
+                } else {
+                    &input
+                };
+                self.history.add(kind, entry);
+            }
             self.prompt.active = false;
+            self.close_completions();
             self.display.clear_message();
             return self.complete_prompt(action, input);
         }
 
-        // Backspace
-        if key == Key(0x7f) || key == Key::ctrl('h') {
-            self.prompt.input.pop();
-            self.update_prompt_display();
+        // M-p/Up and M-n/Down step through earlier inputs
+        if key == Key::meta('p') || key == Key::special(0x48) {
+            self.prompt_history(true);
+            return Ok(());
+        }
+        if key == Key::meta('n') || key == Key::special(0x50) {
+            self.prompt_history(false);
             return Ok(());
         }
 
-        // Printable character
-        if key.is_self_insert() {
-            if let Some(ch) = key.base_char() {
-                self.prompt.input.push(ch);
-                self.update_prompt_display();
+        // TAB completes from the prompt's candidates
+        if key == Key::ctrl('i') {
+            if self.prompt.action.reads_file_name() {
+                self.complete_file_name();
+                return Ok(());
+            }
+            if self.prompt.action == PromptAction::ExtendedCommand {
+                self.complete_command_name();
+                return Ok(());
             }
+            if let Some(candidates) = self.prompt_candidates() {
+                let mut matches = crate::completion::matches(&self.prompt.input, &candidates);
+                let fuzzy = matches.is_empty();
+                if fuzzy {
+                    matches = crate::completion::fuzzy_matches(&self.prompt.input, &candidates);
+                }
+                match matches.len() {
+                    0 => {
+                        let _ = self.terminal.beep();
+                        self.display.set_message(format!("{}: {} [No match]", self.prompt.prompt, self.prompt.input));
+                    }
+                    1 => {
+                        self.set_prompt_input(matches[0].to_string());
+                        self.update_prompt_display();
+                    }
+                    _ => {
+                        if !fuzzy {
+                            self.set_prompt_input(crate::completion::common_prefix(&matches));
+                        }
+                        self.display.set_message(format!(
+                            "{}: {} {{{}}}",
+                            self.prompt.prompt,
+                            self.prompt.input,
+                            matches.join(", ")
+                        ));
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        // Moving, deleting, killing, yanking and typing in the input
+        if self.edit_prompt_input(key) {
+            self.update_prompt_display();
             return Ok(());
         }
 
@@ -1451,29 +2536,207 @@ impl EditorState {
         Ok(())
     }
 
+    /// Completion candidates for the active prompt, if it has any
+    fn prompt_candidates(&self) -> Option<Vec<String>> {
+        match self.prompt.action {
+            PromptAction::BookmarkJump | PromptAction::BookmarkRename | PromptAction::BookmarkDelete => {
+                Some(crate::command::bookmark_names(self))
+            }
+            PromptAction::RecentFile => {
+                let home = crate::completion::home_dir();
+                Some(
+                    self.recent_files
+                        .iter()
+                        .map(|r| crate::completion::abbreviate_path(&r.file, home.as_deref()))
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Directory file names are relative to: that of the current buffer's
+    /// file, or the working directory
+    pub fn default_directory(&self) -> PathBuf {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        match self.current_buffer().filename().and_then(|f| f.parent()) {
+            Some(dir) => cwd.join(dir),
+            None => cwd,
+        }
+    }
+
+    /// Start a prompt for a file name, with the input holding the default
+    /// directory
+    pub fn start_file_prompt(&mut self, prompt: &str, action: PromptAction, default: Option<String>) {
+        self.start_prompt(prompt, action, default);
+        let home = crate::completion::home_dir();
+        self.set_prompt_input(crate::completion::abbreviate_directory(&self.default_directory(), home.as_deref()));
+        self.update_prompt_display();
+    }
+
+    /// The file a file name prompt's input names
+    fn resolve_file_name(&self, input: &str) -> PathBuf {
+        let home = crate::completion::home_dir();
+        let name = crate::completion::file_name_tail(input);
+        crate::completion::expand_file_name(name, home.as_deref(), &self.default_directory())
+    }
+
+    /// TAB in a file name prompt: complete the name against the entries of
+    /// the directory typed so far, listing them in *Completions* if there
+    /// is more than one
+    fn complete_file_name(&mut self) {
+        let tail = crate::completion::file_name_tail(&self.prompt.input).to_string();
+        let (dir_part, name_part) = crate::completion::split_file_name(&tail);
+        let dir = self.resolve_file_name(dir_part);
+        let entries = crate::completion::directory_entries(&dir);
+        let matches = crate::completion::file_matches(name_part, &entries);
+        match matches.len() {
+            0 => {
+                let _ = self.terminal.beep();
+                self.close_completions();
+                self.display.set_message(format!("{}: {} [No match]", self.prompt.prompt, self.prompt.input));
+            }
+            1 => {
+                self.set_prompt_input(format!("{}{}", dir_part, matches[0]));
+                self.close_completions();
+                self.update_prompt_display();
+            }
+            _ => {
+                let prefix = crate::completion::common_prefix(&matches);
+                self.set_prompt_input(format!("{}{}", dir_part, prefix));
+                if self.show_completions(&matches) {
+                    self.update_prompt_display();
+                } else {
+                    self.display.set_message(format!(
+                        "{}: {} {{{}}}",
+                        self.prompt.prompt,
+                        self.prompt.input,
+                        matches.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+
+    /// TAB in the M-x prompt: complete the command name, matching names
+    /// starting with the input or, failing that, those with its chars in
+    /// order, and list them with their keys in *Completions*
+    fn complete_command_name(&mut self) {
+        let names: Vec<String> = self.keytab.command_names().iter().map(|n| n.to_string()).collect();
+        let mut matches = crate::completion::matches(&self.prompt.input, &names);
+        let fuzzy = matches.is_empty();
+        if fuzzy {
+            matches = crate::completion::fuzzy_matches(&self.prompt.input, &names);
+        }
+        match matches.len() {
+            0 => {
+                let _ = self.terminal.beep();
+                self.close_completions();
+                self.display.set_message(format!("{}: {} [No match]", self.prompt.prompt, self.prompt.input));
+            }
+            1 => {
+                self.set_prompt_input(matches[0].to_string());
+                self.close_completions();
+                self.update_prompt_display();
+            }
+            _ => {
+                if !fuzzy {
+                    self.set_prompt_input(crate::completion::common_prefix(&matches));
+                }
+                let lines: Vec<String> = matches
+                    .iter()
+                    .map(|name| {
+                        let keys: Vec<String> = self.keytab.keys_for(name).iter().map(|k| k.display_name()).collect();
+                        format!("{:<32} {}", name, keys.join(", ")).trim_end().to_string()
+                    })
+                    .collect();
+                let lines: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+                if self.show_completions(&lines) {
+                    self.update_prompt_display();
+                } else {
+                    self.display.set_message(format!(
+                        "{}: {} {{{}}}",
+                        self.prompt.prompt,
+                        self.prompt.input,
+                        matches.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+
+    /// List completions in the *Completions* buffer, in a window split off
+    /// below the current one for the rest of the prompt
+    ///
+    /// Returns false if there is no room to split the window.
+    fn show_completions(&mut self, names: &[&str]) -> bool {
+        let mut content = format!("{} possible completions:\n\n", names.len());
+        for name in names {
+            content.push_str(name);
+            content.push('\n');
+        }
+        let idx = match self.buffers.iter().position(|b| b.name() == COMPLETIONS_BUFFER) {
+            Some(idx) => {
+                self.buffers[idx].set_content(&content);
+                idx
+            }
+            None => {
+                self.buffers.push(Buffer::from_content(COMPLETIONS_BUFFER, &content));
+                self.buffers.len() - 1
+            }
+        };
+
+        let shown = self.completions_window.filter(|&w| w < self.windows.len());
+        let window = match shown {
+            Some(window) => window,
+            None => {
+                if !self.split_window(SplitKind::Stacked) {
+                    return false;
+                }
+                self.current_window + 1
+            }
+        };
+        self.windows[window].set_buffer_idx(idx);
+        self.windows[window].set_cursor(0, 0);
+        self.windows[window].set_top_line(0);
+        self.completions_window = Some(window);
+        self.display.force_redraw();
+        true
+    }
+
+    /// Remove the window *Completions* was shown in
+    fn close_completions(&mut self) {
+        if let Some(window) = self.completions_window.take() {
+            if self.remove_window(window) {
+                self.apply_layout();
+            }
+        }
+    }
+
     /// Complete a prompt action
     fn complete_prompt(&mut self, action: PromptAction, input: String) -> Result<()> {
         match action {
             PromptAction::FindFile => {
-                if input.is_empty() {
+                let name = crate::completion::file_name_tail(&input).to_string();
+                if crate::completion::split_file_name(&name).1.is_empty() {
                     self.display.set_message("No file name");
                     return Ok(());
                 }
-                let path = PathBuf::from(&input);
+                let path = self.resolve_file_name(&input);
+                if path.is_dir() {
+                    self.display.set_message(format!("{} is a directory", name));
+                    return Ok(());
+                }
                 match self.open_file(&path) {
                     Ok(()) => {
-                        self.display.set_message(&format!("Opened {}", input));
+                        self.display.set_message(&format!("Opened {}", name));
                     }
-                    Err(e) => {
-                        // File doesn't exist - create new buffer with that name
-                        let mut buffer = Buffer::new(&input);
-                        buffer.set_filename(path);
-                        self.buffers.push(buffer);
-                        let buf_idx = self.buffers.len() - 1;
-                        if let Some(window) = self.windows.get_mut(self.current_window) {
-                            window.set_buffer_idx(buf_idx);
-                        }
-                        self.display.set_message(&format!("(New file) {}", input));
+                    Err(e) if path.exists() => {
+                        self.display.set_message(format!("Cannot open {}: {}", name, e));
+                    }
+                    Err(_) => {
+                        // File doesn't exist - create new buffer for it
+                        self.open_new_file(&path);
                     }
                 }
             }
@@ -1483,6 +2746,7 @@ impl EditorState {
                 }
                 // Find buffer by name
                 if let Some(idx) = self.buffers.iter().position(|b| b.name() == input) {
+                    self.remember_place(self.current_window().buffer_idx());
                     if let Some(window) = self.windows.get_mut(self.current_window) {
                         window.set_buffer_idx(idx);
                         window.set_cursor(0, 0);
@@ -1505,12 +2769,8 @@ impl EditorState {
                     // Check if modified and warning is enabled
                     if self.warn_unsaved && self.buffers[idx].is_modified() {
                         // Prompt for confirmation
-                        self.prompt.active = true;
-                        self.prompt.prompt = format!("Buffer {} modified; kill anyway? (y/n) ", input);
-                        self.prompt.input.clear();
-                        self.prompt.action = PromptAction::ConfirmKillBuffer;
-                        self.prompt.default = Some(input);
-                        self.update_prompt_display();
+                        let prompt = format!("Buffer {} modified; kill anyway? (y/n) ", input);
+                        self.start_prompt(&prompt, PromptAction::ConfirmKillBuffer, Some(input));
                         return Ok(());
                     }
                     // Kill the buffer
@@ -1521,10 +2781,12 @@ impl EditorState {
             }
             PromptAction::GotoLine => {
                 if let Ok(line_num) = input.parse::<usize>() {
-                    let target = line_num.saturating_sub(1); // Convert to 0-indexed
-                    let max_line = self.current_buffer().line_count().saturating_sub(1);
-                    let target = target.min(max_line);
-                    self.current_window_mut().set_cursor(target, 0);
+                    // Line numbers count from the start of a narrowing
+                    let first_line = self.current_buffer().visible_start().0;
+                    let target = first_line + line_num.saturating_sub(1); // Convert to 0-indexed
+                    let (target, col) = self.current_buffer().clamp_visible(target, 0);
+                    self.push_point();
+                    self.current_window_mut().set_cursor(target, col);
                     self.ensure_cursor_visible();
                 } else {
                     self.display.set_message("Invalid line number");
@@ -1537,12 +2799,8 @@ impl EditorState {
                 }
                 // Store search string and prompt for replacement
                 self.query_replace.search = input.clone();
-                self.prompt.active = true;
-                self.prompt.prompt = format!("Query replace {} with: ", input);
-                self.prompt.input.clear();
-                self.prompt.action = PromptAction::QueryReplaceReplace;
-                self.prompt.default = None;
-                self.update_prompt_display();
+                let prompt = format!("Query replace {} with", input);
+                self.start_prompt(&prompt, PromptAction::QueryReplaceReplace, None);
             }
             PromptAction::QueryReplaceReplace => {
                 // Store replacement and start query-replace mode
@@ -1560,12 +2818,8 @@ impl EditorState {
                 }
                 // Store search string and prompt for replacement
                 self.query_replace.search = input.clone();
-                self.prompt.active = true;
-                self.prompt.prompt = format!("Replace {} with: ", input);
-                self.prompt.input.clear();
-                self.prompt.action = PromptAction::ReplaceStringReplace;
-                self.prompt.default = None;
-                self.update_prompt_display();
+                let prompt = format!("Replace {} with", input);
+                self.start_prompt(&prompt, PromptAction::ReplaceStringReplace, None);
             }
             PromptAction::ReplaceStringReplace => {
                 // Perform all replacements without prompting
@@ -1579,11 +2833,12 @@ impl EditorState {
                 self.execute_shell_command(&input);
             }
             PromptAction::InsertFile => {
-                if input.is_empty() {
+                let path = self.resolve_file_name(&input);
+                if input.is_empty() || path.is_dir() {
                     self.display.set_message("No file name");
                     return Ok(());
                 }
-                self.insert_file(&input);
+                self.insert_file(&path.display().to_string());
             }
             PromptAction::FilterBuffer => {
                 if input.is_empty() {
@@ -1608,12 +2863,33 @@ impl EditorState {
                     self.display.set_message("No file name");
                     return Ok(());
                 }
-                self.write_file(&input);
+                // Writing to a directory keeps the file's name
+                let mut path = self.resolve_file_name(&input);
+                if path.is_dir() {
+                    let name = self
+                        .prompt
+                        .default
+                        .as_ref()
+                        .and_then(|d| PathBuf::from(d).file_name().map(|n| n.to_os_string()))
+                        .unwrap_or_else(|| self.current_buffer().name().into());
+                    path.push(name);
+                }
+                self.write_file(&path.display().to_string());
             }
             PromptAction::ExtendedCommand => {
                 if input.is_empty() {
                     return Ok(());
                 }
+                // A name fuzzily matching just one command is completed to
+                // it, and runs only once confirmed with RET
+                if self.keytab.lookup_by_name(&input).is_none() {
+                    if let [only] = &self.command_matches(&input)[..] {
+                        self.start_prompt("M-x", PromptAction::ExtendedCommand, None);
+                        self.set_prompt_input(only.clone());
+                        self.update_prompt_display();
+                        return Ok(());
+                    }
+                }
                 self.execute_named_command(&input);
             }
             PromptAction::ConfirmQuit => {
@@ -1636,6 +2912,84 @@ impl EditorState {
                     self.display.set_message("Buffer not killed");
                 }
             }
+            PromptAction::SetLineEnding => {
+                match crate::file_format::LineEnding::from_name(&input) {
+                    Some(ending) => {
+                        self.current_buffer_mut().set_line_ending(ending);
+                        self.display.set_message(format!("Line endings set to {}", ending.name()));
+                    }
+                    None => {
+                        self.display.set_message(format!("Unknown line ending: {}", input));
+                    }
+                }
+            }
+            PromptAction::ReopenWithEncoding => {
+                let encoding = if input.is_empty() || input == "auto" {
+                    None
+                } else {
+                    match crate::file_format::Encoding::from_name(&input) {
+                        Some(encoding) => Some(encoding),
+                        None => {
+                            self.display.set_message(format!("Unknown encoding: {}", input));
+                            return Ok(());
+                        }
+                    }
+                };
+                match self.current_buffer_mut().reload_with_encoding(encoding) {
+                    Ok(()) => {
+                        let name = self.current_buffer().format().encoding.name();
+                        self.current_window_mut().set_cursor(0, 0);
+                        self.current_window_mut().set_top_line(0);
+                        self.invalidate_syntax_from(0);
+                        self.display.force_redraw();
+                        self.display.set_message(format!("Reopened as {}", name));
+                    }
+                    Err(e) => {
+                        self.display.set_message(format!("Cannot reopen: {}", e));
+                    }
+                }
+            }
+            PromptAction::UndoTreeGoto => {
+                match input.trim().parse::<usize>() {
+                    Ok(state) => {
+                        self.goto_undo_state(state);
+                    }
+                    Err(_) => self.display.set_message("Invalid undo state"),
+                }
+            }
+            PromptAction::StringRectangle => {
+                crate::command::apply_string_rectangle(self, &input);
+            }
+            PromptAction::BookmarkSet => {
+                crate::command::set_bookmark(self, &input);
+            }
+            PromptAction::BookmarkJump => {
+                crate::command::jump_to_bookmark(self, &input);
+            }
+            PromptAction::BookmarkRename => {
+                if !self.bookmarks.iter().any(|b| b.name == input) {
+                    self.display.set_message(format!("No bookmark named {}", input));
+                    return Ok(());
+                }
+                let prompt = format!("Rename bookmark {} to", input);
+                self.start_prompt(&prompt, PromptAction::BookmarkRenameTo(input), None);
+            }
+            PromptAction::BookmarkRenameTo(old) => {
+                crate::command::rename_bookmark(self, &old, &input);
+            }
+            PromptAction::BookmarkDelete => {
+                crate::command::delete_bookmark(self, &input);
+            }
+            PromptAction::RecentFile => {
+                if input.is_empty() {
+                    return Ok(());
+                }
+                let home = crate::completion::home_dir();
+                let path = crate::completion::expand_file_name(&input, home.as_deref(), &self.default_directory());
+                if let Err(e) = self.visit_file(&path) {
+                    self.display.set_message(format!("Cannot open {}: {}", input, e));
+                }
+            }
             PromptAction::None => {}
         }
         Ok(())
@@ -1646,72 +3000,45 @@ impl EditorState {
         self.buffers.iter().map(|b| b.name()).collect()
     }
 
-    /// Split current window horizontally
-    pub fn split_window(&mut self) -> bool {
-        let current_height = self.current_window().height();
-
-        // Need at least 3 rows to split (1 for each window + mode line)
-        if current_height < 4 {
+    /// Split the current window in two, the new window showing the same
+    /// buffer below it (stacked) or right of it (side by side)
+    pub fn split_window(&mut self, kind: SplitKind) -> bool {
+        let area = self.layout_area;
+        if !self.layout.split(self.current_window, kind, area) {
             return false;
         }
 
-        let top_row = self.current_window().top_row();
-        let buffer_idx = self.current_window().buffer_idx();
-        let cursor_line = self.current_window().cursor_line();
-        let cursor_col = self.current_window().cursor_col();
-
-        // Calculate new heights
-        let top_height = current_height / 2;
-        let bottom_height = current_height - top_height - 1; // -1 for mode line
-
-        // Update current window (becomes top)
-        self.current_window_mut().set_height(top_height);
-
-        // Create new window (bottom), showing same buffer
-        let mut new_window = Window::new(buffer_idx, top_row + top_height + 1, bottom_height);
-        new_window.set_cursor(cursor_line, cursor_col);
-        new_window.ensure_cursor_visible();
-
-        // Insert new window after current
-        let insert_pos = self.current_window + 1;
-        self.windows.insert(insert_pos, new_window);
+        // New window shows the same buffer at the same place
+        let mut new_window = self.current_window().clone();
+        new_window.forget_markers();
+        self.windows.insert(self.current_window + 1, new_window);
 
-        // Update positions of windows below the split
-        self.recalculate_window_positions();
-
-        self.display.force_redraw();
+        self.apply_layout();
         true
     }
 
     /// Delete current window
     pub fn delete_window(&mut self) -> bool {
-        if self.windows.len() <= 1 {
+        if !self.remove_window(self.current_window) {
             return false;
         }
+        self.apply_layout();
+        true
+    }
 
-        let deleted_height = self.current_window().height() + 1; // +1 for mode line
-        let deleted_idx = self.current_window;
-
-        // Give space to adjacent window
-        if deleted_idx > 0 {
-            // Give to window above
-            let above_height = self.windows[deleted_idx - 1].height();
-            self.windows[deleted_idx - 1].set_height(above_height + deleted_height);
-        } else if deleted_idx + 1 < self.windows.len() {
-            // Give to window below
-            let below_height = self.windows[deleted_idx + 1].height();
-            self.windows[deleted_idx + 1].set_height(below_height + deleted_height);
+    /// Helper: take window `idx` out of the layout and the window list,
+    /// false for the only window
+    fn remove_window(&mut self, idx: usize) -> bool {
+        if !self.layout.remove(idx) {
+            return false;
         }
-
-        self.windows.remove(deleted_idx);
+        let window = self.windows.remove(idx);
+        self.release_window(window);
 
         // Update current window index
-        if self.current_window >= self.windows.len() {
-            self.current_window = self.windows.len() - 1;
+        if self.current_window > idx || self.current_window >= self.windows.len() {
+            self.current_window -= 1;
         }
-
-        self.recalculate_window_positions();
-        self.display.force_redraw();
         true
     }
 
@@ -1721,105 +3048,301 @@ impl EditorState {
             return false;
         }
 
-        let buffer_idx = self.current_window().buffer_idx();
-        let cursor_line = self.current_window().cursor_line();
-        let cursor_col = self.current_window().cursor_col();
-        let top_line = self.current_window().top_line();
+        let window = self.windows.remove(self.current_window);
+        for other in std::mem::replace(&mut self.windows, vec![window]) {
+            self.release_window(other);
+        }
+        self.current_window = 0;
+        self.layout = Layout::default();
 
-        // Calculate total height available
-        let total_height = self.terminal.rows().saturating_sub(2); // -2 for mode line and minibuffer
+        self.apply_layout();
+        true
+    }
 
-        // Create single window
-        let mut window = Window::new(buffer_idx, 0, total_height);
-        window.set_cursor(cursor_line, cursor_col);
-        window.set_top_line(top_line);
+    /// Save the current windows for a register
+    ///
+    /// Each window's cursor is held in a marker so it follows edits.
+    pub fn save_window_layout(&mut self) -> WindowLayout {
+        let mut windows = Vec::new();
+        for window in &self.windows {
+            let buffer = &mut self.buffers[window.buffer_idx()];
+            let mut copy = window.clone();
+            copy.forget_markers();
+            windows.push(SavedWindow {
+                window: copy,
+                buffer: buffer.name().to_string(),
+                point: buffer.add_marker(window.cursor_line(), window.cursor_col()),
+            });
+        }
+        WindowLayout {
+            windows,
+            tree: self.layout.clone(),
+            area: self.layout_area,
+            current: self.current_window,
+        }
+    }
 
-        self.windows = vec![window];
-        self.current_window = 0;
+    /// Restore windows saved by `save_window_layout`
+    ///
+    /// Fails if one of the buffers has been killed since the layout was
+    /// saved. If the screen size changed, the windows are resized in
+    /// proportion.
+    pub fn restore_window_layout(&mut self, layout: &WindowLayout) -> std::result::Result<(), String> {
+        let mut windows = Vec::new();
+        for saved in &layout.windows {
+            let idx = self
+                .buffers
+                .iter()
+                .position(|b| b.name() == saved.buffer)
+                .ok_or_else(|| format!("Buffer {} no longer exists", saved.buffer))?;
+            let mut window = saved.window.clone();
+            if window.buffer_idx() != idx {
+                window.set_buffer_idx(idx);
+            }
+            let (line, col) = self.buffers[idx].marker(saved.point).unwrap_or((0, 0));
+            window.set_cursor(line, col);
+            window.set_top_line(saved.window.top_line().min(line));
+            windows.push(window);
+        }
 
-        self.display.force_redraw();
-        true
+        for old in std::mem::replace(&mut self.windows, windows) {
+            self.release_window(old);
+        }
+        self.layout = layout.tree.clone();
+        self.layout_area = layout.area;
+        self.current_window = layout.current.min(self.windows.len() - 1);
+        self.apply_layout();
+        Ok(())
     }
 
-    /// Switch to other window
-    pub fn other_window(&mut self) {
-        if self.windows.len() > 1 {
-            self.current_window = (self.current_window + 1) % self.windows.len();
+    /// The session, for the desktop file
+    pub fn desktop_state(&self) -> Desktop {
+        let canonical = |p: &PathBuf| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone());
+        let files = self.buffers.iter().filter_map(|b| b.filename().map(canonical)).collect();
+        let windows = self
+            .windows
+            .iter()
+            .enumerate()
+            .map(|(idx, window)| {
+                let buffer = &self.buffers[window.buffer_idx()];
+                DesktopWindow {
+                    file: buffer.filename().map(canonical),
+                    buffer: buffer.name().to_string(),
+                    top_line: window.top_line(),
+                    point: (window.cursor_line(), window.cursor_col()),
+                    mark: self.window_mark(idx),
+                }
+            })
+            .collect();
+        Desktop {
+            files,
+            windows,
+            layout: self.layout.clone(),
+            rows: self.layout_area.height,
+            cols: self.layout_area.width,
+            current: self.current_window,
+            kill: self.kill_ring.last().filter(|k| !k.is_empty()).cloned(),
+            search: Some(self.search.pattern.clone()).filter(|p| !p.is_empty()),
         }
     }
 
-    /// Recalculate window positions after split/delete
-    fn recalculate_window_positions(&mut self) {
-        let mut current_row: u16 = 0;
-        for window in &mut self.windows {
-            window.set_top_row(current_row);
-            current_row += window.height() + 1; // +1 for mode line
+    /// Restore a session saved by `desktop_state`, returns the number of
+    /// files opened
+    ///
+    /// Files that can no longer be opened are skipped, and windows that
+    /// showed them show the current buffer instead. The windows are
+    /// resized in proportion if the screen size changed.
+    pub fn restore_desktop(&mut self, desktop: &Desktop) -> usize {
+        let canonical = |p: &PathBuf| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone());
+        let mut opened = 0;
+        for file in &desktop.files {
+            if file.is_file() && self.visit_file(file).is_ok() {
+                opened += 1;
+            }
+        }
+
+        let fallback = self.current_window().buffer_idx();
+        let mut windows = Vec::new();
+        let mut marks = Vec::new();
+        for saved in &desktop.windows {
+            let idx = match &saved.file {
+                Some(file) => {
+                    let target = canonical(file);
+                    self.buffers
+                        .iter()
+                        .position(|b| b.filename().map(canonical).as_ref() == Some(&target))
+                }
+                None => self.buffers.iter().position(|b| b.name() == saved.buffer),
+            }
+            .unwrap_or(fallback);
+            let buffer = &self.buffers[idx];
+            let (line, col) = buffer.clamp_position(saved.point.0, saved.point.1);
+            let mut window = Window::new(idx, 0, 0);
+            window.set_cursor(line, col);
+            window.set_top_line(saved.top_line.min(line));
+            marks.push(saved.mark.map(|(mark_line, mark_col)| buffer.clamp_position(mark_line, mark_col)));
+            windows.push(window);
+        }
+        if !windows.is_empty() && windows.len() == desktop.layout.window_count() {
+            for old in std::mem::replace(&mut self.windows, windows) {
+                self.release_window(old);
+            }
+            for (idx, mark) in marks.into_iter().enumerate() {
+                if let Some((line, col)) = mark {
+                    self.set_window_mark(idx, line, col);
+                }
+            }
+            self.layout = desktop.layout.clone();
+            self.layout_area = Rect { top: 0, left: 0, height: desktop.rows, width: desktop.cols };
+            self.current_window = desktop.current.min(self.windows.len() - 1);
+            self.apply_layout();
+        }
+
+        if let Some(kill) = desktop.kill.as_ref().filter(|k| self.kill_ring.last() != Some(*k)) {
+            self.kill_ring.push(kill.clone());
+            self.kill_ring_idx = self.kill_ring.len() - 1;
         }
+        if let Some(search) = &desktop.search {
+            self.search.pattern = search.clone();
+        }
+        self.display.force_redraw();
+        opened
     }
 
-    /// Get number of windows
-    pub fn window_count(&self) -> usize {
-        self.windows.len()
+    /// Store a register, releasing the markers of what it held before
+    pub fn set_register(&mut self, name: char, register: Register) {
+        if let Some(old) = self.registers.insert(name, register) {
+            for id in old.markers() {
+                for buffer in &mut self.buffers {
+                    buffer.remove_marker(id);
+                }
+            }
+        }
     }
 
-    /// Enlarge current window by n lines (taking from adjacent window)
-    pub fn enlarge_window(&mut self, n: u16) -> bool {
-        if self.windows.len() < 2 {
-            return false;
+    /// Save a position in the current buffer on its mark ring and on the
+    /// global mark ring
+    ///
+    /// The global ring only gets the position if its newest entry is in
+    /// another buffer, so it holds one entry per visit to a buffer.
+    pub fn push_mark(&mut self, line: usize, col: usize) {
+        let buf_idx = self.current_window().buffer_idx();
+        self.buffers[buf_idx].push_mark(line, col);
+
+        let newest = self.global_mark_ring.last().and_then(|&id| self.find_marker(id));
+        if newest.map(|(idx, _)| idx) == Some(buf_idx) {
+            return;
         }
+        if self.global_mark_ring.len() >= crate::buffer::MARK_RING_MAX {
+            let oldest = self.global_mark_ring.remove(0);
+            for buffer in &mut self.buffers {
+                buffer.remove_marker(oldest);
+            }
+        }
+        let id = self.buffers[buf_idx].add_marker(line, col);
+        self.global_mark_ring.push(id);
+    }
 
-        // Find adjacent window to take space from (prefer below, else above)
-        let other_idx = if self.current_window + 1 < self.windows.len() {
-            self.current_window + 1
-        } else {
-            self.current_window - 1
-        };
+    /// Save point on the mark rings before a jump
+    pub fn push_point(&mut self) {
+        let line = self.current_window().cursor_line();
+        let col = self.current_window().cursor_col();
+        self.push_mark(line, col);
+    }
 
-        let other_height = self.windows[other_idx].height();
-        if other_height <= n + 1 {
-            // Other window would become too small (need at least 2 lines)
-            return false;
+    /// Find a marker in any buffer, returns (buffer index, (line, col))
+    pub fn find_marker(&self, id: MarkerId) -> Option<(usize, (usize, usize))> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .find_map(|(idx, buffer)| buffer.marker(id).map(|pos| (idx, pos)))
+    }
+
+    /// Switch to other window
+    pub fn other_window(&mut self) {
+        if let Some(idx) = self.next_window() {
+            self.select_window(idx);
         }
+    }
 
-        // Adjust heights
-        let current_height = self.windows[self.current_window].height();
-        self.windows[self.current_window].set_height(current_height + n);
-        self.windows[other_idx].set_height(other_height - n);
+    /// Next window on screen after the current one, if there is another
+    pub fn next_window(&self) -> Option<usize> {
+        let count = self.windows.len();
+        (1..count)
+            .map(|offset| (self.current_window + offset) % count)
+            .find(|&idx| !self.windows[idx].is_hidden())
+    }
 
-        self.recalculate_window_positions();
-        self.display.force_redraw();
-        true
+    /// Screen area shared by the windows: all but the minibuffer row
+    fn screen_area(&self) -> Rect {
+        Rect {
+            top: 0,
+            left: 0,
+            height: self.terminal.rows().saturating_sub(1),
+            width: self.terminal.cols(),
+        }
     }
 
-    /// Shrink current window by n lines (giving to adjacent window)
-    pub fn shrink_window(&mut self, n: u16) -> bool {
-        if self.windows.len() < 2 {
-            return false;
+    /// Place the windows on screen as the layout says
+    ///
+    /// If the terminal changed size, the windows are resized in
+    /// proportion; when it is too small for all of them, windows other
+    /// than the current one are hidden, last first, until the rest fit.
+    /// The layout keeps the hidden windows, so they come back when the
+    /// terminal grows again.
+    fn apply_layout(&mut self) {
+        let area = self.screen_area();
+        if area != self.layout_area && self.layout.fits(area) {
+            self.layout.fit(self.layout_area, area);
+            self.layout_area = area;
         }
 
-        let current_height = self.windows[self.current_window].height();
-        if current_height <= n + 1 {
-            // Current window would become too small (need at least 2 lines)
-            return false;
+        let rects = self.layout.squeezed_rects(self.layout_area, area, self.current_window);
+        let hidden = rects.iter().filter(|rect| rect.is_none()).count();
+        let was_hidden = self.windows.iter().filter(|w| w.is_hidden()).count();
+        for (window, rect) in self.windows.iter_mut().zip(rects) {
+            window.set_hidden(rect.is_none());
+            if let Some(rect) = rect {
+                window.set_top_row(rect.top);
+                window.set_height(rect.height.saturating_sub(1)); // -1 for mode line
+                window.set_screen_col(rect.left);
+                window.set_width(rect.width);
+            }
+        }
+        if hidden != was_hidden && hidden > 0 {
+            self.display.set_message(format!("Terminal too small: {} window(s) hidden", hidden));
         }
+        for idx in 0..self.windows.len() {
+            self.ensure_window_cursor_visible(idx);
+        }
+        self.display.force_redraw();
+    }
 
-        // Find adjacent window to give space to (prefer below, else above)
-        let other_idx = if self.current_window + 1 < self.windows.len() {
-            self.current_window + 1
-        } else {
-            self.current_window - 1
-        };
+    /// Get number of windows
+    pub fn window_count(&self) -> usize {
+        self.windows.len()
+    }
 
-        // Adjust heights
-        let other_height = self.windows[other_idx].height();
-        self.windows[self.current_window].set_height(current_height - n);
-        self.windows[other_idx].set_height(other_height + n);
+    /// Enlarge current window by n rows (stacked) or columns (side by
+    /// side), taking them from the windows beside it
+    pub fn enlarge_window(&mut self, kind: SplitKind, n: u16) -> bool {
+        self.resize_window(kind, i32::from(n))
+    }
 
-        // Make sure cursor is still visible in current window
-        self.current_window_mut().ensure_cursor_visible();
+    /// Shrink current window by n rows (stacked) or columns (side by
+    /// side), giving them to the windows beside it
+    pub fn shrink_window(&mut self, kind: SplitKind, n: u16) -> bool {
+        self.resize_window(kind, -i32::from(n))
+    }
 
-        self.recalculate_window_positions();
-        self.display.force_redraw();
+    /// Helper: resize the current window, false if there is no window
+    /// beside it or one would get too small
+    fn resize_window(&mut self, kind: SplitKind, delta: i32) -> bool {
+        let area = self.layout_area;
+        if !self.layout.resize(self.current_window, kind, delta, area) {
+            return false;
+        }
+        self.apply_layout();
         true
     }
 
@@ -1831,42 +3354,35 @@ impl EditorState {
 
         let start_line = self.current_window().cursor_line();
         let start_col = self.current_window().cursor_col();
-        let line_count = self.current_buffer().line_count();
+        let last_line = self.current_buffer().visible_end().0;
         let pattern = self.query_replace.search.clone();
 
         // Search forward from current position
-        for line_idx in start_line..line_count {
-            if let Some(line) = self.current_buffer().line(line_idx) {
-                let text = line.text();
-                let search_start = if line_idx == start_line {
-                    start_col
-                } else {
-                    0
-                };
-
-                if search_start < text.len() {
-                    if let Some(pos) = text[search_start..].find(&pattern) {
-                        let match_col = search_start + pos;
-                        self.current_window_mut().set_cursor(line_idx, match_col);
-                        self.ensure_cursor_visible();
-
-                        // Show prompt for this match
-                        if self.query_replace.replace_all {
-                            // Auto-replace without prompting
-                            self.query_replace_do_replace();
-                            return self.query_replace_next();
-                        } else {
-                            let search = &self.query_replace.search;
-                            let replace = &self.query_replace.replace;
-                            self.display.set_message(&format!(
-                                "Query replacing {} with {}: (y/n/!/q/?)",
-                                search, replace
-                            ));
-                        }
-                        return true;
-                    }
-                }
+        let found = (start_line..=last_line)
+            .flat_map(|line_idx| {
+                self.visible_matches(line_idx, &pattern)
+                    .into_iter()
+                    .map(move |col| (line_idx, col))
+            })
+            .find(|&pos| pos >= (start_line, start_col));
+        if let Some((line_idx, match_col)) = found {
+            self.current_window_mut().set_cursor(line_idx, match_col);
+            self.ensure_cursor_visible();
+
+            // Show prompt for this match
+            if self.query_replace.replace_all {
+                // Auto-replace without prompting
+                self.query_replace_do_replace();
+                return self.query_replace_next();
+            } else {
+                let search = &self.query_replace.search;
+                let replace = &self.query_replace.replace;
+                self.display.set_message(format!(
+                    "Query replacing {} with {}: (y/n/!/q/?)",
+                    search, replace
+                ));
             }
+            return true;
         }
 
         // No more matches - wrap to beginning? For now, just end
@@ -1883,19 +3399,9 @@ impl EditorState {
         let search_len = self.query_replace.search.len();
         let replace_str = self.query_replace.replace.clone();
 
-        if let Some(line) = self.current_buffer_mut().line_mut(line_idx) {
-            // Delete the search string
-            let end_col = (col + search_len).min(line.len());
-            line.delete_range(col, end_col);
-            // Insert the replacement - track byte offset for multi-byte chars
-            let mut byte_offset = col;
-            for ch in replace_str.chars() {
-                line.insert_char(byte_offset, ch);
-                byte_offset += ch.len_utf8();
-            }
-        }
-
-        self.current_buffer_mut().set_modified(true);
+        let buffer = self.current_buffer_mut();
+        buffer.delete_range(line_idx, col, col + search_len);
+        buffer.insert_str(line_idx, col, &replace_str);
         self.query_replace.count += 1;
 
         // Move cursor past the replacement (use byte length)
@@ -1956,44 +3462,33 @@ impl EditorState {
     }
 
     /// Replace all occurrences of search with replace in current buffer (non-interactive)
+    ///
+    /// Only the visible part of a narrowed buffer is changed. Searching
+    /// resumes after each replacement, so a replacement that contains the
+    /// search string is not replaced again.
     pub fn replace_all_occurrences(&mut self, search: &str, replace: &str) -> usize {
         if search.is_empty() {
             return 0;
         }
 
         let mut count = 0;
-        let line_count = self.current_buffer().line_count();
-
-        // Process each line
-        for line_idx in 0..line_count {
-            loop {
-                // Find next occurrence in this line
-                let found = if let Some(line) = self.current_buffer().line(line_idx) {
-                    line.text().find(search)
-                } else {
-                    None
-                };
-
-                if let Some(col) = found {
-                    // Perform replacement
-                    if let Some(line) = self.current_buffer_mut().line_mut(line_idx) {
-                        let end_col = col + search.len();
-                        line.delete_range(col, end_col);
-                        let mut byte_offset = col;
-                        for ch in replace.chars() {
-                            line.insert_char(byte_offset, ch);
-                            byte_offset += ch.len_utf8();
-                        }
-                    }
-                    count += 1;
-                } else {
-                    break;
-                }
+        let mut line_idx = self.current_buffer().visible_start().0;
+
+        // Process each line (the end of a narrowing moves as lines change)
+        while line_idx <= self.current_buffer().visible_end().0 {
+            let mut from = 0;
+            while let Some(col) = self
+                .visible_matches(line_idx, search)
+                .into_iter()
+                .find(|&col| col >= from)
+            {
+                let buffer = self.current_buffer_mut();
+                buffer.delete_range(line_idx, col, col + search.len());
+                buffer.insert_str(line_idx, col, replace);
+                from = col + replace.len();
+                count += 1;
             }
-        }
-
-        if count > 0 {
-            self.current_buffer_mut().set_modified(true);
+            line_idx += 1;
         }
 
         count
@@ -2026,6 +3521,19 @@ impl EditorState {
 
         content.push_str(&format!("\n{} bindings total\n", bindings.len()));
 
+        // Commands run only with M-x
+        let unbound: Vec<&str> = self.keytab.command_names()
+            .into_iter()
+            .filter(|name| self.keytab.keys_for(name).is_empty())
+            .collect();
+        if !unbound.is_empty() {
+            content.push_str("\nCommands without a key (run with M-x)\n");
+            content.push_str("=====================================\n\n");
+            for name in unbound {
+                content.push_str(&format!("{}\n", name));
+            }
+        }
+
         // Find or create the *Help* buffer
         let help_buf_name = "*Help*";
         if let Some(idx) = self.buffers.iter().position(|b| b.name() == help_buf_name) {
@@ -2092,29 +3600,64 @@ impl EditorState {
             ));
         }
 
-        // Find or create the *Buffer List* buffer
-        let list_buf_name = "*Buffer List*";
-        if let Some(idx) = self.buffers.iter().position(|b| b.name() == list_buf_name) {
-            // Update existing buffer
-            self.buffers[idx].set_content(&content);
-            // Switch to it
-            if let Some(window) = self.windows.get_mut(self.current_window) {
-                window.set_buffer_idx(idx);
-                window.set_cursor(0, 0);
+        self.show_generated_buffer("*Buffer List*", &content);
+        self.display.set_message("");
+    }
+
+    /// Show generated text in a named buffer, creating or replacing it
+    pub fn show_generated_buffer(&mut self, name: &str, content: &str) {
+        let idx = match self.buffers.iter().position(|b| b.name() == name) {
+            Some(idx) => {
+                self.buffers[idx].set_content(content);
+                idx
             }
-        } else {
-            // Create new buffer
-            let buffer = Buffer::from_content(list_buf_name, &content);
-            self.buffers.push(buffer);
-            let idx = self.buffers.len() - 1;
-            if let Some(window) = self.windows.get_mut(self.current_window) {
-                window.set_buffer_idx(idx);
-                window.set_cursor(0, 0);
+            None => {
+                self.buffers.push(Buffer::from_content(name, content));
+                self.buffers.len() - 1
             }
+        };
+        if let Some(window) = self.windows.get_mut(self.current_window) {
+            window.set_buffer_idx(idx);
+            window.set_cursor(0, 0);
         }
+        self.display.force_redraw();
+    }
 
+    /// Show the current buffer's undo history in the *Undo Tree* buffer
+    pub fn show_undo_tree(&mut self) {
+        let source = self.current_buffer().name().to_string();
+        let tree = self.current_buffer().undo_tree();
+        let header = format!("Undo tree for {} (* = current state)\n\n", source);
+        // Line of the source buffer's current state, found before the
+        // window switches to *Undo Tree*
+        let current_line = tree.render_line(tree.current()).map(|line| line + header.lines().count());
+        let mut content = header;
+        content.push_str(&tree.render());
+        content.push_str("\nMove to a state and type C-x M-u to jump there\n");
+
+        self.show_generated_buffer("*Undo Tree*", &content);
+
+        // Put the cursor on the current state
+        if let Some(line) = current_line {
+            self.current_window_mut().set_cursor(line, 0);
+        }
+        self.undo_tree_source = Some(source);
+        self.ensure_cursor_visible();
         self.display.force_redraw();
-        self.display.set_message("");
+    }
+
+    /// Move the current buffer to a state in its undo tree
+    pub fn goto_undo_state(&mut self, state: usize) {
+        if state >= self.current_buffer().undo_tree().len() {
+            self.display.set_message(format!("No undo state {}", state));
+            return;
+        }
+        if let Some((line, col)) = self.current_buffer_mut().goto_undo_state(state) {
+            self.current_window_mut().set_cursor(line, col);
+        }
+        self.invalidate_syntax_from(0);
+        self.ensure_cursor_visible();
+        self.display.set_message(format!("Undo state {}", state));
     }
 
     /// Execute a shell command and display output in a buffer
@@ -2175,7 +3718,16 @@ impl EditorState {
         self.display.set_message(&format!("Shell command: {}", command));
     }
 
+    /// Command names with the chars of `input` in order, best first
+    fn command_matches(&self, input: &str) -> Vec<String> {
+        let names: Vec<String> = self.keytab.command_names().iter().map(|n| n.to_string()).collect();
+        crate::completion::fuzzy_matches(input, &names).into_iter().map(String::from).collect()
+    }
+
     /// Execute a command by name (M-x)
+    ///
+    /// Only an exact name runs; for any other the closest names are
+    /// suggested.
     pub fn execute_named_command(&mut self, name: &str) {
         // Look up the command by name
         let cmd_fn = self.keytab.lookup_by_name(name);
@@ -2189,14 +3741,9 @@ impl EditorState {
                 }
             }
         } else {
-            // Try to find partial matches for a helpful message
-            let names = self.keytab.command_names();
-            let matches: Vec<&str> = names.iter()
-                .filter(|n| n.contains(name))
-                .take(3)
-                .copied()
-                .collect();
-
+            // The best fuzzy matches make a helpful message
+            let matches = self.command_matches(name);
+            let matches = &matches[..matches.len().min(3)];
             if matches.is_empty() {
                 self.display.set_message(&format!("Unknown command: {}", name));
             } else {
@@ -2325,54 +3872,36 @@ impl EditorState {
 
     /// Write buffer to a new filename (Save As)
     pub fn write_file(&mut self, filename: &str) {
-        use std::fs::File;
-        use std::io::Write;
         use std::path::PathBuf;
 
         let path = PathBuf::from(filename);
-
-        // Collect buffer content
-        let mut content = String::new();
         let line_count = self.current_buffer().line_count();
-        for i in 0..line_count {
-            if let Some(line) = self.current_buffer().line(i) {
-                content.push_str(line.text());
-                if i + 1 < line_count {
-                    content.push('\n');
-                }
-            }
-        }
 
-        match File::create(&path) {
-            Ok(mut file) => {
-                match file.write_all(content.as_bytes()) {
-                    Ok(()) => {
-                        // Delete old auto-save file if there was a previous filename
-                        if let Some(old_path) = self.current_buffer().filename() {
-                            self.delete_auto_save_file(old_path);
-                        }
-                        // Update buffer's filename and clear modified flag
-                        self.current_buffer_mut().set_filename(path.clone());
-                        self.current_buffer_mut().set_modified(false);
-                        // Delete auto-save file for new path too
-                        self.delete_auto_save_file(&path);
-                        // Update buffer name to match new filename
-                        let name = path.file_name()
-                            .map(|s| s.to_string_lossy().to_string())
-                            .unwrap_or_else(|| filename.to_string());
-                        self.current_buffer_mut().set_name(&name);
-                        self.display.set_message(&format!(
-                            "Wrote {} lines to {}",
-                            line_count, filename
-                        ));
-                    }
-                    Err(e) => {
-                        self.display.set_message(&format!("Error writing file: {}", e));
-                    }
+        // Write through the buffer so encoding and line endings are kept
+        match self.current_buffer().write_to(&path) {
+            Ok(()) => {
+                // Delete old auto-save file if there was a previous filename
+                if let Some(old_path) = self.current_buffer().filename() {
+                    self.delete_auto_save_file(old_path);
                 }
+                // Update buffer's filename and clear modified flag
+                self.current_buffer_mut().set_filename(path.clone());
+                self.current_buffer_mut().set_modified(false);
+                // Delete auto-save file for new path too
+                self.delete_auto_save_file(&path);
+                // Update buffer name to match new filename
+                let name = path.file_name()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_else(|| filename.to_string());
+                self.current_buffer_mut().set_name(&name);
+                self.note_buffer_saved(self.current_window().buffer_idx());
+                self.display.set_message(&format!(
+                    "Wrote {} lines to {}",
+                    line_count, filename
+                ));
             }
             Err(e) => {
-                self.display.set_message(&format!("Cannot create {}: {}", filename, e));
+                self.display.set_message(&format!("Error writing file: {}", e));
             }
         }
     }
@@ -2420,13 +3949,15 @@ impl EditorState {
         use std::io::Write;
         use std::process::{Command, Stdio};
 
-        // Collect buffer content
+        // Collect buffer content (the narrowed part, if narrowed)
+        let (first_line, start_col) = self.current_buffer().visible_start();
+        let (last_line, end_col) = self.current_buffer().visible_end();
         let mut content = String::new();
-        let line_count = self.current_buffer().line_count();
-        for i in 0..line_count {
-            if let Some(line) = self.current_buffer().line(i) {
-                content.push_str(line.text());
-                if i + 1 < line_count {
+        for i in first_line..=last_line {
+            let buffer = self.current_buffer();
+            if let (Some(line), Some((from, to))) = (buffer.line(i), buffer.visible_span(i)) {
+                content.push_str(line.safe_slice(from, to));
+                if i < last_line {
                     content.push('\n');
                 }
             }
@@ -2461,14 +3992,25 @@ impl EditorState {
                     Ok(output) => {
                         let new_content = String::from_utf8_lossy(&output.stdout);
 
-                        // Replace buffer content
-                        self.current_buffer_mut().set_content(&new_content);
-                        self.current_window_mut().set_cursor(0, 0);
-                        self.current_window_mut().set_top_line(0);
+                        // Replace buffer content; a narrowed buffer keeps the
+                        // text outside the narrowing
+                        let new_line_count = if self.current_buffer().is_narrowed() {
+                            let new_content = new_content.strip_suffix('\n').unwrap_or(&new_content);
+                            crate::command::delete_region(self, first_line, start_col, last_line, end_col);
+                            let (end_line, _) = self.insert_text_at(first_line, start_col, new_content);
+                            self.current_window_mut().set_cursor(first_line, start_col);
+                            self.current_window_mut().set_top_line(first_line);
+                            end_line - first_line + 1
+                        } else {
+                            self.current_buffer_mut().set_content(&new_content);
+                            self.current_window_mut().set_cursor(0, 0);
+                            self.current_window_mut().set_top_line(0);
+                            self.current_buffer().line_count()
+                        };
                         self.current_buffer_mut().set_modified(true);
+                        self.invalidate_syntax_from(first_line);
                         self.display.force_redraw();
 
-                        let new_line_count = self.current_buffer().line_count();
                         self.display.set_message(&format!(
                             "Filter complete: {} lines",
                             new_line_count
@@ -2665,6 +4207,36 @@ impl EditorState {
         }
     }
 
+    /// Run the timers that are due
+    ///
+    /// A message shown by a timer gets its own timeout, since there was
+    /// no input to start one.
+    fn run_timers(&mut self) {
+        let message = self.display.message().map(str::to_string);
+        for callback in self.timers.due(Instant::now()) {
+            callback(self);
+        }
+        if self.display.message().is_some() && self.display.message() != message.as_deref() {
+            self.timers.restart(self.message_timer, Instant::now());
+        }
+    }
+
+    /// Clear the message after a while without keys, unless the
+    /// minibuffer is reading something (message timeout timer)
+    fn expire_message(&mut self) {
+        let reading = self.prompt.active
+            || self.search.active
+            || self.query_replace.active
+            || self.input.is_pending()
+            || self.prefix_arg.active
+            || self.quote_pending
+            || self.register_pending.is_some()
+            || self.pending_quit;
+        if !reading {
+            self.display.clear_message();
+        }
+    }
+
     /// Generate auto-save filename for a buffer
     /// Emacs style: /path/to/file.txt -> /path/to/#file.txt#
     pub fn auto_save_path(path: &PathBuf) -> PathBuf {
@@ -2678,29 +4250,22 @@ impl EditorState {
         PathBuf::from(format!("#{}#", path.display()))
     }
 
-    /// Check if it's time to auto-save and do it if needed
+    /// Auto-save if enabled (run by the auto-save timer)
     pub fn check_auto_save(&mut self) {
-        if !self.auto_save_enabled {
-            return;
-        }
-
-        let now = Instant::now();
-        if now.duration_since(self.last_auto_save) < self.auto_save_interval {
-            return;
+        if self.auto_save_enabled {
+            self.do_auto_save();
         }
-
-        self.last_auto_save = now;
-        self.do_auto_save();
     }
 
-    /// Perform auto-save on all modified buffers with filenames
+    /// Perform auto-save on all buffers with filenames changed since
+    /// they were last saved or auto-saved
     fn do_auto_save(&mut self) {
         let mut saved_count = 0;
 
-        for buffer in &self.buffers {
-            // Only auto-save modified buffers that have a filename
+        for buffer in &mut self.buffers {
+            // Only auto-save changed buffers that have a filename
             // Skip special buffers (names starting with *)
-            if !buffer.is_modified() {
+            if !buffer.auto_save_due() {
                 continue;
             }
             if buffer.name().starts_with('*') {
@@ -2709,6 +4274,7 @@ impl EditorState {
             if let Some(path) = buffer.filename() {
                 let auto_path = Self::auto_save_path(path);
                 if buffer.write_to(&auto_path).is_ok() {
+                    buffer.set_auto_saved();
                     saved_count += 1;
                 }
             }
@@ -2730,9 +4296,133 @@ impl EditorState {
         self.auto_save_enabled = !self.auto_save_enabled;
         if self.auto_save_enabled {
             self.display.set_message("Auto-save enabled");
-            self.last_auto_save = Instant::now();
+            self.timers.restart(self.auto_save_timer, Instant::now());
         } else {
             self.display.set_message("Auto-save disabled");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_editor(text: &str) -> EditorState {
+        let mut editor = EditorState::new(Terminal::headless(80, 24));
+        editor.osc52_clipboard = false;
+        editor.buffers[0] = Buffer::from_content("test", text);
+        editor
+    }
+
+    #[test]
+    fn test_finished_search_is_added_to_history() {
+        let mut editor = test_editor("one two three");
+        editor.start_search(SearchDirection::Forward);
+        editor.search.pattern.push_str("two");
+        editor.end_search(false);
+        assert_eq!(editor.history.entries(HistoryKind::Search), ["two"]);
+
+        // An aborted search isn't kept
+        editor.start_search(SearchDirection::Forward);
+        editor.search.pattern.push_str("three");
+        editor.end_search(true);
+        assert_eq!(editor.history.entries(HistoryKind::Search), ["two"]);
+    }
+
+    fn text(editor: &EditorState) -> String {
+        editor.current_buffer().terminated_lines().map(|(text, _)| text).collect::<Vec<_>>().join("\n")
+    }
+
+    fn cursor(editor: &EditorState) -> (usize, usize) {
+        (editor.current_window().cursor_line(), editor.current_window().cursor_col())
+    }
+
+    #[test]
+    fn test_mark_next_like_this() {
+        let mut editor = test_editor("foo bar foo\nfoo");
+        editor.current_window_mut().set_cursor(0, 1);
+        editor.handle_key(Key::meta('n')).unwrap();
+        assert_eq!(editor.cursor_positions(), [(0, 9)]);
+        editor.handle_key(Key::meta('n')).unwrap();
+        assert_eq!(editor.cursor_positions(), [(0, 9), (1, 1)]);
+        editor.handle_key(Key::meta('n')).unwrap();
+        assert_eq!(editor.cursor_positions(), [(0, 9), (1, 1)]);
+
+        // A region is looked for as it is
+        let mut editor = test_editor("a-b a-b a");
+        editor.set_mark_at(0, 0);
+        editor.current_window_mut().set_cursor(0, 3);
+        editor.handle_key(Key::meta('n')).unwrap();
+        assert_eq!(editor.cursor_positions(), [(0, 7)]);
+    }
+
+    #[test]
+    fn test_edit_lines() {
+        let mut editor = test_editor("abcd\nab\nabcd\nabcd");
+        editor.set_mark_at(0, 1);
+        editor.current_window_mut().set_cursor(2, 3);
+        editor.handle_key(Key::ctlx_meta('e')).unwrap();
+        assert_eq!(editor.cursor_positions(), [(0, 3), (1, 2)]);
+        assert!(editor.mark().is_none());
+    }
+
+    #[test]
+    fn test_insert_and_delete_at_cursors() {
+        // Two cursors on one line and the main cursor on the next
+        let mut editor = test_editor("foo bar\nbaz");
+        editor.current_window_mut().set_cursor(1, 0);
+        editor.add_cursor(0, 0);
+        editor.add_cursor(0, 4);
+        editor.handle_key(Key('x' as u32)).unwrap();
+        assert_eq!(text(&editor), "xfoo xbar\nxbaz");
+        assert_eq!(editor.cursor_positions(), [(0, 1), (0, 6)]);
+        assert_eq!(cursor(&editor), (1, 1));
+
+        editor.handle_key(Key(0x7f)).unwrap();
+        assert_eq!(text(&editor), "foo bar\nbaz");
+        editor.handle_key(Key::ctrl('d')).unwrap();
+        assert_eq!(text(&editor), "oo ar\naz");
+    }
+
+    #[test]
+    fn test_overlapping_cursors_merge() {
+        let mut editor = test_editor("abc");
+        editor.current_window_mut().set_cursor(0, 2);
+        editor.add_cursor(0, 1);
+        editor.handle_key(Key(0x7f)).unwrap();
+        assert_eq!(text(&editor), "c");
+        assert!(editor.cursors.is_empty());
+        assert_eq!(cursor(&editor), (0, 0));
+        assert!(!editor.add_cursor(0, 0));
+    }
+
+    #[test]
+    fn test_kill_and_yank_at_cursors() {
+        let mut editor = test_editor("one\ntwo\nthree");
+        editor.kill_ring = vec!["old".to_string(), "new".to_string()];
+        editor.add_cursor(1, 0);
+        assert_eq!(editor.cursors[0].kill_ring, ["new"]);
+
+        editor.handle_key(Key::ctrl('k')).unwrap();
+        assert_eq!(text(&editor), "\n\nthree");
+        assert_eq!(editor.cursors[0].kill_ring, ["new", "two"]);
+        assert_eq!(editor.kill_ring, ["old", "new", "one"]);
+
+        // Each cursor yanks what it killed
+        editor.handle_key(Key::ctrl('e')).unwrap();
+        editor.handle_key(Key::ctrl('y')).unwrap();
+        assert_eq!(text(&editor), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn test_rectangle_columns_use_tab_width() {
+        let mut editor = test_editor("a\tbc\nxxxxxyz");
+        editor.display.tab_width = 4;
+        // Columns 4 to 6: what follows the tab, and the last x and the y
+        editor.set_mark_at(0, 2);
+        editor.current_window_mut().set_cursor(1, 6);
+        editor.handle_key(Key::ctlx_r('k')).unwrap();
+        assert_eq!(editor.killed_rectangle, ["bc", "xy"]);
+        assert_eq!(text(&editor), "a\t\nxxxxz");
+    }
+}
@@ -0,0 +1,453 @@
+//! On-disk file format - encoding, line endings, final newline and BOM
+//!
+//! Buffers hold lines without their terminators. To write a file back
+//! exactly as it was read, the buffer records the text encoding, the
+//! line-ending style, whether the last line was terminated and whether the
+//! file started with a byte order mark. Files with mixed line endings keep a
+//! per-line override (see `Line::eol`) for every line that differs from the
+//! dominant style.
+
+use std::io::{self, BufRead, Write};
+
+/// UTF-8 byte order mark
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// UTF-16 little-endian byte order mark
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+
+/// UTF-16 big-endian byte order mark
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Windows-1252 characters for bytes 0x80-0x9F
+///
+/// The five bytes Windows-1252 leaves undefined map to the matching C1
+/// control characters, so every byte decodes and re-encodes unchanged.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// A text encoding used to read and write a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1: every byte is the code point of the same value
+    Latin1,
+    /// Windows-1252: Latin-1 with printable characters in 0x80-0x9F
+    Windows1252,
+    /// Binary data: bytes shown as Latin-1 code points, saved back unchanged
+    Raw,
+}
+
+impl Encoding {
+    /// Name shown in the mode line and accepted by `from_name`
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+            Encoding::Windows1252 => "windows-1252",
+            Encoding::Raw => "raw",
+        }
+    }
+
+    /// Parse an encoding name (case-insensitive, common aliases accepted)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" | "utf-16" | "utf16" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            "raw" | "binary" | "raw-text" => Some(Encoding::Raw),
+            _ => None,
+        }
+    }
+
+    /// Byte order mark written for this encoding when the format has one
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => UTF8_BOM,
+            Encoding::Utf16Le => UTF16LE_BOM,
+            Encoding::Utf16Be => UTF16BE_BOM,
+            _ => b"",
+        }
+    }
+
+    /// Identify an encoding from a byte order mark at the start of `head`
+    pub fn from_bom(head: &[u8]) -> Option<Self> {
+        if head.starts_with(UTF8_BOM) {
+            Some(Encoding::Utf8)
+        } else if head.starts_with(UTF16LE_BOM) {
+            Some(Encoding::Utf16Le)
+        } else if head.starts_with(UTF16BE_BOM) {
+            Some(Encoding::Utf16Be)
+        } else {
+            None
+        }
+    }
+
+    /// Pick a single-byte encoding for data that is not valid UTF-8
+    ///
+    /// NUL bytes almost never appear in text, so their presence means the
+    /// file is treated as raw binary rather than Windows-1252.
+    pub fn detect_single_byte(data: &[u8]) -> Self {
+        if data.contains(&0) {
+            Encoding::Raw
+        } else {
+            Encoding::Windows1252
+        }
+    }
+
+    /// Decode bytes (without BOM) into a string
+    pub fn decode(self, data: &[u8]) -> io::Result<String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(data.to_vec())
+                .map_err(|_| self.decode_error()),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                if !data.len().is_multiple_of(2) {
+                    return Err(self.decode_error());
+                }
+                let units = data.chunks_exact(2).map(|pair| {
+                    if self == Encoding::Utf16Le {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    }
+                });
+                char::decode_utf16(units)
+                    .collect::<Result<String, _>>()
+                    .map_err(|_| self.decode_error())
+            }
+            Encoding::Latin1 | Encoding::Raw => Ok(data.iter().map(|&b| b as char).collect()),
+            Encoding::Windows1252 => Ok(data
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect()),
+        }
+    }
+
+    /// Encode a string, failing on characters the encoding cannot represent
+    pub fn encode(self, text: &str, out: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            Encoding::Utf8 => out.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => {
+                for unit in text.encode_utf16() {
+                    out.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            Encoding::Utf16Be => {
+                for unit in text.encode_utf16() {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            Encoding::Latin1 | Encoding::Raw => {
+                for ch in text.chars() {
+                    let byte = u8::try_from(u32::from(ch)).map_err(|_| self.encode_error(ch))?;
+                    out.push(byte);
+                }
+            }
+            Encoding::Windows1252 => {
+                for ch in text.chars() {
+                    let byte = match u32::from(ch) {
+                        c @ (0..=0x7F | 0xA0..=0xFF) => c as u8,
+                        _ => match WINDOWS_1252_HIGH.iter().position(|&h| h == ch) {
+                            Some(i) => 0x80 + i as u8,
+                            None => return Err(self.encode_error(ch)),
+                        },
+                    };
+                    out.push(byte);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Error for data that is not valid in this encoding
+    pub fn decode_error(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("file is not valid {}", self.name()),
+        )
+    }
+
+    /// Error for a character this encoding cannot represent
+    fn encode_error(self, ch: char) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("character {:?} (U+{:04X}) cannot be encoded in {}", ch, u32::from(ch), self.name()),
+        )
+    }
+}
+
+/// A line terminator style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// Unix `\n`
+    #[default]
+    Lf,
+    /// DOS/Windows `\r\n`
+    Crlf,
+    /// Classic Mac `\r`
+    Cr,
+}
+
+impl LineEnding {
+    /// Terminator text
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// Short name shown in the mode line
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    /// Parse a style name (case-insensitive; accepts unix/dos/mac aliases)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Some(LineEnding::Lf),
+            "crlf" | "dos" | "windows" => Some(LineEnding::Crlf),
+            "cr" | "mac" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+}
+
+/// How a buffer's text is laid out on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFormat {
+    /// Text encoding
+    pub encoding: Encoding,
+    /// Dominant line ending, used for lines without an override
+    pub line_ending: LineEnding,
+    /// True if some lines carry a different line ending
+    pub mixed: bool,
+    /// True if the last line is followed by a line ending
+    pub final_newline: bool,
+    /// True if the file starts with a byte order mark
+    pub bom: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            mixed: false,
+            final_newline: true,
+            bom: false,
+        }
+    }
+}
+
+impl FileFormat {
+    /// Format for an empty or unterminated file
+    pub fn unterminated() -> Self {
+        Self {
+            final_newline: false,
+            ..Self::default()
+        }
+    }
+
+    /// Mode line indicator, e.g. "CRLF", "LF BOM", "Mixed", "latin-1 LF"
+    pub fn indicator(&self) -> String {
+        let mut s = String::new();
+        if self.encoding != Encoding::Utf8 {
+            s.push_str(self.encoding.name());
+            s.push(' ');
+        }
+        s.push_str(if self.mixed { "Mixed" } else { self.line_ending.name() });
+        if self.bom {
+            s.push_str(" BOM");
+        }
+        if !self.final_newline {
+            s.push_str(" noeol");
+        }
+        s
+    }
+}
+
+/// Read one raw line into `out`, returning the terminator that ended it
+///
+/// Returns `Ok(None)` at end of input; `out` then holds any unterminated
+/// trailing text. `\r\n` split across buffer refills is still one CRLF.
+pub fn read_raw_line<R: BufRead>(reader: &mut R, out: &mut Vec<u8>) -> io::Result<Option<LineEnding>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|&b| b == b'\n' || b == b'\r') {
+            Some(pos) => {
+                out.extend_from_slice(&buf[..pos]);
+                let is_lf = buf[pos] == b'\n';
+                reader.consume(pos + 1);
+                if is_lf {
+                    return Ok(Some(LineEnding::Lf));
+                }
+                if reader.fill_buf()?.first() == Some(&b'\n') {
+                    reader.consume(1);
+                    return Ok(Some(LineEnding::Crlf));
+                }
+                return Ok(Some(LineEnding::Cr));
+            }
+            None => {
+                let n = buf.len();
+                out.extend_from_slice(buf);
+                reader.consume(n);
+            }
+        }
+    }
+}
+
+/// Pick the most common line ending (ties prefer LF, then CRLF)
+pub fn dominant_ending(counts: [usize; 3]) -> LineEnding {
+    let [lf, crlf, cr] = counts;
+    if lf >= crlf && lf >= cr {
+        LineEnding::Lf
+    } else if crlf >= cr {
+        LineEnding::Crlf
+    } else {
+        LineEnding::Cr
+    }
+}
+
+/// Index into a `[lf, crlf, cr]` count array
+pub fn ending_index(ending: LineEnding) -> usize {
+    match ending {
+        LineEnding::Lf => 0,
+        LineEnding::Crlf => 1,
+        LineEnding::Cr => 2,
+    }
+}
+
+/// Write the encoding's BOM if the format has one
+pub fn write_bom<W: Write>(out: &mut W, format: &FileFormat) -> io::Result<()> {
+    if format.bom {
+        out.write_all(format.encoding.bom())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    fn split(data: &[u8], capacity: usize) -> Vec<(String, Option<LineEnding>)> {
+        let mut reader = BufReader::with_capacity(capacity, Cursor::new(data.to_vec()));
+        let mut result = Vec::new();
+        loop {
+            let mut raw = Vec::new();
+            let ending = read_raw_line(&mut reader, &mut raw).unwrap();
+            if ending.is_none() && raw.is_empty() {
+                break;
+            }
+            result.push((String::from_utf8(raw).unwrap(), ending));
+            if ending.is_none() {
+                break;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_read_raw_line_styles() {
+        let lines = split(b"a\nb\r\nc\rd", 64);
+        assert_eq!(
+            lines,
+            vec![
+                ("a".to_string(), Some(LineEnding::Lf)),
+                ("b".to_string(), Some(LineEnding::Crlf)),
+                ("c".to_string(), Some(LineEnding::Cr)),
+                ("d".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crlf_across_refill() {
+        // Capacity 2 forces "\r" and "\n" into separate reads
+        let lines = split(b"a\r\nb\r\n", 2);
+        assert_eq!(
+            lines,
+            vec![
+                ("a".to_string(), Some(LineEnding::Crlf)),
+                ("b".to_string(), Some(LineEnding::Crlf)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dominant_ending() {
+        assert_eq!(dominant_ending([0, 0, 0]), LineEnding::Lf);
+        assert_eq!(dominant_ending([1, 3, 0]), LineEnding::Crlf);
+        assert_eq!(dominant_ending([1, 1, 2]), LineEnding::Cr);
+    }
+
+    #[test]
+    fn test_indicator() {
+        let mut format = FileFormat::default();
+        assert_eq!(format.indicator(), "LF");
+        format.line_ending = LineEnding::Crlf;
+        format.bom = true;
+        format.final_newline = false;
+        assert_eq!(format.indicator(), "CRLF BOM noeol");
+        format.mixed = true;
+        assert_eq!(format.indicator(), "Mixed BOM noeol");
+        format.encoding = Encoding::Utf16Le;
+        format.final_newline = true;
+        assert_eq!(format.indicator(), "utf-16le Mixed BOM");
+    }
+
+    #[test]
+    fn test_windows_1252_roundtrip_all_bytes() {
+        let data: Vec<u8> = (0..=255).collect();
+        let text = Encoding::Windows1252.decode(&data).unwrap();
+        assert!(text.contains('\u{20AC}'));
+        let mut out = Vec::new();
+        Encoding::Windows1252.encode(&text, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_utf16_decode_and_errors() {
+        let text = Encoding::Utf16Le.decode(b"h\0i\0=\xD8\x00\xDE").unwrap();
+        assert_eq!(text, "hi\u{1F600}");
+        let mut out = Vec::new();
+        Encoding::Utf16Be.encode("hi", &mut out).unwrap();
+        assert_eq!(out, b"\0h\0i");
+
+        // Odd length and unpaired surrogate are hard errors
+        assert!(Encoding::Utf16Le.decode(b"h\0i").is_err());
+        assert!(Encoding::Utf16Le.decode(b"\x00\xD8").is_err());
+    }
+
+    #[test]
+    fn test_encode_rejects_unrepresentable() {
+        let mut out = Vec::new();
+        assert!(Encoding::Latin1.encode("caf\u{e9}", &mut out).is_ok());
+        assert_eq!(out, b"caf\xE9");
+        assert!(Encoding::Latin1.encode("\u{20AC}", &mut out).is_err());
+        assert_eq!(Encoding::from_name("CP1252"), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::detect_single_byte(b"a\0b"), Encoding::Raw);
+    }
+}
@@ -0,0 +1,252 @@
+//! Minibuffer history - earlier inputs to prompts, kept between sessions
+//!
+//! Each kind of prompt has its own list, most recent first. The history
+//! is saved to `~/.uemacs-history` (or `%USERPROFILE%\.uemacs-history` on
+//! Windows) on exit, one section per list:
+//!
+//! ```text
+//! [file]
+//! entry = ~/src/main.rs
+//! entry = ~/notes.txt
+//!
+//! [shell]
+//! entry = cargo build
+//! ```
+//!
+//! Newlines in an entry are written as `\n` and backslashes as `\\`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Most entries kept in each list
+pub const HISTORY_MAX: usize = 100;
+
+/// A history list, shared by the prompts that read the same kind of input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HistoryKind {
+    /// File names
+    File,
+    /// Buffer names
+    Buffer,
+    /// Shell commands
+    Shell,
+    /// M-x command names
+    Command,
+    /// Search strings
+    Search,
+    /// Replacement strings
+    Replace,
+}
+
+impl HistoryKind {
+    /// Every kind, in the order they are saved
+    pub const ALL: [HistoryKind; 6] = [
+        HistoryKind::File,
+        HistoryKind::Buffer,
+        HistoryKind::Shell,
+        HistoryKind::Command,
+        HistoryKind::Search,
+        HistoryKind::Replace,
+    ];
+
+    /// Section name in the history file
+    pub fn name(self) -> &'static str {
+        match self {
+            HistoryKind::File => "file",
+            HistoryKind::Buffer => "buffer",
+            HistoryKind::Shell => "shell",
+            HistoryKind::Command => "command",
+            HistoryKind::Search => "search",
+            HistoryKind::Replace => "replace",
+        }
+    }
+
+    /// Kind for a section name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// Earlier prompt inputs by kind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    lists: HashMap<HistoryKind, Vec<String>>,
+}
+
+impl History {
+    /// Entries of a list, most recent first
+    pub fn entries(&self, kind: HistoryKind) -> &[String] {
+        self.lists.get(&kind).map(|l| l.as_slice()).unwrap_or(&[])
+    }
+
+    /// Add an input to the front of a list, moving it there if it is
+    /// already in the list; empty inputs aren't kept
+    pub fn add(&mut self, kind: HistoryKind, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+        let list = self.lists.entry(kind).or_default();
+        list.retain(|entry| entry != input);
+        list.insert(0, input.to_string());
+        list.truncate(HISTORY_MAX);
+    }
+
+    /// Whether every list is empty
+    pub fn is_empty(&self) -> bool {
+        self.lists.values().all(|l| l.is_empty())
+    }
+}
+
+/// Get the path to the history file
+pub fn history_file_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var("USERPROFILE")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-history"))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("HOME")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-history"))
+    }
+}
+
+/// Escape newlines and backslashes for a one-line value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+/// Undo `escape`
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Parse the history from the file format
+pub fn parse_history(content: &str) -> History {
+    let mut history = History::default();
+    let mut kind = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        // Skip empty lines and comments
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+
+        // Section header names the list (unknown lists are skipped)
+        if trimmed.starts_with('[') && trimmed.ends_with(']') && trimmed.len() >= 2 {
+            kind = HistoryKind::from_name(&trimmed[1..trimmed.len() - 1]);
+            continue;
+        }
+
+        // entry = value (keeping its inner spacing)
+        if let (Some(kind), Some((key, value))) = (kind, line.split_once('=')) {
+            if key.trim() == "entry" {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                let list = history.lists.entry(kind).or_default();
+                if list.len() < HISTORY_MAX {
+                    list.push(unescape(value));
+                }
+            }
+        }
+    }
+    history
+}
+
+/// Format the history for the history file
+pub fn format_history(history: &History) -> String {
+    let mut out = String::new();
+    out.push_str("# uEmacs.rs minibuffer history\n");
+    out.push_str("# One section per list, most recent first\n");
+    for kind in HistoryKind::ALL {
+        let entries = history.entries(kind);
+        if entries.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("\n[{}]\n", kind.name()));
+        for entry in entries {
+            out.push_str(&format!("entry = {}\n", escape(entry)));
+        }
+    }
+    out
+}
+
+/// Load the history from the history file
+pub fn load_history() -> History {
+    history_file_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_history(&content))
+        .unwrap_or_default()
+}
+
+/// Save the history to the history file
+pub fn save_history(history: &History) -> io::Result<()> {
+    let path = match history_file_path() {
+        Some(p) => p,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Could not determine history file path",
+            ))
+        }
+    };
+    let mut file = fs::File::create(&path)?;
+    file.write_all(format_history(history).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut history = History::default();
+        history.add(HistoryKind::Shell, "make");
+        history.add(HistoryKind::Shell, "ls -l");
+        history.add(HistoryKind::Shell, "make");
+        history.add(HistoryKind::Shell, "");
+        assert_eq!(history.entries(HistoryKind::Shell), ["make", "ls -l"]);
+        assert!(history.entries(HistoryKind::File).is_empty());
+
+        for i in 0..HISTORY_MAX {
+            history.add(HistoryKind::Search, &i.to_string());
+        }
+        history.add(HistoryKind::Search, "last");
+        assert_eq!(history.entries(HistoryKind::Search).len(), HISTORY_MAX);
+        assert_eq!(history.entries(HistoryKind::Search)[0], "last");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut history = History::default();
+        assert!(history.is_empty());
+        history.add(HistoryKind::File, "~/src/main.rs");
+        history.add(HistoryKind::Search, " fn main");
+        history.add(HistoryKind::Replace, "a\\nb\nc = d");
+        history.add(HistoryKind::Command, "goto-line");
+        history.add(HistoryKind::Command, "sort-lines");
+        assert_eq!(parse_history(&format_history(&history)), history);
+        // Unknown lists are skipped
+        let parsed = parse_history("[other]\nentry = x\n[buffer]\nentry = *scratch*\n");
+        assert_eq!(parsed.entries(HistoryKind::Buffer), ["*scratch*"]);
+    }
+}
@@ -8,6 +8,8 @@ pub mod key_flags {
     pub const META: u32 = 0x2000_0000;
     pub const CTLX: u32 = 0x4000_0000;
     pub const SPEC: u32 = 0x8000_0000;
+    /// `C-x r` prefix (rectangles and registers), always combined with CTLX
+    pub const CTLX_R: u32 = 0x0100_0000;
 }
 
 /// Represents a key input with modifiers
@@ -45,6 +47,11 @@ impl Key {
         Key(key_flags::CTLX | key_flags::META | ch.to_ascii_lowercase() as u32)
     }
 
+    /// Create a C-x r key (C-x r x); case is kept, as in `C-x r N`
+    pub fn ctlx_r(ch: char) -> Self {
+        Key(key_flags::CTLX | key_flags::CTLX_R | ch as u32)
+    }
+
     /// Create a special key (function keys, etc.)
     pub fn special(code: u32) -> Self {
         Key(key_flags::SPEC | code)
@@ -70,6 +77,11 @@ impl Key {
         self.0 & key_flags::CTLX != 0
     }
 
+    /// Check if this is a C-x r prefixed key
+    pub fn is_ctlx_r(&self) -> bool {
+        self.0 & key_flags::CTLX_R != 0
+    }
+
     /// Check if this is a special key
     pub fn is_special(&self) -> bool {
         self.0 & key_flags::SPEC != 0
@@ -104,7 +116,9 @@ impl Key {
         let mut result = String::new();
 
         // Handle C-x prefix
-        if self.is_ctlx() {
+        if self.is_ctlx_r() {
+            result.push_str("C-x r ");
+        } else if self.is_ctlx() {
             result.push_str("C-x ");
         }
 
@@ -170,7 +184,10 @@ impl Key {
         let mut remaining = s;
 
         // Check for C-x prefix
-        if remaining.starts_with("C-x ") {
+        if remaining.starts_with("C-x r ") {
+            flags |= key_flags::CTLX | key_flags::CTLX_R;
+            remaining = &remaining[6..];
+        } else if remaining.starts_with("C-x ") {
             flags |= key_flags::CTLX;
             remaining = &remaining[4..];
         }
@@ -245,6 +262,8 @@ pub struct InputState {
     meta_pending: bool,
     /// Waiting for C-x Meta continuation (C-x ESC sequence)
     ctlx_meta_pending: bool,
+    /// Waiting for C-x r continuation
+    ctlx_r_pending: bool,
 }
 
 impl InputState {
@@ -253,6 +272,7 @@ impl InputState {
             ctlx_pending: false,
             meta_pending: false,
             ctlx_meta_pending: false,
+            ctlx_r_pending: false,
         }
     }
 
@@ -261,11 +281,12 @@ impl InputState {
         self.ctlx_pending = false;
         self.meta_pending = false;
         self.ctlx_meta_pending = false;
+        self.ctlx_r_pending = false;
     }
 
     /// Check if waiting for continuation key
     pub fn is_pending(&self) -> bool {
-        self.ctlx_pending || self.meta_pending || self.ctlx_meta_pending
+        self.ctlx_pending || self.meta_pending || self.ctlx_meta_pending || self.ctlx_r_pending
     }
 
     /// Check if waiting for C-x continuation
@@ -273,6 +294,11 @@ impl InputState {
         self.ctlx_pending
     }
 
+    /// Check if waiting for C-x r continuation
+    pub fn is_ctlx_r_pending(&self) -> bool {
+        self.ctlx_r_pending
+    }
+
     /// Check if waiting for Meta/ESC continuation
     pub fn is_meta_pending(&self) -> bool {
         self.meta_pending
@@ -302,6 +328,11 @@ impl InputState {
             return self.translate_with_meta(code, modifiers);
         }
 
+        if self.ctlx_r_pending {
+            self.ctlx_r_pending = false;
+            return self.translate_with_ctlx_r(code, modifiers);
+        }
+
         if self.ctlx_pending {
             self.ctlx_pending = false;
             // Check if ESC is pressed after C-x - start C-x M- sequence
@@ -309,6 +340,11 @@ impl InputState {
                 self.ctlx_meta_pending = true;
                 return None; // Wait for next key
             }
+            // C-x r starts the rectangle/register prefix
+            if code == KeyCode::Char('r') && modifiers.is_empty() {
+                self.ctlx_r_pending = true;
+                return None; // Wait for next key
+            }
             return self.translate_with_ctlx(code, modifiers);
         }
 
@@ -346,6 +382,7 @@ impl InputState {
             }
             KeyCode::Enter => Some(Key::ctrl('m')),
             KeyCode::Tab => Some(Key::ctrl('i')),
+            KeyCode::Backspace if alt => Some(Key(key_flags::META | 0x7f)), // M-DEL
             KeyCode::Backspace => Some(Key(0x7f)), // DEL
             KeyCode::Delete => Some(Key::special(0x53)),
             KeyCode::Home => Some(Key::special(0x47)),
@@ -399,6 +436,26 @@ impl InputState {
         }
     }
 
+    fn translate_with_ctlx_r(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Key> {
+        // C-x r <key> -> CTLX | CTLX_R | key, keeping the character's case
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        let alt = modifiers.contains(KeyModifiers::ALT);
+
+        match code {
+            KeyCode::Char(ch) => {
+                let mut key_code = key_flags::CTLX | key_flags::CTLX_R | ch as u32;
+                if ctrl {
+                    key_code = key_flags::CTLX | key_flags::CTLX_R | key_flags::CONTROL | ch.to_ascii_lowercase() as u32;
+                }
+                if alt {
+                    key_code |= key_flags::META;
+                }
+                Some(Key(key_code))
+            }
+            _ => self.translate_normal(code, modifiers).map(|k| Key(k.0 | key_flags::CTLX | key_flags::CTLX_R)),
+        }
+    }
+
     fn translate_with_ctlx_meta(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Key> {
         // C-x ESC <key> -> CTLX | META | key
         let ctrl = modifiers.contains(KeyModifiers::CONTROL);
@@ -451,6 +508,13 @@ mod tests {
         // Test special keys
         let key = Key::special(0x48); // Up
         assert_eq!(Key::from_display_name(&key.display_name()), Some(key));
+
+        // Test C-x r keys, which keep their case
+        let key = Key::ctlx_r('N');
+        assert_eq!(key.display_name(), "C-x r N");
+        assert_eq!(Key::from_display_name(&key.display_name()), Some(key));
+        let key = Key(Key::ctlx_r('w').0 | key_flags::META);
+        assert_eq!(Key::from_display_name(&key.display_name()), Some(key));
     }
 
     #[test]
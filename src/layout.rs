@@ -0,0 +1,492 @@
+//! Window layout tree
+//!
+//! The screen above the minibuffer is divided between the windows by a
+//! tree of splits. Each split gives part of its rectangle to its first
+//! child (the top or left part) and the rest to its second. Windows are
+//! numbered in screen order, which is the order of the leaves, so the
+//! numbers match `EditorState::windows`.
+//!
+//! Each window's rectangle includes its mode line. Side-by-side windows
+//! are separated by a one-column divider drawn right of the left window.
+
+/// Fewest rows a window can have: one line of text and its mode line
+pub const MIN_ROWS: u16 = 2;
+
+/// Fewest columns a window can have
+pub const MIN_COLS: u16 = 10;
+
+/// A rectangle of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    /// First row
+    pub top: u16,
+    /// First column
+    pub left: u16,
+    /// Number of rows
+    pub height: u16,
+    /// Number of columns
+    pub width: u16,
+}
+
+/// How a split divides its rectangle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitKind {
+    /// First part above the second (split-window-below)
+    Stacked,
+    /// First part left of the second, with a divider between them
+    /// (split-window-right)
+    SideBySide,
+}
+
+/// Layout of the windows on screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// A window, by its index in the window list
+    Window(usize),
+    /// Two parts, the first above or left of the second
+    Split {
+        kind: SplitKind,
+        /// Rows or columns of the first part, with its mode line or the
+        /// divider right of it
+        first_size: u16,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Window(0)
+    }
+}
+
+impl Layout {
+    /// Rectangle of each window, indexed by window
+    pub fn rects(&self, area: Rect) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        self.collect_rects(area, &mut rects);
+        rects.sort_by_key(|&(idx, _)| idx);
+        rects.into_iter().map(|(_, rect)| rect).collect()
+    }
+
+    fn collect_rects(&self, area: Rect, rects: &mut Vec<(usize, Rect)>) {
+        match self {
+            Layout::Window(idx) => rects.push((*idx, area)),
+            Layout::Split { kind, first_size, first, second } => {
+                let (first_area, second_area) = split_area(*kind, *first_size, area);
+                first.collect_rects(first_area, rects);
+                second.collect_rects(second_area, rects);
+            }
+        }
+    }
+
+    /// Rectangle of each window in `area`, for a layout sized for
+    /// `sized_for`
+    ///
+    /// If `area` is too small for every window, windows other than `keep`
+    /// are left out, last first, until the rest fit, and get None. The
+    /// layout itself is unchanged, so they come back once the area is big
+    /// enough again.
+    pub fn squeezed_rects(&self, sized_for: Rect, area: Rect, keep: usize) -> Vec<Option<Rect>> {
+        let mut layout = self.clone();
+        let mut shown: Vec<usize> = (0..self.window_count()).collect();
+        while !layout.fits(area) && shown.len() > 1 {
+            let last = shown.len() - 1;
+            let idx = if shown[last] == keep { last - 1 } else { last };
+            layout.remove(idx);
+            shown.remove(idx);
+        }
+        layout.fit(sized_for, area);
+
+        let mut rects = vec![None; self.window_count()];
+        for (idx, rect) in shown.into_iter().zip(layout.rects(area)) {
+            rects[idx] = Some(rect);
+        }
+        rects
+    }
+
+    /// Check if window `idx` is in this part of the layout
+    fn contains(&self, idx: usize) -> bool {
+        match self {
+            Layout::Window(i) => *i == idx,
+            Layout::Split { first, second, .. } => first.contains(idx) || second.contains(idx),
+        }
+    }
+
+    /// Smallest size this part of the layout can have across `kind` (rows
+    /// for stacked, columns for side by side), without a divider right of it
+    fn min_size(&self, kind: SplitKind) -> u16 {
+        match self {
+            Layout::Window(_) => match kind {
+                SplitKind::Stacked => MIN_ROWS,
+                SplitKind::SideBySide => MIN_COLS,
+            },
+            Layout::Split { kind: split_kind, first, second, .. } if *split_kind == kind => {
+                let divider = u16::from(kind == SplitKind::SideBySide);
+                first.min_size(kind) + divider + second.min_size(kind)
+            }
+            Layout::Split { first, second, .. } => first.min_size(kind).max(second.min_size(kind)),
+        }
+    }
+
+    /// Check if every window can have its smallest size in `area`
+    pub fn fits(&self, area: Rect) -> bool {
+        self.min_size(SplitKind::Stacked) <= area.height && self.min_size(SplitKind::SideBySide) <= area.width
+    }
+
+    /// Resize the layout from `old` to `new`, keeping each split's parts
+    /// in proportion as far as the smallest window sizes allow
+    pub fn fit(&mut self, old: Rect, new: Rect) {
+        let (kind, first_size, first, second) = match self {
+            Layout::Window(_) => return,
+            Layout::Split { kind, first_size, first, second } => (*kind, first_size, first, second),
+        };
+        let (old_first, old_second) = split_area(kind, *first_size, old);
+
+        let (old_total, new_total, divider) = match kind {
+            SplitKind::Stacked => (old.height, new.height, 0),
+            SplitKind::SideBySide => (old.width, new.width, 1),
+        };
+        let size = if old_total == 0 {
+            u32::from(*first_size)
+        } else {
+            (u32::from(*first_size) * u32::from(new_total) + u32::from(old_total) / 2) / u32::from(old_total)
+        };
+        let min_first = first.min_size(kind) + divider;
+        let max_first = new_total.saturating_sub(second.min_size(kind)).max(min_first);
+        *first_size = (size.min(u32::from(u16::MAX)) as u16).clamp(min_first, max_first);
+
+        let (new_first, new_second) = split_area(kind, *first_size, new);
+        first.fit(old_first, new_first);
+        second.fit(old_second, new_second);
+    }
+
+    /// Text form of the layout, for saving: `window` for a window, and
+    /// `below SIZE FIRST SECOND` or `right SIZE FIRST SECOND` for a split
+    pub fn to_spec(&self) -> String {
+        match self {
+            Layout::Window(_) => "window".to_string(),
+            Layout::Split { kind, first_size, first, second } => {
+                let name = match kind {
+                    SplitKind::Stacked => "below",
+                    SplitKind::SideBySide => "right",
+                };
+                format!("{} {} {} {}", name, first_size, first.to_spec(), second.to_spec())
+            }
+        }
+    }
+
+    /// Parse the text form from `to_spec`, numbering the windows in
+    /// screen order
+    pub fn from_spec(spec: &str) -> Option<Layout> {
+        let mut words = spec.split_whitespace();
+        let mut count = 0;
+        let layout = Self::parse_words(&mut words, &mut count)?;
+        match words.next() {
+            Some(_) => None,
+            None => Some(layout),
+        }
+    }
+
+    fn parse_words<'a>(words: &mut impl Iterator<Item = &'a str>, count: &mut usize) -> Option<Layout> {
+        let kind = match words.next()? {
+            "window" => {
+                *count += 1;
+                return Some(Layout::Window(*count - 1));
+            }
+            "below" => SplitKind::Stacked,
+            "right" => SplitKind::SideBySide,
+            _ => return None,
+        };
+        let first_size = words.next()?.parse().ok()?;
+        let first = Box::new(Self::parse_words(words, count)?);
+        let second = Box::new(Self::parse_words(words, count)?);
+        Some(Layout::Split { kind, first_size, first, second })
+    }
+
+    /// Number of windows
+    pub fn window_count(&self) -> usize {
+        match self {
+            Layout::Window(_) => 1,
+            Layout::Split { first, second, .. } => first.window_count() + second.window_count(),
+        }
+    }
+
+    /// Change every window number with `f`
+    fn renumber(&mut self, f: &impl Fn(usize) -> usize) {
+        match self {
+            Layout::Window(idx) => *idx = f(*idx),
+            Layout::Split { first, second, .. } => {
+                first.renumber(f);
+                second.renumber(f);
+            }
+        }
+    }
+
+    /// Split window `idx` in two, the new window (numbered `idx + 1`)
+    /// below or right of it; false if the window is too small
+    pub fn split(&mut self, idx: usize, kind: SplitKind, area: Rect) -> bool {
+        let rect = match self.rects(area).get(idx) {
+            Some(rect) => *rect,
+            None => return false,
+        };
+        let first_size = match kind {
+            // Need at least 4 text rows: 1 for each window + mode line
+            SplitKind::Stacked if rect.height >= 5 => (rect.height - 1) / 2 + 1,
+            SplitKind::SideBySide if rect.width > 2 * MIN_COLS => rect.width.div_ceil(2),
+            _ => return false,
+        };
+
+        self.renumber(&|i| if i > idx { i + 1 } else { i });
+        self.replace_window(idx, &mut |_| Layout::Split {
+            kind,
+            first_size,
+            first: Box::new(Layout::Window(idx)),
+            second: Box::new(Layout::Window(idx + 1)),
+        });
+        true
+    }
+
+    /// Replace the leaf of window `idx` with `f(leaf)`
+    fn replace_window(&mut self, idx: usize, f: &mut impl FnMut(Layout) -> Layout) {
+        match self {
+            Layout::Window(i) if *i == idx => {
+                let leaf = std::mem::take(self);
+                *self = f(leaf);
+            }
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.replace_window(idx, f);
+                second.replace_window(idx, f);
+            }
+        }
+    }
+
+    /// Remove window `idx`, giving its space to the other part of its
+    /// split, and renumber the windows after it; false for the only window
+    pub fn remove(&mut self, idx: usize) -> bool {
+        if !self.remove_window(idx) {
+            return false;
+        }
+        self.renumber(&|i| if i > idx { i - 1 } else { i });
+        true
+    }
+
+    fn remove_window(&mut self, idx: usize) -> bool {
+        let (first, second) = match self {
+            Layout::Window(_) => return false,
+            Layout::Split { first, second, .. } => (first, second),
+        };
+        if **first == Layout::Window(idx) {
+            *self = std::mem::take(second.as_mut());
+            true
+        } else if **second == Layout::Window(idx) {
+            *self = std::mem::take(first.as_mut());
+            true
+        } else {
+            first.remove_window(idx) || second.remove_window(idx)
+        }
+    }
+
+    /// Grow window `idx` by `delta` rows or columns (shrink it if
+    /// negative), taking them from the other part of the innermost split
+    /// of `kind` holding it; false if either part would get too small
+    pub fn resize(&mut self, idx: usize, kind: SplitKind, delta: i32, area: Rect) -> bool {
+        self.adjust(idx, kind, false, area, &mut |first_size, in_first, total, min_first, min_second| {
+            let size = i32::from(*first_size) + if in_first { delta } else { -delta };
+            if size < i32::from(min_first) || i32::from(total) - size < i32::from(min_second) {
+                return false;
+            }
+            *first_size = size as u16;
+            true
+        })
+        .unwrap_or(false)
+    }
+
+    /// Move the bottom (stacked) or right (side by side) edge of window
+    /// `idx` by `delta`, as far as the windows on either side allow;
+    /// false if it didn't move
+    pub fn move_edge(&mut self, idx: usize, kind: SplitKind, delta: i32, area: Rect) -> bool {
+        self.adjust(idx, kind, true, area, &mut |first_size, _, total, min_first, min_second| {
+            let max = i32::from(total.saturating_sub(min_second)).max(i32::from(min_first));
+            let size = (i32::from(*first_size) + delta).clamp(i32::from(min_first), max) as u16;
+            let moved = size != *first_size;
+            *first_size = size;
+            moved
+        })
+        .unwrap_or(false)
+    }
+
+    /// Call `f` on the innermost split of `kind` holding window `idx` (in
+    /// its first part, if `first_only`), with the split's first size,
+    /// whether the window is in the first part, the size of the split
+    /// and the smallest sizes of its parts; None if there is no such split
+    fn adjust(
+        &mut self,
+        idx: usize,
+        kind: SplitKind,
+        first_only: bool,
+        area: Rect,
+        f: &mut impl FnMut(&mut u16, bool, u16, u16, u16) -> bool,
+    ) -> Option<bool> {
+        let (split_kind, first_size, first, second) = match self {
+            Layout::Window(_) => return None,
+            Layout::Split { kind, first_size, first, second } => (*kind, first_size, first, second),
+        };
+        let (first_area, second_area) = split_area(split_kind, *first_size, area);
+        let in_first = first.contains(idx);
+        let inner = if in_first {
+            first.adjust(idx, kind, first_only, first_area, f)
+        } else if second.contains(idx) {
+            second.adjust(idx, kind, first_only, second_area, f)
+        } else {
+            return None;
+        };
+        if inner.is_some() || split_kind != kind || (first_only && !in_first) {
+            return inner;
+        }
+
+        let (total, divider) = match kind {
+            SplitKind::Stacked => (area.height, 0),
+            SplitKind::SideBySide => (area.width, 1),
+        };
+        let min_first = first.min_size(kind) + divider;
+        let min_second = second.min_size(kind);
+        Some(f(first_size, in_first, total, min_first, min_second))
+    }
+}
+
+/// Divide a split's area between its parts
+fn split_area(kind: SplitKind, first_size: u16, area: Rect) -> (Rect, Rect) {
+    match kind {
+        SplitKind::Stacked => {
+            let height = first_size.min(area.height);
+            let first = Rect { height, ..area };
+            let second = Rect { top: area.top + height, height: area.height - height, ..area };
+            (first, second)
+        }
+        SplitKind::SideBySide => {
+            let width = first_size.min(area.width);
+            let first = Rect { width: width.saturating_sub(1), ..area };
+            let second = Rect { left: area.left + width, width: area.width - width, ..area };
+            (first, second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rect = Rect { top: 0, left: 0, height: 23, width: 80 };
+
+    #[test]
+    fn test_split_and_remove() {
+        let mut layout = Layout::default();
+        assert!(layout.split(0, SplitKind::SideBySide, SCREEN));
+        assert!(layout.split(0, SplitKind::Stacked, SCREEN));
+        // Window 0 top left, 1 below it, 2 on the right
+        assert_eq!(
+            layout.rects(SCREEN),
+            vec![
+                Rect { top: 0, left: 0, height: 12, width: 39 },
+                Rect { top: 12, left: 0, height: 11, width: 39 },
+                Rect { top: 0, left: 40, height: 23, width: 40 },
+            ]
+        );
+
+        // The window below takes the removed window's rows
+        assert!(layout.remove(0));
+        assert_eq!(
+            layout.rects(SCREEN),
+            vec![Rect { top: 0, left: 0, height: 23, width: 39 }, Rect { top: 0, left: 40, height: 23, width: 40 }]
+        );
+        assert!(layout.remove(1));
+        assert!(!layout.remove(0));
+        assert_eq!(layout, Layout::Window(0));
+    }
+
+    #[test]
+    fn test_resize_limits() {
+        let mut layout = Layout::default();
+        assert!(layout.split(0, SplitKind::Stacked, SCREEN));
+        assert!(layout.resize(1, SplitKind::Stacked, 3, SCREEN));
+        assert_eq!(layout.rects(SCREEN)[1].height, 14);
+        // No side-by-side split to resize, and window 0 can't go below
+        // MIN_ROWS
+        assert!(!layout.resize(0, SplitKind::SideBySide, 1, SCREEN));
+        assert!(!layout.resize(1, SplitKind::Stacked, 8, SCREEN));
+        // Dragging an edge stops at the smallest size
+        assert!(layout.move_edge(0, SplitKind::Stacked, -20, SCREEN));
+        assert_eq!(layout.rects(SCREEN)[0].height, MIN_ROWS);
+        assert!(!layout.move_edge(1, SplitKind::Stacked, 1, SCREEN));
+    }
+
+    #[test]
+    fn test_spec_roundtrip() {
+        let mut layout = Layout::default();
+        assert!(layout.split(0, SplitKind::Stacked, SCREEN));
+        assert!(layout.split(1, SplitKind::SideBySide, SCREEN));
+        let spec = layout.to_spec();
+        assert_eq!(spec, "below 12 window right 40 window window");
+        assert_eq!(Layout::from_spec(&spec), Some(layout.clone()));
+        assert_eq!(layout.window_count(), 3);
+
+        assert_eq!(Layout::from_spec("window"), Some(Layout::Window(0)));
+        assert_eq!(Layout::from_spec("below 12 window"), None);
+        assert_eq!(Layout::from_spec("window window"), None);
+        assert_eq!(Layout::from_spec("right x window window"), None);
+    }
+
+    #[test]
+    fn test_fit_keeps_proportions() {
+        let mut layout = Layout::default();
+        assert!(layout.split(0, SplitKind::SideBySide, SCREEN));
+        assert!(layout.resize(0, SplitKind::SideBySide, -10, SCREEN));
+        assert!(layout.split(1, SplitKind::Stacked, SCREEN));
+        // Left window three eighths of the width, right ones half the height
+        let small = Rect { top: 0, left: 0, height: 11, width: 40 };
+        layout.fit(SCREEN, small);
+        assert_eq!(
+            layout.rects(small),
+            vec![
+                Rect { top: 0, left: 0, height: 11, width: 14 },
+                Rect { top: 0, left: 15, height: 6, width: 25 },
+                Rect { top: 6, left: 15, height: 5, width: 25 },
+            ]
+        );
+        // Too small for the windows to keep their proportions
+        let tiny = Rect { top: 0, left: 0, height: 4, width: 24 };
+        assert!(layout.fits(tiny));
+        layout.fit(small, tiny);
+        assert_eq!(layout.rects(tiny)[0].width, MIN_COLS);
+        assert!(!layout.fits(Rect { height: 3, ..tiny }));
+        assert!(!layout.fits(Rect { width: 20, ..tiny }));
+    }
+
+    #[test]
+    fn test_squeezed_rects_hide_what_does_not_fit() {
+        let mut layout = Layout::default();
+        assert!(layout.split(0, SplitKind::SideBySide, SCREEN));
+        assert!(layout.split(1, SplitKind::Stacked, SCREEN));
+        let full: Vec<_> = layout.rects(SCREEN).into_iter().map(Some).collect();
+        assert_eq!(layout.squeezed_rects(SCREEN, SCREEN, 0), full);
+
+        // Too short for the stacked windows: the last one that isn't kept
+        // goes, and the other right window takes its rows
+        let short = Rect { top: 0, left: 0, height: 3, width: 80 };
+        let rects = layout.squeezed_rects(SCREEN, short, 0);
+        assert_eq!(rects[2], None);
+        assert_eq!(rects[1].map(|r| r.height), Some(3));
+        let rects = layout.squeezed_rects(SCREEN, short, 2);
+        assert_eq!(rects[1], None);
+        assert_eq!(rects[2].map(|r| r.height), Some(3));
+
+        // Too narrow for side-by-side windows: only the kept one is left
+        let narrow = Rect { top: 0, left: 0, height: 23, width: 15 };
+        assert_eq!(layout.squeezed_rects(SCREEN, narrow, 0), vec![Some(narrow), None, None]);
+
+        // The layout is untouched, so everything shows again at full size
+        assert_eq!(layout.squeezed_rects(SCREEN, SCREEN, 0), full);
+    }
+}
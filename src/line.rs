@@ -1,12 +1,62 @@
 //! Line representation and text operations
 
-use unicode_width::UnicodeWidthStr;
+use crate::file_format::LineEnding;
+
+/// Default distance between tab stops, in display columns
+pub const TAB_WIDTH: usize = 8;
+
+/// Display width of a character starting at display column `col`, with
+/// tab stops every `tab_width` columns
+///
+/// A tab advances to the next tab stop; other characters use their
+/// Unicode width (wide CJK characters and emoji take two columns).
+pub fn char_width(ch: char, col: usize, tab_width: usize) -> usize {
+    if ch == '\t' {
+        tab_width - col % tab_width
+    } else {
+        unicode_width::UnicodeWidthChar::width(ch).unwrap_or(1)
+    }
+}
+
+/// Split text into screen rows at most `width` columns wide, returning
+/// the byte offset each row starts at (the first is always 0)
+///
+/// Rows break after the last space or tab that fits, or inside a word
+/// that is wider than a row. Spaces may run past the end of a row rather
+/// than start the next one. Columns (for tab stops) count from the start
+/// of each row.
+pub fn wrap_rows(text: &str, width: usize, tab_width: usize) -> Vec<usize> {
+    let width = width.max(1);
+    let mut starts = vec![0];
+    let mut row_start = 0;
+    let mut col = 0;
+    let mut after_blank = None;
+
+    for (byte_pos, ch) in text.char_indices() {
+        while ch != ' ' && byte_pos > row_start && col + char_width(ch, col, tab_width) > width {
+            row_start = match after_blank {
+                Some(pos) if pos > row_start => pos,
+                _ => byte_pos,
+            };
+            after_blank = None;
+            starts.push(row_start);
+            col = text[row_start..byte_pos].chars().fold(0, |c, ch| c + char_width(ch, c, tab_width));
+        }
+        col += char_width(ch, col, tab_width);
+        if ch == ' ' || ch == '\t' {
+            after_blank = Some(byte_pos + 1);
+        }
+    }
+    starts
+}
 
 /// A single line of text in a buffer
 #[derive(Debug, Clone)]
 pub struct Line {
     /// The text content (without trailing newline)
     text: String,
+    /// Line ending override for files with mixed endings (None = buffer default)
+    eol: Option<LineEnding>,
 }
 
 impl Line {
@@ -14,12 +64,13 @@ impl Line {
     pub fn new() -> Self {
         Self {
             text: String::new(),
+            eol: None,
         }
     }
 
     /// Create a line from a string
     pub fn from_string(s: String) -> Self {
-        Self { text: s }
+        Self { text: s, eol: None }
     }
 
     /// Get the text content
@@ -27,6 +78,16 @@ impl Line {
         &self.text
     }
 
+    /// Get the line ending override, if any
+    pub fn eol(&self) -> Option<LineEnding> {
+        self.eol
+    }
+
+    /// Set the line ending override
+    pub fn set_eol(&mut self, eol: Option<LineEnding>) {
+        self.eol = eol;
+    }
+
     /// Get mutable access to the text
     pub fn text_mut(&mut self) -> &mut String {
         &mut self.text
@@ -43,8 +104,8 @@ impl Line {
     }
 
     /// Get the display width of the line
-    pub fn display_width(&self) -> usize {
-        self.text.width()
+    pub fn display_width(&self, tab_width: usize) -> usize {
+        self.byte_to_col(self.text.len(), tab_width)
     }
 
     /// Insert a character at byte position
@@ -65,14 +126,21 @@ impl Line {
     }
 
     /// Split the line at byte position, returning the remainder
+    ///
+    /// The remainder keeps this line's ending; the new break uses the
+    /// buffer default.
     pub fn split_off(&mut self, byte_pos: usize) -> Line {
         let remainder = self.text.split_off(byte_pos);
-        Line::from_string(remainder)
+        Line {
+            text: remainder,
+            eol: self.eol.take(),
+        }
     }
 
-    /// Append another line's content to this line
+    /// Append another line's content to this line, taking over its ending
     pub fn append(&mut self, other: Line) {
         self.text.push_str(other.text());
+        self.eol = other.eol;
     }
 
     /// Append a string to this line
@@ -87,13 +155,16 @@ impl Line {
 
     /// Get byte position for a given column (display position)
     /// Returns None if column is beyond line end
-    pub fn col_to_byte(&self, col: usize) -> Option<usize> {
+    ///
+    /// A wide character or tab that spans `col` counts as starting before
+    /// it, so the position returned is the character after it.
+    pub fn col_to_byte(&self, col: usize, tab_width: usize) -> Option<usize> {
         let mut current_col = 0;
         for (byte_idx, ch) in self.text.char_indices() {
             if current_col >= col {
                 return Some(byte_idx);
             }
-            current_col += unicode_width::UnicodeWidthChar::width(ch).unwrap_or(1);
+            current_col += char_width(ch, current_col, tab_width);
         }
         if current_col >= col {
             Some(self.text.len())
@@ -103,8 +174,10 @@ impl Line {
     }
 
     /// Get column (display position) for a given byte position
-    pub fn byte_to_col(&self, byte_pos: usize) -> usize {
-        self.text[..byte_pos.min(self.text.len())].width()
+    pub fn byte_to_col(&self, byte_pos: usize, tab_width: usize) -> usize {
+        self.text[..byte_pos.min(self.text.len())]
+            .chars()
+            .fold(0, |col, ch| col + char_width(ch, col, tab_width))
     }
 
     /// Find the nearest valid UTF-8 char boundary at or before `pos`
@@ -202,6 +275,40 @@ mod tests {
         assert_eq!(line.text(), "Hello!");
     }
 
+    #[test]
+    fn test_columns_with_tabs_and_wide_chars() {
+        let line = Line::from("a\tb你c");
+        // 'a' at 0, tab to 8, 'b' at 8, '你' at 9-10, 'c' at 11
+        assert_eq!(line.byte_to_col(1, TAB_WIDTH), 1);
+        assert_eq!(line.byte_to_col(2, TAB_WIDTH), 8);
+        assert_eq!(line.byte_to_col(3, TAB_WIDTH), 9);
+        assert_eq!(line.byte_to_col(6, TAB_WIDTH), 11);
+        assert_eq!(line.display_width(TAB_WIDTH), 12);
+        assert_eq!(line.col_to_byte(8, TAB_WIDTH), Some(2));
+        assert_eq!(line.col_to_byte(4, TAB_WIDTH), Some(2));
+        assert_eq!(line.col_to_byte(10, TAB_WIDTH), Some(6));
+        assert_eq!(line.col_to_byte(12, TAB_WIDTH), Some(7));
+        assert_eq!(line.col_to_byte(13, TAB_WIDTH), None);
+        // Narrower tab stops
+        assert_eq!(line.byte_to_col(2, 4), 4);
+        assert_eq!(line.display_width(4), 8);
+        assert_eq!(line.col_to_byte(3, 4), Some(2));
+    }
+
+    #[test]
+    fn test_wrap_rows() {
+        assert_eq!(wrap_rows("", 4, TAB_WIDTH), vec![0]);
+        assert_eq!(wrap_rows("short", 10, TAB_WIDTH), vec![0]);
+        // Break after the last space that fits
+        assert_eq!(wrap_rows("hello world foo", 8, TAB_WIDTH), vec![0, 6, 12]);
+        // Words wider than a row are split
+        assert_eq!(wrap_rows("abcdefghij", 4, TAB_WIDTH), vec![0, 4, 8]);
+        // Spaces hang past the end rather than start a row
+        assert_eq!(wrap_rows("abcd  ef", 4, TAB_WIDTH), vec![0, 6]);
+        // Wide characters don't straddle rows
+        assert_eq!(wrap_rows("a你好", 4, TAB_WIDTH), vec![0, 4]);
+    }
+
     #[test]
     fn test_utf8_emoji() {
         let line = Line::from("Hello 😀 World");
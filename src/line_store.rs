@@ -0,0 +1,267 @@
+//! Line storage - a rope-like sequence of lines held in fixed-size chunks
+//!
+//! A plain `Vec<Line>` has to shift every following line when one is
+//! inserted or removed, which makes edits near the top of a very large
+//! file slow. `LineStore` keeps lines in chunks of bounded size, so an
+//! insert or remove only shifts lines inside one chunk plus a small index
+//! of chunk start positions. Chunks that grow too big are split and those
+//! that shrink too small are merged into a neighbour, so the index stays
+//! short however the buffer was edited.
+
+use crate::line::Line;
+
+/// Preferred number of lines per chunk
+const CHUNK_SIZE: usize = 512;
+
+/// A chunk is split in half once it grows beyond this many lines
+const MAX_CHUNK_SIZE: usize = CHUNK_SIZE * 2;
+
+/// A chunk is merged into a neighbour once it shrinks below this many lines
+const MIN_CHUNK_SIZE: usize = CHUNK_SIZE / 2;
+
+/// Chunked sequence of lines
+#[derive(Debug, Clone, Default)]
+pub struct LineStore {
+    /// Line chunks, in buffer order
+    chunks: Vec<Vec<Line>>,
+    /// Index of the first line of each chunk
+    starts: Vec<usize>,
+    /// Total number of lines
+    len: usize,
+}
+
+impl LineStore {
+    /// Create an empty line store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a line store from an iterator of lines
+    pub fn from_lines<I: IntoIterator<Item = Line>>(lines: I) -> Self {
+        let mut store = Self::new();
+        for line in lines {
+            store.push(line);
+        }
+        store
+    }
+
+    /// Number of lines
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if there are no lines
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get a line by index
+    pub fn get(&self, idx: usize) -> Option<&Line> {
+        let (chunk, offset) = self.locate(idx)?;
+        self.chunks[chunk].get(offset)
+    }
+
+    /// Get a mutable line by index
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut Line> {
+        let (chunk, offset) = self.locate(idx)?;
+        self.chunks[chunk].get_mut(offset)
+    }
+
+    /// Append a line at the end
+    pub fn push(&mut self, line: Line) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < CHUNK_SIZE => chunk.push(line),
+            _ => {
+                self.starts.push(self.len);
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                chunk.push(line);
+                self.chunks.push(chunk);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Insert a line at index, shifting later lines down
+    ///
+    /// Panics if `idx > len`, like `Vec::insert`.
+    pub fn insert(&mut self, idx: usize, line: Line) {
+        assert!(idx <= self.len, "insert index {} out of range ({})", idx, self.len);
+        if idx == self.len {
+            self.push(line);
+            return;
+        }
+
+        let (chunk_idx, offset) = self.locate(idx).expect("index checked above");
+        let chunk = &mut self.chunks[chunk_idx];
+        chunk.insert(offset, line);
+        self.len += 1;
+
+        if chunk.len() > MAX_CHUNK_SIZE {
+            let tail = chunk.split_off(chunk.len() / 2);
+            self.chunks.insert(chunk_idx + 1, tail);
+            self.starts.insert(chunk_idx + 1, 0);
+        }
+        self.reindex_from(chunk_idx);
+    }
+
+    /// Remove and return the line at index, shifting later lines up
+    ///
+    /// Panics if `idx >= len`, like `Vec::remove`.
+    pub fn remove(&mut self, idx: usize) -> Line {
+        let (chunk_idx, offset) = self
+            .locate(idx)
+            .unwrap_or_else(|| panic!("remove index {} out of range ({})", idx, self.len));
+        let line = self.chunks[chunk_idx].remove(offset);
+        self.len -= 1;
+
+        let changed = self.merge_small(chunk_idx);
+        self.reindex_from(changed);
+        line
+    }
+
+    /// Iterate over all lines in order
+    pub fn iter(&self) -> impl Iterator<Item = &Line> {
+        self.chunks.iter().flatten()
+    }
+
+    /// Iterate mutably over all lines in order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Line> {
+        self.chunks.iter_mut().flatten()
+    }
+
+    /// Drop the chunk at `idx` if it is empty, or merge it into a neighbour
+    /// if it has become small, splitting the result again if it is too big
+    ///
+    /// Returns the first chunk whose start may have moved.
+    fn merge_small(&mut self, idx: usize) -> usize {
+        if self.chunks[idx].is_empty() {
+            self.chunks.remove(idx);
+            self.starts.remove(idx);
+            return idx;
+        }
+        if self.chunks[idx].len() >= MIN_CHUNK_SIZE || self.chunks.len() == 1 {
+            return idx;
+        }
+
+        // Merge with the next chunk, or the previous one for the last chunk
+        let left = if idx + 1 < self.chunks.len() { idx } else { idx - 1 };
+        let right = self.chunks.remove(left + 1);
+        self.starts.remove(left + 1);
+        self.chunks[left].extend(right);
+
+        let chunk = &mut self.chunks[left];
+        if chunk.len() > MAX_CHUNK_SIZE {
+            let tail = chunk.split_off(chunk.len() / 2);
+            self.chunks.insert(left + 1, tail);
+            self.starts.insert(left + 1, 0);
+        }
+        left
+    }
+
+    /// Find the chunk and offset within it for a line index
+    fn locate(&self, idx: usize) -> Option<(usize, usize)> {
+        if idx >= self.len {
+            return None;
+        }
+        let chunk = self.starts.partition_point(|&start| start <= idx) - 1;
+        Some((chunk, idx - self.starts[chunk]))
+    }
+
+    /// Recompute chunk start positions after the chunk at `from` changed size
+    fn reindex_from(&mut self, from: usize) {
+        if let Some(first) = self.starts.first_mut() {
+            *first = 0;
+        }
+        for i in from.max(1)..self.chunks.len() {
+            self.starts[i] = self.starts[i - 1] + self.chunks[i - 1].len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(n: usize) -> LineStore {
+        LineStore::from_lines((0..n).map(|i| Line::from(i.to_string())))
+    }
+
+    fn texts(store: &LineStore) -> Vec<String> {
+        store.iter().map(|l| l.text().to_string()).collect()
+    }
+
+    #[test]
+    fn test_get_across_chunks() {
+        let store = numbered(CHUNK_SIZE * 3 + 7);
+        assert_eq!(store.len(), CHUNK_SIZE * 3 + 7);
+        for idx in [0, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE * 3 + 6] {
+            assert_eq!(store.get(idx).unwrap().text(), idx.to_string());
+        }
+        assert!(store.get(CHUNK_SIZE * 3 + 7).is_none());
+    }
+
+    #[test]
+    fn test_insert_splits_chunk() {
+        let mut store = numbered(CHUNK_SIZE);
+        let mut model: Vec<String> = texts(&store);
+        for i in 0..(MAX_CHUNK_SIZE + 10) {
+            store.insert(1, Line::from(format!("new{}", i)));
+            model.insert(1, format!("new{}", i));
+        }
+        assert!(store.chunks.len() > 1);
+        assert_eq!(texts(&store), model);
+        assert_eq!(store.get(CHUNK_SIZE + 5).unwrap().text(), model[CHUNK_SIZE + 5]);
+    }
+
+    #[test]
+    fn test_remove_drops_empty_chunks() {
+        let mut store = numbered(CHUNK_SIZE * 2);
+        let mut model: Vec<String> = texts(&store);
+        for _ in 0..CHUNK_SIZE {
+            store.remove(0);
+            model.remove(0);
+        }
+        assert_eq!(store.chunks.len(), 1);
+        assert_eq!(texts(&store), model);
+        assert_eq!(store.get(0).unwrap().text(), CHUNK_SIZE.to_string());
+    }
+
+    #[test]
+    fn test_bulk_remove_merges_chunks() {
+        let mut store = numbered(CHUNK_SIZE * 20);
+        let mut model: Vec<String> = texts(&store);
+        // Keep every tenth line, deleting from the end like a region kill
+        for idx in (0..CHUNK_SIZE * 20).rev() {
+            if idx % 10 != 0 {
+                store.remove(idx);
+                model.remove(idx);
+            }
+        }
+        assert_eq!(texts(&store), model);
+        assert_eq!(store.len(), CHUNK_SIZE * 2);
+        assert!(store.chunks.len() <= store.len() / MIN_CHUNK_SIZE);
+        assert!(store.chunks.iter().all(|c| c.len() >= MIN_CHUNK_SIZE));
+        for idx in [0, CHUNK_SIZE, CHUNK_SIZE * 2 - 1] {
+            assert_eq!(store.get(idx).unwrap().text(), (idx * 10).to_string());
+        }
+
+        // Deleting nearly everything leaves one chunk
+        while store.len() > 3 {
+            store.remove(1);
+        }
+        assert_eq!(store.chunks.len(), 1);
+        assert_eq!(store.get(2).unwrap().text(), ((CHUNK_SIZE * 2 - 1) * 10).to_string());
+    }
+
+    #[test]
+    fn test_insert_at_end_and_into_empty() {
+        let mut store = LineStore::new();
+        assert!(store.is_empty());
+        store.insert(0, Line::from("a"));
+        store.insert(1, Line::from("c"));
+        store.insert(1, Line::from("b"));
+        assert_eq!(texts(&store), vec!["a", "b", "c"]);
+        store.get_mut(2).unwrap().append_str("!");
+        assert_eq!(store.get(2).unwrap().text(), "c!");
+    }
+}
@@ -2,17 +2,32 @@
 //!
 //! Based on uEmacs/PK 4.0 by Petri Kutvonen
 
+mod bookmark_store;
 mod buffer;
+mod clipboard;
 mod command;
+mod completion;
 mod config;
+mod desktop_store;
 mod display;
 mod editor;
 mod error;
+mod file_format;
+mod history_store;
 mod input;
+mod layout;
 mod line;
+mod line_store;
 mod macro_store;
+mod marker;
+mod minibuffer;
+mod recent_store;
+mod register;
 mod syntax;
 mod terminal;
+mod timer;
+mod undo;
+mod undo_store;
 mod window;
 
 use std::env;
@@ -21,7 +36,7 @@ use std::process;
 
 use config::Config;
 use editor::EditorState;
-use error::Result;
+use error::{EditorError, Result};
 use terminal::Terminal;
 
 fn main() {
@@ -62,15 +77,35 @@ fn run() -> Result<()> {
     // Apply configuration
     editor.apply_config(&config);
 
+    // Restore the last session
+    let mut restored = 0;
+    if config.desktop {
+        if let Some(desktop) = desktop_store::load_desktop() {
+            restored = editor.restore_desktop(&desktop);
+        }
+    }
+
     // Open file if provided
-    if args.len() > 1 && !args[1].starts_with('-') {
+    let has_file = args.len() > 1 && !args[1].starts_with('-');
+    if has_file {
         let path = PathBuf::from(&args[1]);
-        if let Err(_) = editor.open_file(&path) {
-            // File doesn't exist - create new buffer with that filename
-            editor.open_new_file(&path);
+        if let Err(e) = editor.open_file(&path) {
+            if !path.exists() {
+                // File doesn't exist - create new buffer with that filename
+                editor.open_new_file(&path);
+            } else {
+                // Never stand in an empty buffer for a file we couldn't read:
+                // saving it would overwrite the original
+                return Err(EditorError::Message(format!("{}: {}", path.display(), e)));
+            }
         }
     }
 
+    // Nothing to show: offer the recent files instead of a bare *scratch*
+    if !has_file && restored == 0 && config.startup_recent_files && !editor.recent_files.is_empty() {
+        command::commands::recentf_open(&mut editor, false, 1)?;
+    }
+
     // Run the editor
     editor.run()?;
 
@@ -0,0 +1,210 @@
+//! Markers - buffer positions that follow edits
+//!
+//! A marker is a (line, byte column) position registered with a buffer.
+//! Every mutating buffer method reports its change to the buffer's
+//! `MarkerSet`, which shifts the markers after the edit point so they keep
+//! pointing at the same text. Text inserted exactly at a marker goes after
+//! it, unless the marker was added with `add_advancing`, in which case the
+//! marker moves past the new text (used for the end of a narrowed region).
+//! Text deleted around a marker collapses it to the start of the deletion.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Source of marker ids, unique across all buffers
+static NEXT_MARKER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle to a marker registered with a buffer
+///
+/// Ids are never reused, so looking one up in the wrong buffer (or after it
+/// was removed) simply finds nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarkerId(usize);
+
+/// A registered marker
+#[derive(Debug, Clone)]
+struct Marker {
+    id: MarkerId,
+    /// (line, col) position
+    pos: (usize, usize),
+    /// Whether text inserted at the marker goes before it
+    advance: bool,
+}
+
+/// The markers of one buffer
+#[derive(Debug, Clone, Default)]
+pub struct MarkerSet {
+    /// Registered markers
+    markers: Vec<Marker>,
+}
+
+impl MarkerSet {
+    /// Create an empty marker set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a marker at a position
+    pub fn add(&mut self, line: usize, col: usize) -> MarkerId {
+        self.add_marker(line, col, false)
+    }
+
+    /// Register a marker that moves past text inserted at it
+    pub fn add_advancing(&mut self, line: usize, col: usize) -> MarkerId {
+        self.add_marker(line, col, true)
+    }
+
+    fn add_marker(&mut self, line: usize, col: usize, advance: bool) -> MarkerId {
+        let id = MarkerId(NEXT_MARKER_ID.fetch_add(1, Ordering::Relaxed));
+        self.markers.push(Marker { id, pos: (line, col), advance });
+        id
+    }
+
+    /// Get a marker's position
+    pub fn get(&self, id: MarkerId) -> Option<(usize, usize)> {
+        self.markers.iter().find(|m| m.id == id).map(|m| m.pos)
+    }
+
+    /// Move a marker, returns false if it isn't registered here
+    pub fn set(&mut self, id: MarkerId, line: usize, col: usize) -> bool {
+        match self.markers.iter_mut().find(|m| m.id == id) {
+            Some(marker) => {
+                marker.pos = (line, col);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Unregister a marker
+    pub fn remove(&mut self, id: MarkerId) {
+        self.markers.retain(|m| m.id != id);
+    }
+
+    /// `len` bytes were inserted at (line, col)
+    pub fn insert_text(&mut self, line: usize, col: usize, len: usize) {
+        for m in &mut self.markers {
+            let (l, c) = &mut m.pos;
+            if *l == line && (*c > col || (m.advance && *c == col)) {
+                *c += len;
+            }
+        }
+    }
+
+    /// `len` bytes were deleted from (line, col)
+    pub fn delete_text(&mut self, line: usize, col: usize, len: usize) {
+        for m in &mut self.markers {
+            let (l, c) = &mut m.pos;
+            if *l == line && *c > col {
+                *c = if *c > col + len { *c - len } else { col };
+            }
+        }
+    }
+
+    /// Line `line` was split at `col`
+    pub fn split_line(&mut self, line: usize, col: usize) {
+        for m in &mut self.markers {
+            let (l, c) = &mut m.pos;
+            if *l > line {
+                *l += 1;
+            } else if *l == line && (*c > col || (m.advance && *c == col)) {
+                *l += 1;
+                *c -= col;
+            }
+        }
+    }
+
+    /// Line `line + 1` was appended to line `line`, which was `col` bytes long
+    pub fn join_lines(&mut self, line: usize, col: usize) {
+        for m in &mut self.markers {
+            let (l, c) = &mut m.pos;
+            if *l == line + 1 {
+                *l = line;
+                *c += col;
+            } else if *l > line + 1 {
+                *l -= 1;
+            }
+        }
+    }
+
+    /// A new line was inserted before line `line`
+    pub fn insert_line(&mut self, line: usize) {
+        for m in &mut self.markers {
+            if m.pos.0 >= line {
+                m.pos.0 += 1;
+            }
+        }
+    }
+
+    /// Line `line` was removed; markers on it move to `to`
+    ///
+    /// `to` is given in positions after the removal.
+    pub fn remove_line(&mut self, line: usize, to: (usize, usize)) {
+        for m in &mut self.markers {
+            if m.pos.0 == line {
+                m.pos = to;
+            } else if m.pos.0 > line {
+                m.pos.0 -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markers_follow_text_edits() {
+        let mut set = MarkerSet::new();
+        let before = set.add(0, 2);
+        let at = set.add(0, 4);
+        let after = set.add(0, 8);
+        let other = set.add(1, 5);
+
+        set.insert_text(0, 4, 3);
+        assert_eq!(set.get(before), Some((0, 2)));
+        assert_eq!(set.get(at), Some((0, 4)));
+        assert_eq!(set.get(after), Some((0, 11)));
+        assert_eq!(set.get(other), Some((1, 5)));
+
+        set.delete_text(0, 1, 5);
+        assert_eq!(set.get(before), Some((0, 1)));
+        assert_eq!(set.get(at), Some((0, 1)));
+        assert_eq!(set.get(after), Some((0, 6)));
+
+        set.remove(at);
+        assert_eq!(set.get(at), None);
+        assert!(!set.set(at, 0, 0));
+
+        let end = set.add_advancing(0, 6);
+        set.insert_text(0, 6, 2);
+        assert_eq!(set.get(end), Some((0, 8)));
+        set.split_line(0, 8);
+        assert_eq!(set.get(end), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_markers_follow_line_edits() {
+        let mut set = MarkerSet::new();
+        let head = set.add(0, 1);
+        let tail = set.add(0, 6);
+        let below = set.add(2, 3);
+
+        set.split_line(0, 4);
+        assert_eq!(set.get(head), Some((0, 1)));
+        assert_eq!(set.get(tail), Some((1, 2)));
+        assert_eq!(set.get(below), Some((3, 3)));
+
+        set.join_lines(0, 4);
+        assert_eq!(set.get(tail), Some((0, 6)));
+        assert_eq!(set.get(below), Some((2, 3)));
+
+        set.insert_line(1);
+        assert_eq!(set.get(below), Some((3, 3)));
+        set.remove_line(3, (2, 0));
+        assert_eq!(set.get(below), Some((2, 0)));
+        set.remove_line(0, (0, 0));
+        assert_eq!(set.get(head), Some((0, 0)));
+        assert_eq!(set.get(below), Some((1, 0)));
+    }
+}
@@ -0,0 +1,183 @@
+//! Minibuffer line editing - moving through a prompt's input, and fitting
+//! the prompt line to the screen
+//!
+//! Positions are byte offsets into the input, always on char boundaries.
+//! A prompt line wider than the screen scrolls sideways by half a screen
+//! at a time to keep the cursor in view, with a `$` marking text cut off
+//! on either side.
+
+use crate::command::commands::is_word_char;
+use crate::input::{key_flags, Key};
+use crate::line::char_width;
+
+/// Offset of the char after the one at `pos`
+pub fn next_char(text: &str, pos: usize) -> usize {
+    text[pos..].chars().next().map_or(pos, |ch| pos + ch.len_utf8())
+}
+
+/// Offset of the char before `pos`
+pub fn prev_char(text: &str, pos: usize) -> usize {
+    text[..pos].chars().next_back().map_or(pos, |ch| pos - ch.len_utf8())
+}
+
+/// Offset of the end of the word at or after `pos`
+pub fn forward_word(text: &str, pos: usize) -> usize {
+    let rest = &text[pos..];
+    let start = rest.find(is_word_char).unwrap_or(rest.len());
+    let end = rest[start..].find(|ch| !is_word_char(ch)).map_or(rest.len(), |i| start + i);
+    pos + end
+}
+
+/// Offset of the start of the word before `pos`
+pub fn backward_word(text: &str, pos: usize) -> usize {
+    let before = &text[..pos];
+    let end = before.rfind(is_word_char).map_or(0, |i| next_char(before, i));
+    before[..end].rfind(|ch| !is_word_char(ch)).map_or(0, |i| next_char(before, i))
+}
+
+/// Character a key inserts after C-q: control keys give control
+/// characters (so C-q TAB inserts a tab), other keys their character
+pub fn quoted_char(key: Key) -> Option<char> {
+    if key.0 & (key_flags::META | key_flags::CTLX | key_flags::SPEC | key_flags::CTLX_R) != 0 {
+        return None;
+    }
+    let ch = key.base_char()?;
+    if key.0 & key_flags::CONTROL != 0 {
+        match ch {
+            '@' | 'a'..='z' | '[' | '\\' | ']' | '^' | '_' => Some(((ch as u8) & 0x1f) as char),
+            '?' => Some('\x7f'),
+            _ => None,
+        }
+    } else {
+        Some(ch)
+    }
+}
+
+/// The part of a prompt line shown in `cols` columns, and the cursor's
+/// column in it, with the cursor at byte `cursor` of `line`
+///
+/// Tabs are shown as spaces up to the next of the tab stops `tab_width`
+/// apart, C0 control characters and DEL as `^X`, and
+/// other control characters as `\u{XXXX}`.
+pub fn fit_line(line: &str, cursor: usize, cols: usize, tab_width: usize) -> (String, usize) {
+    // Screen cells of the whole line: (column, width, text)
+    let mut cells = Vec::new();
+    let mut col = 0;
+    let mut cursor_col = None;
+    for (pos, ch) in line.char_indices() {
+        if pos == cursor {
+            cursor_col = Some(col);
+        }
+        let text = match ch {
+            '\t' => " ".repeat(char_width(ch, col, tab_width)),
+            '\x7f' => "^?".to_string(),
+            ch if ch < '\x20' => format!("^{}", ((ch as u8) | 0x40) as char),
+            ch if ch.is_control() => format!("\\u{{{:04X}}}", ch as u32),
+            ch => ch.to_string(),
+        };
+        let width = if ch == '\t' || ch.is_control() { text.len() } else { char_width(ch, col, tab_width) };
+        cells.push((col, width, text));
+        col += width;
+    }
+    let total = col;
+    let cursor_col = cursor_col.unwrap_or(total);
+
+    // Leave the last column for the cursor at the end of the input
+    let usable = cols.saturating_sub(1).max(2);
+    let step = usable / 2;
+    let start = if cursor_col < usable {
+        0
+    } else {
+        ((cursor_col - usable) / step + 1) * step
+    };
+
+    // Columns from `start`, the first covered by `$` if scrolled
+    let mut shown = String::new();
+    let mut width = 0;
+    if start > 0 {
+        shown.push('$');
+        width = 1;
+    }
+    let mut cut_off = false;
+    for (cell_col, cell_width, text) in cells {
+        let end = cell_col + cell_width;
+        if end <= start + width {
+            continue;
+        }
+        // Only the last cell may use the last column
+        let limit = if end == total { cols } else { cols.saturating_sub(1) };
+        if end - start > limit {
+            cut_off = true;
+            break;
+        }
+        if cell_col < start + width {
+            // Partly hidden on the left
+            shown.push_str(&" ".repeat(end - start - width));
+        } else {
+            shown.push_str(&text);
+        }
+        width = end - start;
+    }
+    if cut_off {
+        // The last column shows `$`
+        shown.push_str(&" ".repeat(cols.saturating_sub(1 + width)));
+        shown.push('$');
+    }
+    (shown, cursor_col - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line::TAB_WIDTH;
+
+    #[test]
+    fn test_movement() {
+        let text = "cargo  build--release é";
+        assert_eq!(forward_word(text, 0), 5);
+        assert_eq!(forward_word(text, 5), 12);
+        assert_eq!(forward_word(text, 12), 21);
+        assert_eq!(forward_word(text, 21), 24);
+        assert_eq!(forward_word(text, 24), 24);
+        assert_eq!(backward_word(text, 24), 22);
+        assert_eq!(backward_word(text, 21), 14);
+        assert_eq!(backward_word(text, 14), 7);
+        assert_eq!(backward_word(text, 3), 0);
+        assert_eq!(backward_word(text, 0), 0);
+        assert_eq!(next_char(text, 22), 24);
+        assert_eq!(prev_char(text, 24), 22);
+        assert_eq!(prev_char(text, 0), 0);
+        assert_eq!(next_char(text, 24), 24);
+    }
+
+    #[test]
+    fn test_quoted_char() {
+        assert_eq!(quoted_char(Key::ctrl('i')), Some('\t'));
+        assert_eq!(quoted_char(Key::ctrl('j')), Some('\n'));
+        assert_eq!(quoted_char(Key::char('x')), Some('x'));
+        assert_eq!(quoted_char(Key(0x7f)), Some('\x7f'));
+        assert_eq!(quoted_char(Key::meta('x')), None);
+    }
+
+    #[test]
+    fn test_fit_line() {
+        // Fits: shown as is
+        assert_eq!(fit_line("Find: abc", 9, 20, TAB_WIDTH), ("Find: abc".to_string(), 9));
+        assert_eq!(fit_line("a\tb\x01", 0, 20, TAB_WIDTH), ("a       b^A".to_string(), 0));
+        assert_eq!(fit_line("a\tb\x01", 3, 20, TAB_WIDTH), ("a       b^A".to_string(), 9));
+        // C1 controls aren't `^X` characters
+        assert_eq!(fit_line("\u{85}x\x7f", 3, 20, TAB_WIDTH), ("\\u{0085}x^?".to_string(), 9));
+
+        // Cursor at the end of a long line: scrolled half a screen at a
+        // time, with `$` over the first column shown
+        let line = "0123456789abcdefghij";
+        assert_eq!(fit_line(line, 20, 11, TAB_WIDTH), ("$ghij".to_string(), 5));
+        assert_eq!(fit_line(line, 12, 11, TAB_WIDTH), ("$6789abcde$".to_string(), 7));
+        // Cursor near the start: cut off on the right
+        assert_eq!(fit_line(line, 9, 11, TAB_WIDTH), ("0123456789$".to_string(), 9));
+        // A line that just fits isn't cut off
+        assert_eq!(fit_line(&line[..11], 0, 11, TAB_WIDTH), ("0123456789a".to_string(), 0));
+        // A wide character cut off on the left shows as a space
+        assert_eq!(fit_line("ab日本語", 11, 6, TAB_WIDTH), ("$ 語".to_string(), 4));
+    }
+}
@@ -0,0 +1,174 @@
+//! Recent files - the files opened lately and where the cursor was in each
+//!
+//! The list is saved to `~/.uemacs-recent` (or `%USERPROFILE%\.uemacs-recent`
+//! on Windows), next to the macros file, most recent first:
+//!
+//! ```text
+//! [file]
+//! file = /home/me/src/main.rs
+//! line = 14
+//! column = 4
+//!
+//! [file]
+//! file = /home/me/notes.txt
+//! line = 1
+//! column = 0
+//! ```
+//!
+//! Lines are 1-based. Only the newest `RECENT_MAX` files are kept.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Most files remembered
+pub const RECENT_MAX: usize = 50;
+
+/// A recently opened file and the cursor's place in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentFile {
+    /// The file (canonical path)
+    pub file: PathBuf,
+    /// Line (0-indexed)
+    pub line: usize,
+    /// Column (byte offset within the line)
+    pub col: usize,
+}
+
+/// Get the path to the recent files file
+pub fn recent_file_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var("USERPROFILE")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-recent"))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("HOME")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-recent"))
+    }
+}
+
+/// The entry for a file, if it is in the list
+pub fn find<'a>(recent: &'a [RecentFile], file: &Path) -> Option<&'a RecentFile> {
+    recent.iter().find(|r| r.file == file)
+}
+
+/// Put a file at the front of the list, replacing its old entry and
+/// dropping the oldest files beyond `RECENT_MAX`
+pub fn record(recent: &mut Vec<RecentFile>, entry: RecentFile) {
+    recent.retain(|r| r.file != entry.file);
+    recent.insert(0, entry);
+    recent.truncate(RECENT_MAX);
+}
+
+/// Parse the recent files list from the file format
+pub fn parse_recent(content: &str) -> Vec<RecentFile> {
+    let mut recent = Vec::new();
+    let mut current: Option<RecentFile> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        // Skip empty lines and comments
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+
+        // Each [file] section is one file
+        if trimmed == "[file]" {
+            recent.extend(current.take().filter(|r| !r.file.as_os_str().is_empty()));
+            current = Some(RecentFile {
+                file: PathBuf::new(),
+                line: 0,
+                col: 0,
+            });
+            continue;
+        }
+
+        if let (Some(entry), Some((key, value))) = (current.as_mut(), trimmed.split_once('=')) {
+            let value = value.trim();
+            match key.trim() {
+                "file" => entry.file = PathBuf::from(value),
+                "line" => entry.line = value.parse::<usize>().unwrap_or(1).saturating_sub(1),
+                "column" => entry.col = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+    recent.extend(current.filter(|r| !r.file.as_os_str().is_empty()));
+    recent.truncate(RECENT_MAX);
+    recent
+}
+
+/// Format the recent files list for the recent files file
+pub fn format_recent(recent: &[RecentFile]) -> String {
+    let mut out = String::new();
+    out.push_str("# uEmacs.rs recent files\n");
+    out.push_str("# Most recent first; line is 1-based\n");
+    for entry in recent {
+        out.push_str("\n[file]\n");
+        out.push_str(&format!("file = {}\n", entry.file.display()));
+        out.push_str(&format!("line = {}\n", entry.line + 1));
+        out.push_str(&format!("column = {}\n", entry.col));
+    }
+    out
+}
+
+/// Load the recent files list
+pub fn load_recent() -> Vec<RecentFile> {
+    recent_file_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| parse_recent(&content))
+        .unwrap_or_default()
+}
+
+/// Save the recent files list
+pub fn save_recent(recent: &[RecentFile]) -> io::Result<()> {
+    let path = match recent_file_path() {
+        Some(p) => p,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Could not determine recent files path",
+            ))
+        }
+    };
+    let mut file = fs::File::create(&path)?;
+    file.write_all(format_recent(recent).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, line: usize, col: usize) -> RecentFile {
+        RecentFile { file: PathBuf::from(file), line, col }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let recent = vec![entry("/tmp/a.rs", 13, 4), entry("/tmp/b c.txt", 0, 0)];
+        assert_eq!(parse_recent(&format_recent(&recent)), recent);
+        // Entries without a file are dropped
+        assert_eq!(parse_recent("[file]\nline = 3\n\n[file]\nfile = /x\n"), vec![entry("/x", 0, 0)]);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut recent = vec![entry("/a", 1, 0), entry("/b", 2, 0)];
+        record(&mut recent, entry("/b", 5, 1));
+        assert_eq!(recent, vec![entry("/b", 5, 1), entry("/a", 1, 0)]);
+        assert_eq!(find(&recent, Path::new("/a")), Some(&entry("/a", 1, 0)));
+        assert_eq!(find(&recent, Path::new("/c")), None);
+
+        for i in 0..RECENT_MAX {
+            record(&mut recent, entry(&format!("/f{}", i), 0, 0));
+        }
+        assert_eq!(recent.len(), RECENT_MAX);
+        assert_eq!(recent[0].file, PathBuf::from(format!("/f{}", RECENT_MAX - 1)));
+        assert!(find(&recent, Path::new("/a")).is_none());
+    }
+}
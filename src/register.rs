@@ -0,0 +1,112 @@
+//! Registers - named slots holding text, positions or window layouts
+//!
+//! Each register is named by a single character. Positions are held as
+//! buffer markers, so a saved position keeps pointing at the same text
+//! while the buffer is edited; it is lost only when the buffer is killed.
+
+use crate::layout::{Layout, Rect};
+use crate::marker::MarkerId;
+use crate::window::Window;
+
+/// Contents of a register
+#[derive(Debug, Clone)]
+pub enum Register {
+    /// Text from the region
+    Text(String),
+    /// A rectangle, one string per line
+    Rectangle(Vec<String>),
+    /// A position (marker ids are unique, so the buffer is found by its marker)
+    Position(MarkerId),
+    /// A window layout
+    Windows(WindowLayout),
+}
+
+impl Register {
+    /// Markers owned by this register
+    pub fn markers(&self) -> Vec<MarkerId> {
+        match self {
+            Register::Position(id) => vec![*id],
+            Register::Windows(layout) => layout.windows.iter().map(|w| w.point).collect(),
+            Register::Text(_) | Register::Rectangle(_) => Vec::new(),
+        }
+    }
+}
+
+/// The windows on screen when a layout was saved
+#[derive(Debug, Clone)]
+pub struct WindowLayout {
+    /// Windows in screen order
+    pub windows: Vec<SavedWindow>,
+    /// How the windows divided the screen
+    pub tree: Layout,
+    /// Screen area the windows shared
+    pub area: Rect,
+    /// Index of the selected window
+    pub current: usize,
+}
+
+/// One window of a saved layout
+#[derive(Debug, Clone)]
+pub struct SavedWindow {
+    /// Window as it was (its buffer index may be stale)
+    pub window: Window,
+    /// Name of the buffer it showed
+    pub buffer: String,
+    /// Marker at the window's cursor
+    pub point: MarkerId,
+}
+
+/// Which register command is waiting for a register name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterCommand {
+    /// copy-to-register; `delete` removes the region too
+    CopyText { delete: bool },
+    /// copy-rectangle-to-register; `delete` removes the rectangle too
+    CopyRectangle { delete: bool },
+    /// insert-register; `after` leaves point after the text
+    Insert { after: bool },
+    /// point-to-register
+    Point,
+    /// jump-to-register
+    Jump,
+    /// window-configuration-to-register
+    WindowLayout,
+}
+
+impl RegisterCommand {
+    /// Prompt shown while waiting for the register name
+    pub fn prompt(&self) -> &'static str {
+        match self {
+            RegisterCommand::CopyText { .. } => "Copy to register: ",
+            RegisterCommand::CopyRectangle { .. } => "Copy rectangle to register: ",
+            RegisterCommand::Insert { .. } => "Insert register: ",
+            RegisterCommand::Point => "Point to register: ",
+            RegisterCommand::Jump => "Jump to register: ",
+            RegisterCommand::WindowLayout => "Window configuration to register: ",
+        }
+    }
+}
+
+/// First line of some text, shortened to `max` characters for listings
+pub fn preview(text: &str, max: usize) -> String {
+    let first = text.lines().next().unwrap_or("");
+    let mut shown: String = first.chars().take(max).collect();
+    if shown.len() < first.len() || first.len() < text.trim_end_matches('\n').len() {
+        shown.push_str("...");
+    }
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview("hello", 10), "hello");
+        assert_eq!(preview("hello world", 5), "hello...");
+        assert_eq!(preview("one\ntwo", 10), "one...");
+        assert_eq!(preview("one\n", 10), "one");
+        assert_eq!(preview("", 10), "");
+    }
+}
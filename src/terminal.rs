@@ -4,7 +4,7 @@ use std::io::{self, Write};
 
 use crossterm::{
     cursor,
-    event::{self, Event, KeyEvent},
+    event::{self, Event, KeyEvent, MouseEvent},
     execute, queue,
     style::Print,
     terminal::{self, ClearType},
@@ -12,12 +12,28 @@ use crossterm::{
 
 use crate::error::Result;
 
+/// Input read from the terminal
+pub enum InputEvent {
+    /// A key press
+    Key(KeyEvent),
+    /// Text pasted into the terminal (bracketed paste), in one piece
+    Paste(String),
+    /// A mouse click, drag or wheel turn (only with mouse capture on)
+    Mouse(MouseEvent),
+    /// The terminal changed size (`cols()` and `rows()` are already updated)
+    Resize,
+}
+
 /// Terminal wrapper for cross-platform terminal I/O
 pub struct Terminal {
     /// Terminal width in columns
     cols: u16,
     /// Terminal height in rows
     rows: u16,
+    /// Whether mouse events are being captured
+    mouse_capture: bool,
+    /// Whether the real terminal was set up and must be restored on drop
+    set_up: bool,
 }
 
 impl Terminal {
@@ -26,13 +42,23 @@ impl Terminal {
         terminal::enable_raw_mode()?;
         let (cols, rows) = terminal::size()?;
 
-        let mut term = Self { cols, rows };
+        let mut term = Self { cols, rows, mouse_capture: false, set_up: true };
         term.enter_alternate_screen()?;
         term.hide_cursor()?;
+        // Not every terminal supports bracketed paste; pastes then arrive
+        // as keys
+        let _ = execute!(io::stdout(), event::EnableBracketedPaste);
 
         Ok(term)
     }
 
+    /// Create a terminal of a given size that leaves the real one alone,
+    /// for tests
+    #[cfg(test)]
+    pub fn headless(cols: u16, rows: u16) -> Self {
+        Self { cols, rows, mouse_capture: false, set_up: false }
+    }
+
     /// Enter alternate screen buffer
     fn enter_alternate_screen(&mut self) -> Result<()> {
         execute!(io::stdout(), terminal::EnterAlternateScreen)?;
@@ -57,6 +83,22 @@ impl Terminal {
         Ok(())
     }
 
+    /// Turn mouse capture on or off
+    ///
+    /// While the mouse is captured the terminal's own text selection
+    /// usually needs Shift held down.
+    pub fn set_mouse_capture(&mut self, on: bool) -> Result<()> {
+        if on != self.mouse_capture {
+            if on {
+                execute!(io::stdout(), event::EnableMouseCapture)?;
+            } else {
+                execute!(io::stdout(), event::DisableMouseCapture)?;
+            }
+            self.mouse_capture = on;
+        }
+        Ok(())
+    }
+
     /// Get terminal width
     pub fn cols(&self) -> u16 {
         self.cols
@@ -121,18 +163,20 @@ impl Terminal {
         Ok(())
     }
 
-    /// Read a key event (blocking)
-    pub fn read_key(&mut self) -> Result<KeyEvent> {
+    /// Read a key event, a paste, a mouse event or a resize (blocking)
+    pub fn read_key(&mut self) -> Result<InputEvent> {
         loop {
             match event::read()? {
-                Event::Key(key_event) => return Ok(key_event),
+                Event::Key(key_event) => return Ok(InputEvent::Key(key_event)),
+                Event::Paste(text) => return Ok(InputEvent::Paste(text)),
+                Event::Mouse(mouse_event) => return Ok(InputEvent::Mouse(mouse_event)),
                 Event::Resize(cols, rows) => {
                     self.cols = cols;
                     self.rows = rows;
-                    // Continue waiting for key event
+                    return Ok(InputEvent::Resize);
                 }
                 _ => {
-                    // Ignore other events (mouse, focus, etc.)
+                    // Ignore other events (focus, etc.)
                 }
             }
         }
@@ -242,6 +286,13 @@ impl Terminal {
         }
     }
 
+    /// Copy text to the system clipboard (OSC 52)
+    pub fn set_clipboard(&mut self, text: &str) -> Result<()> {
+        let in_tmux = std::env::var_os("TMUX").is_some();
+        queue!(io::stdout(), Print(crate::clipboard::osc52_sequence(text, in_tmux)))?;
+        self.flush()
+    }
+
     /// Sound the bell
     pub fn beep(&mut self) -> Result<()> {
         print!("\x07");
@@ -252,7 +303,12 @@ impl Terminal {
 
 impl Drop for Terminal {
     fn drop(&mut self) {
+        if !self.set_up {
+            return;
+        }
         // Restore terminal state
+        let _ = self.set_mouse_capture(false);
+        let _ = execute!(io::stdout(), event::DisableBracketedPaste);
         let _ = self.show_cursor();
         let _ = self.leave_alternate_screen();
         let _ = terminal::disable_raw_mode();
@@ -0,0 +1,178 @@
+//! Timers - tasks the main loop runs between keys
+//!
+//! A timer runs its callback either every so often, whether or not keys
+//! are typed, or once each time input has been idle for a while. The main
+//! loop waits for input only until the next timer is due, so timers run
+//! in an idle editor without holding up key handling.
+
+use std::time::{Duration, Instant};
+
+/// Identifies a timer, for removing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+/// When a timer runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every interval
+    Every(Duration),
+    /// Once input has been idle this long, then not again until the next
+    /// input
+    Idle(Duration),
+}
+
+impl Schedule {
+    /// Wait before the timer first runs
+    fn delay(self) -> Duration {
+        match self {
+            Schedule::Every(delay) | Schedule::Idle(delay) => delay,
+        }
+    }
+}
+
+/// A registered timer
+#[derive(Debug, Clone)]
+struct Timer<F> {
+    id: TimerId,
+    schedule: Schedule,
+    callback: F,
+    /// When it runs next (None for an idle timer waiting for input)
+    next: Option<Instant>,
+}
+
+/// Registered timers
+#[derive(Debug, Clone)]
+pub struct Timers<F> {
+    timers: Vec<Timer<F>>,
+    next_id: u64,
+}
+
+impl<F: Copy> Timers<F> {
+    /// Create an empty timer list
+    pub fn new() -> Self {
+        Self {
+            timers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Register a callback, counting its first interval from `now`
+    pub fn add(&mut self, schedule: Schedule, callback: F, now: Instant) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id,
+            schedule,
+            callback,
+            next: Some(now + schedule.delay()),
+        });
+        id
+    }
+
+    /// Remove a timer, returns false if it wasn't registered
+    pub fn remove(&mut self, id: TimerId) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+        self.timers.len() != count
+    }
+
+    /// Start a timer's interval or idle wait again from `now`
+    pub fn restart(&mut self, id: TimerId, now: Instant) {
+        if let Some(timer) = self.timers.iter_mut().find(|t| t.id == id) {
+            timer.next = Some(now + timer.schedule.delay());
+        }
+    }
+
+    /// Note input at `now`: idle timers start counting again
+    pub fn input(&mut self, now: Instant) {
+        for timer in &mut self.timers {
+            if let Schedule::Idle(delay) = timer.schedule {
+                timer.next = Some(now + delay);
+            }
+        }
+    }
+
+    /// Callbacks of the timers due at `now`, in the order they were
+    /// added; each timer is scheduled again (idle timers after the next
+    /// input)
+    pub fn due(&mut self, now: Instant) -> Vec<F> {
+        let mut callbacks = Vec::new();
+        for timer in &mut self.timers {
+            if timer.next.is_some_and(|next| next <= now) {
+                callbacks.push(timer.callback);
+                timer.next = match timer.schedule {
+                    Schedule::Every(interval) => Some(now + interval),
+                    Schedule::Idle(_) => None,
+                };
+            }
+        }
+        callbacks
+    }
+
+    /// When the next timer is due, if any is waiting
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.iter().filter_map(|t| t.next).min()
+    }
+}
+
+impl<F: Copy> Default for Timers<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut timers = Timers::new();
+        let id = timers.add(Schedule::Every(Duration::from_secs(5)), 'a', start);
+        timers.add(Schedule::Every(Duration::from_secs(2)), 'b', start);
+
+        assert_eq!(timers.next_deadline(), Some(secs(2)));
+        assert!(timers.due(secs(1)).is_empty());
+        assert_eq!(timers.due(secs(2)), vec!['b']);
+        assert_eq!(timers.due(secs(5)), vec!['a', 'b']);
+        // Input doesn't delay periodic timers
+        timers.input(secs(6));
+        assert_eq!(timers.next_deadline(), Some(secs(7)));
+
+        assert!(timers.remove(id));
+        assert!(!timers.remove(id));
+        assert_eq!(timers.due(secs(10)), vec!['b']);
+    }
+
+    #[test]
+    fn test_idle() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut timers = Timers::new();
+        timers.add(Schedule::Idle(Duration::from_secs(3)), 'i', start);
+
+        // Input restarts the wait
+        timers.input(secs(2));
+        assert!(timers.due(secs(4)).is_empty());
+        assert_eq!(timers.due(secs(5)), vec!['i']);
+        // Runs once per idle period
+        assert_eq!(timers.next_deadline(), None);
+        assert!(timers.due(secs(20)).is_empty());
+        timers.input(secs(21));
+        assert_eq!(timers.next_deadline(), Some(secs(24)));
+    }
+
+    #[test]
+    fn test_restart() {
+        let start = Instant::now();
+        let secs = |n| start + Duration::from_secs(n);
+        let mut timers = Timers::new();
+        let id = timers.add(Schedule::Idle(Duration::from_secs(3)), 'i', start);
+        assert_eq!(timers.due(secs(3)), vec!['i']);
+        // A waiting idle timer can be started again without input
+        timers.restart(id, secs(10));
+        assert_eq!(timers.next_deadline(), Some(secs(13)));
+    }
+}
@@ -0,0 +1,567 @@
+//! Undo tree - branching undo history
+//!
+//! Edits are recorded as `UndoEntry` values. An `UndoEntry::Boundary`
+//! closes the current group, which becomes a node in the tree: the node's
+//! entries take the buffer from its parent's state to its own. Undo moves
+//! to the parent, redo moves back down to the most recently visited child,
+//! and a new edit after an undo starts a new branch instead of discarding
+//! the undone changes.
+
+use crate::buffer::UndoEntry;
+use crate::file_format::LineEnding;
+
+/// A state in the undo tree
+#[derive(Debug, Clone, Default)]
+struct UndoNode {
+    /// Parent state (None for the root)
+    parent: Option<usize>,
+    /// Child states, oldest first
+    children: Vec<usize>,
+    /// Index into `children` followed by redo
+    active_child: usize,
+    /// Changes leading from the parent state to this one
+    entries: Vec<UndoEntry>,
+}
+
+/// Branching undo history for one buffer
+#[derive(Debug, Clone)]
+pub struct UndoTree {
+    /// All states; index 0 is the root (the buffer as loaded)
+    nodes: Vec<UndoNode>,
+    /// State the buffer is currently in
+    current: usize,
+    /// Entries recorded since the last boundary
+    pending: Vec<UndoEntry>,
+    /// State matching the file on disk (the root until a save)
+    saved: usize,
+    /// Content hash of that file, once known
+    saved_hash: Option<u64>,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UndoTree {
+    /// Create a tree holding only the root state
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![UndoNode::default()],
+            current: 0,
+            pending: Vec::new(),
+            saved: 0,
+            saved_hash: None,
+        }
+    }
+
+    /// Discard all history
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Record an entry; a `Boundary` closes the pending group
+    pub fn push(&mut self, entry: UndoEntry) {
+        match entry {
+            UndoEntry::Boundary => self.commit(),
+            entry => self.pending.push(entry),
+        }
+    }
+
+    /// Turn pending entries into a new child of the current state
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: Some(self.current),
+            children: Vec::new(),
+            active_child: 0,
+            entries: std::mem::take(&mut self.pending),
+        });
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(id);
+        parent.active_child = parent.children.len() - 1;
+        self.current = id;
+    }
+
+    /// Check if there is anything to undo
+    pub fn can_undo(&self) -> bool {
+        self.current != 0 || !self.pending.is_empty()
+    }
+
+    /// Current state number
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Number of states, including the root
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Record that the current state matches file content with `hash`
+    pub fn mark_saved(&mut self, hash: u64) {
+        self.commit();
+        self.saved = self.current;
+        self.saved_hash = Some(hash);
+    }
+
+    /// Record that the current state matches the file, without knowing
+    /// its content hash
+    pub fn mark_clean(&mut self) {
+        self.commit();
+        if self.saved != self.current {
+            self.saved = self.current;
+            self.saved_hash = None;
+        }
+    }
+
+    /// Whether the buffer is in the state matching the file
+    pub fn is_saved(&self) -> bool {
+        self.pending.is_empty() && self.current == self.saved
+    }
+
+    /// Content hash of the file the saved state matches, if known
+    pub fn saved_hash(&self) -> Option<u64> {
+        self.saved_hash
+    }
+
+    /// Move to the parent state, returning the entries to revert
+    pub fn undo(&mut self) -> Option<Vec<UndoEntry>> {
+        self.commit();
+        let node = self.current;
+        let parent = self.nodes[node].parent?;
+        self.select_child(node);
+        self.current = parent;
+        Some(self.nodes[node].entries.clone())
+    }
+
+    /// Move to the active child state, returning the entries to reapply
+    pub fn redo(&mut self) -> Option<Vec<UndoEntry>> {
+        self.commit();
+        let node = &self.nodes[self.current];
+        let child = *node.children.get(node.active_child)?;
+        self.current = child;
+        Some(self.nodes[child].entries.clone())
+    }
+
+    /// Make `child` the branch its parent redoes into
+    pub fn select_child(&mut self, child: usize) {
+        if let Some(parent) = self.nodes.get(child).and_then(|n| n.parent) {
+            let parent = &mut self.nodes[parent];
+            if let Some(pos) = parent.children.iter().position(|&c| c == child) {
+                parent.active_child = pos;
+            }
+        }
+    }
+
+    /// Route from the current state to `target`
+    ///
+    /// Returns the number of undo steps up to the common ancestor and the
+    /// states to redo into from there, in order.
+    pub fn path_to(&mut self, target: usize) -> Option<(usize, Vec<usize>)> {
+        self.commit();
+        if target >= self.nodes.len() {
+            return None;
+        }
+        let from_root = |mut node: usize| {
+            let mut path = vec![node];
+            while let Some(parent) = self.nodes[node].parent {
+                path.push(parent);
+                node = parent;
+            }
+            path.reverse();
+            path
+        };
+        let here = from_root(self.current);
+        let there = from_root(target);
+        let common = here.iter().zip(&there).take_while(|(a, b)| a == b).count();
+        Some((here.len() - common, there[common..].to_vec()))
+    }
+
+    /// Draw the tree as text, one state per line
+    ///
+    /// Each line starts with the state number so a line in the
+    /// `*Undo Tree*` buffer can be mapped back to its state. Linear history
+    /// stays in one column; older branches are indented beneath their
+    /// parent.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (id, depth) in self.render_order() {
+            let node = &self.nodes[id];
+            let marker = if id == self.current { '*' } else { 'o' };
+            let summary = if id == 0 {
+                "(original)".to_string()
+            } else {
+                summarize(&node.entries)
+            };
+            out.push_str(&format!("{:>5} {}{} {}\n", id, "  ".repeat(depth), marker, summary));
+        }
+        out
+    }
+
+    /// Line of `render`'s output that shows a state
+    pub fn render_line(&self, state: usize) -> Option<usize> {
+        self.render_order().iter().position(|&(id, _)| id == state)
+    }
+
+    /// States in the order `render` draws them, with their indentation
+    fn render_order(&self) -> Vec<(usize, usize)> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(0usize, 0usize)];
+        while let Some((id, depth)) = stack.pop() {
+            order.push((id, depth));
+
+            // Newest child continues this column; older ones branch off
+            if let Some((&newest, older)) = self.nodes[id].children.split_last() {
+                stack.push((newest, depth));
+                for &child in older.iter().rev() {
+                    stack.push((child, depth + 1));
+                }
+            }
+        }
+        order
+    }
+}
+
+impl UndoTree {
+    /// Serialize the tree for persistent undo
+    ///
+    /// The saved state is written as the current one, since that is the
+    /// state the file on disk will be in when the history is read back.
+    /// Pending entries are not included; call `commit` first to keep them.
+    /// Returns None if the tree has never been marked saved.
+    pub fn to_text(&self) -> Option<String> {
+        let (state, hash) = (self.saved, self.saved_hash?);
+        let mut out = String::from("# uEmacs.rs undo history\n");
+        out.push_str(&format!("hash {:016x}\nstate {}\n", hash, state));
+        out.push_str(&format!("root {}\n", self.nodes[0].active_child));
+        for node in &self.nodes[1..] {
+            out.push_str(&format!("node {} {}\n", node.parent.unwrap_or(0), node.active_child));
+            for entry in &node.entries {
+                let line = match entry {
+                    UndoEntry::Insert { line, col, text } => format!("+ {} {} {}", line, col, escape(text)),
+                    UndoEntry::Delete { line, col, text } => format!("- {} {} {}", line, col, escape(text)),
+                    UndoEntry::InsertNewline { line, col } => format!("n {} {}", line, col),
+                    UndoEntry::DeleteNewline { line, col, eol } => {
+                        format!("j {} {} {}", line, col, eol.map_or("-", |eol| eol.name()))
+                    }
+                    UndoEntry::SetLineEnding { from, mixed, overrides, to } => {
+                        let mut line = format!("e {} {} {}", from.name(), to.name(), *mixed as u8);
+                        for (idx, eol) in overrides {
+                            line.push_str(&format!(" {}:{}", idx, eol.name()));
+                        }
+                        line
+                    }
+                    UndoEntry::Boundary => continue,
+                };
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        Some(out)
+    }
+
+    /// Parse a tree written by `to_text`, returning it with its content hash
+    ///
+    /// Returns None if the text is malformed.
+    pub fn from_text(text: &str) -> Option<(Self, u64)> {
+        let mut tree = Self::new();
+        let mut hash = None;
+        let mut state = None;
+
+        for line in text.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (tag, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match tag {
+                "hash" => hash = Some(u64::from_str_radix(rest, 16).ok()?),
+                "state" => state = Some(rest.parse::<usize>().ok()?),
+                "root" => tree.nodes[0].active_child = rest.parse().ok()?,
+                "node" => {
+                    let (parent, active) = rest.split_once(' ')?;
+                    let parent: usize = parent.parse().ok()?;
+                    let id = tree.nodes.len();
+                    if parent >= id {
+                        return None;
+                    }
+                    tree.nodes.push(UndoNode {
+                        parent: Some(parent),
+                        children: Vec::new(),
+                        active_child: active.parse().ok()?,
+                        entries: Vec::new(),
+                    });
+                    tree.nodes[parent].children.push(id);
+                }
+                "+" | "-" | "n" | "j" | "e" => {
+                    let entry = if tag == "e" {
+                        parse_line_ending_entry(rest)?
+                    } else {
+                        let mut fields = rest.splitn(3, ' ');
+                        let line: usize = fields.next()?.parse().ok()?;
+                        let col: usize = fields.next()?.parse().ok()?;
+                        match tag {
+                            "+" => UndoEntry::Insert { line, col, text: unescape(fields.next()?) },
+                            "-" => UndoEntry::Delete { line, col, text: unescape(fields.next()?) },
+                            "n" => UndoEntry::InsertNewline { line, col },
+                            _ => {
+                                let eol = match fields.next()? {
+                                    "-" => None,
+                                    name => Some(LineEnding::from_name(name)?),
+                                };
+                                UndoEntry::DeleteNewline { line, col, eol }
+                            }
+                        }
+                    };
+                    let last = tree.nodes.len() - 1;
+                    if last == 0 {
+                        return None;
+                    }
+                    tree.nodes[last].entries.push(entry);
+                }
+                _ => return None,
+            }
+        }
+
+        let (hash, state) = (hash?, state?);
+        if state >= tree.nodes.len() {
+            return None;
+        }
+        tree.current = state;
+        tree.saved = state;
+        tree.saved_hash = Some(hash);
+        Some((tree, hash))
+    }
+}
+
+/// Parse the fields of a line-ending conversion: `from to mixed line:ending...`
+fn parse_line_ending_entry(rest: &str) -> Option<UndoEntry> {
+    let mut fields = rest.split(' ');
+    let from = LineEnding::from_name(fields.next()?)?;
+    let to = LineEnding::from_name(fields.next()?)?;
+    let mixed = fields.next()? == "1";
+    let overrides = fields
+        .map(|field| {
+            let (idx, eol) = field.split_once(':')?;
+            Some((idx.parse().ok()?, LineEnding::from_name(eol)?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(UndoEntry::SetLineEnding { from, mixed, overrides, to })
+}
+
+/// Escape text so it fits on one line of the history file
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Reverse `escape`
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Short description of a change group, e.g. `+"hello" (5 changes)`
+fn summarize(entries: &[UndoEntry]) -> String {
+    let mut inserted = String::new();
+    let mut deleted = String::new();
+    for entry in entries {
+        match entry {
+            UndoEntry::Insert { text, .. } => inserted.push_str(text),
+            UndoEntry::Delete { text, .. } => deleted.push_str(text),
+            UndoEntry::InsertNewline { .. } => inserted.push('\n'),
+            UndoEntry::DeleteNewline { .. } => deleted.push('\n'),
+            UndoEntry::SetLineEnding { .. } | UndoEntry::Boundary => {}
+        }
+    }
+    let clip = |s: &str| {
+        let clipped: String = s.chars().take(24).collect();
+        if clipped.len() < s.len() {
+            format!("{}...", clipped)
+        } else {
+            clipped
+        }
+    };
+    let mut parts = Vec::new();
+    if !inserted.is_empty() {
+        parts.push(format!("+{:?}", clip(&inserted)));
+    }
+    if !deleted.is_empty() {
+        parts.push(format!("-{:?}", clip(&deleted)));
+    }
+    if entries.len() > 1 {
+        parts.push(format!("({} changes)", entries.len()));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(text: &str) -> UndoEntry {
+        UndoEntry::Insert { line: 0, col: 0, text: text.to_string() }
+    }
+
+    #[test]
+    fn test_undo_redo_linear() {
+        let mut tree = UndoTree::new();
+        tree.push(insert("a"));
+        tree.push(UndoEntry::Boundary);
+        tree.push(insert("b"));
+        assert_eq!(tree.len(), 2);
+        assert!(tree.undo().is_some());
+        assert_eq!(tree.current(), 1);
+        assert!(tree.undo().is_some());
+        assert_eq!(tree.current(), 0);
+        assert!(tree.undo().is_none());
+        assert!(tree.redo().is_some());
+        assert!(tree.redo().is_some());
+        assert_eq!(tree.current(), 2);
+        assert!(tree.redo().is_none());
+    }
+
+    #[test]
+    fn test_new_edit_after_undo_keeps_branch() {
+        let mut tree = UndoTree::new();
+        tree.push(insert("a"));
+        tree.undo();
+        tree.push(insert("b"));
+        tree.commit();
+        assert_eq!(tree.current(), 2);
+        assert_eq!(tree.len(), 3);
+
+        // Undo then redo follows the newest branch
+        tree.undo();
+        tree.redo();
+        assert_eq!(tree.current(), 2);
+
+        // The old branch is still reachable
+        let (ups, downs) = tree.path_to(1).unwrap();
+        assert_eq!(ups, 1);
+        assert_eq!(downs, vec![1]);
+    }
+
+    #[test]
+    fn test_text_roundtrip() {
+        let mut tree = UndoTree::new();
+        tree.push(insert("a\\b\nc"));
+        tree.push(UndoEntry::Boundary);
+        tree.push(UndoEntry::InsertNewline { line: 0, col: 1 });
+        tree.push(UndoEntry::Delete { line: 1, col: 0, text: " x".to_string() });
+        tree.mark_saved(0xfeed);
+        tree.undo();
+        tree.push(UndoEntry::DeleteNewline { line: 0, col: 2, eol: Some(LineEnding::Crlf) });
+        tree.push(UndoEntry::DeleteNewline { line: 0, col: 4, eol: None });
+        tree.push(UndoEntry::Boundary);
+        tree.push(UndoEntry::SetLineEnding {
+            from: LineEnding::Lf,
+            mixed: true,
+            overrides: vec![(0, LineEnding::Cr), (3, LineEnding::Crlf)],
+            to: LineEnding::Crlf,
+        });
+        tree.commit();
+        assert!(UndoTree::new().to_text().is_none());
+
+        let text = tree.to_text().unwrap();
+        let (restored, hash) = UndoTree::from_text(&text).unwrap();
+        assert_eq!(hash, 0xfeed);
+        assert_eq!(restored.len(), tree.len());
+        // Restored at the saved state, not where editing stopped
+        assert_eq!(restored.current(), 2);
+        assert_eq!(restored.to_text().unwrap(), text);
+        assert!(UndoTree::from_text("hash zz\nstate 0\n").is_none());
+        // Every join records the ending it removed
+        assert!(text.contains("\nj 0 2 CRLF\nj 0 4 -\n"));
+        let no_ending = "hash 1\nstate 1\nroot 1\nnode 0 0\nj 0 2\n";
+        assert!(UndoTree::from_text(no_ending).is_none());
+    }
+
+    #[test]
+    fn test_is_saved() {
+        let mut tree = UndoTree::new();
+        assert!(tree.is_saved());
+        tree.push(insert("a"));
+        assert!(!tree.is_saved());
+        tree.mark_clean();
+        assert!(tree.is_saved());
+        tree.push(UndoEntry::Boundary);
+        tree.push(insert("b"));
+        tree.undo();
+        assert!(tree.is_saved());
+        tree.undo();
+        assert!(!tree.is_saved());
+        tree.redo();
+        assert!(tree.is_saved());
+
+        // Marking clean elsewhere forgets a hash for another state
+        tree.mark_saved(0xfeed);
+        tree.mark_clean();
+        assert_eq!(tree.saved_hash(), Some(0xfeed));
+        tree.redo();
+        tree.mark_clean();
+        assert_eq!(tree.saved_hash(), None);
+    }
+
+    #[test]
+    fn test_render_marks_current_and_branches() {
+        let mut tree = UndoTree::new();
+        tree.push(insert("a"));
+        tree.undo();
+        tree.push(insert("b"));
+        tree.commit();
+        let text = tree.render();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "    0 o (original)");
+        assert_eq!(lines[1], "    1   o +\"a\"");
+        assert_eq!(lines[2], "    2 * +\"b\"");
+    }
+
+    #[test]
+    fn test_render_line_after_undos() {
+        let mut tree = UndoTree::new();
+        for text in ["a", "b", "c"] {
+            tree.push(insert(text));
+            tree.push(UndoEntry::Boundary);
+        }
+        tree.undo();
+        tree.undo();
+        tree.push(insert("d"));
+        tree.push(UndoEntry::Boundary);
+        tree.undo();
+        assert_eq!(tree.current(), 1);
+
+        // The current state's line is the one marked `*`
+        let text = tree.render();
+        let lines: Vec<&str> = text.lines().collect();
+        let line = tree.render_line(tree.current()).unwrap();
+        assert_eq!(lines[line], "    1 * +\"a\"");
+        assert_eq!(lines[tree.render_line(3).unwrap()], "    3   o +\"c\"");
+        assert_eq!(tree.render_line(9), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Persistent undo history
+//!
+//! When `persistent-undo` is enabled, each file's undo tree is written to
+//! `~/.uemacs-undo/` (or `%USERPROFILE%\.uemacs-undo\` on Windows) when its
+//! buffer is saved or killed, and read back when the file is opened again.
+//! Every history file records a hash of the file content it belongs to; if
+//! the file was changed outside the editor the hash no longer matches and
+//! the stale history is deleted instead of being applied.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::file_format::FileFormat;
+use crate::undo::UndoTree;
+
+/// Get the directory holding undo history files
+pub fn undo_dir_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var("USERPROFILE")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-undo"))
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("HOME")
+            .ok()
+            .map(|p| PathBuf::from(p).join(".uemacs-undo"))
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across runs and platforms
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+/// FNV-1a offset basis
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Hash buffer content as it is written to disk
+///
+/// `lines` are (text, terminator) pairs. The encoding and byte order mark
+/// are hashed too, so re-encoding a file or converting its line endings
+/// outside the editor invalidates its history.
+pub fn content_hash<'a, 'b>(
+    format: &FileFormat,
+    lines: impl Iterator<Item = (&'a str, &'b str)>,
+) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET, format.encoding.name().as_bytes());
+    hash = fnv1a(hash, if format.bom { b"\0bom\0" } else { b"\0" });
+    for (text, ending) in lines {
+        hash = fnv1a(hash, text.as_bytes());
+        hash = fnv1a(hash, ending.as_bytes());
+    }
+    hash
+}
+
+/// History file for a given file: `<hash of full path>-<file name>`
+fn history_path(dir: &Path, file: &Path) -> PathBuf {
+    let full = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+    let name = file
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let key = fnv1a(FNV_OFFSET, full.to_string_lossy().as_bytes());
+    dir.join(format!("{:016x}-{}", key, name))
+}
+
+/// Write a file's undo history
+pub fn save_history(file: &Path, tree: &mut UndoTree) -> io::Result<()> {
+    let dir = match undo_dir_path() {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    tree.commit();
+    match tree.to_text() {
+        Some(text) => {
+            fs::create_dir_all(&dir)?;
+            fs::write(history_path(&dir, file), text)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Read a file's undo history if it matches the file's current content
+///
+/// Stale or unreadable history is deleted.
+pub fn load_history(file: &Path, hash: u64) -> Option<UndoTree> {
+    let path = history_path(&undo_dir_path()?, file);
+    let text = fs::read_to_string(&path).ok()?;
+    match UndoTree::from_text(&text) {
+        Some((tree, saved_hash)) if saved_hash == hash => Some(tree),
+        _ => {
+            let _ = fs::remove_file(&path);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_format::Encoding;
+
+    #[test]
+    fn test_content_hash() {
+        let format = FileFormat::default();
+        let a = [("ab", "\n"), ("c", "\n")];
+        let b = [("a", "\n"), ("bc", "\n")];
+        assert_ne!(content_hash(&format, a.into_iter()), content_hash(&format, b.into_iter()));
+        assert_eq!(content_hash(&format, a.into_iter()), content_hash(&format, a.into_iter()));
+        // Known FNV-1a value for "a"
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_content_hash_covers_file_format() {
+        let format = FileFormat::default();
+        let lf = content_hash(&format, [("a", "\n"), ("b", "\n")].into_iter());
+
+        // Line endings, including a missing final newline
+        assert_ne!(lf, content_hash(&format, [("a", "\r\n"), ("b", "\r\n")].into_iter()));
+        assert_ne!(lf, content_hash(&format, [("a", "\n"), ("b", "")].into_iter()));
+
+        // Encoding and byte order mark
+        let latin1 = FileFormat { encoding: Encoding::Latin1, ..format };
+        assert_ne!(lf, content_hash(&latin1, [("a", "\n"), ("b", "\n")].into_iter()));
+        let bom = FileFormat { bom: true, ..format };
+        assert_ne!(lf, content_hash(&bom, [("a", "\n"), ("b", "\n")].into_iter()));
+    }
+}
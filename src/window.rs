@@ -1,7 +1,14 @@
 //! Window representation - a viewport into a buffer
+//!
+//! A window's point and mark live in markers registered with its buffer,
+//! so edits made through any window move them along with the text. The
+//! cursor fields are the working copy of the point that commands read and
+//! set; the editor keeps them and the point marker in step.
+
+use crate::marker::MarkerId;
 
 /// A window displaying a portion of a buffer
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Window {
     /// Index of the buffer being displayed
     buffer_idx: usize,
@@ -11,16 +18,33 @@ pub struct Window {
     cursor_line: usize,
     /// Cursor column position (byte offset within line)
     cursor_col: usize,
-    /// Mark line position (for region operations)
-    mark_line: Option<usize>,
-    /// Mark column position
-    mark_col: Option<usize>,
+    /// Marker holding the point in the buffer, once one is placed
+    point: Option<MarkerId>,
+    /// Cursor position when it was last exchanged with the point marker
+    point_synced: (usize, usize),
+    /// Marker holding the mark (for region operations)
+    mark: Option<MarkerId>,
+    /// Markers given up by a buffer switch, still to be unregistered
+    released: Vec<MarkerId>,
+    /// Whether the region is treated as a rectangle (C-x SPC)
+    rectangle: bool,
     /// Row on screen where window starts
     top_row: u16,
     /// Number of text rows in window (excluding mode line)
     height: u16,
+    /// Column on screen where window starts
+    screen_col: u16,
+    /// Number of columns in window (excluding a divider right of it)
+    width: u16,
+    /// Left off the screen because the terminal is too small for it
+    hidden: bool,
     /// Goal column for vertical movement
     goal_col: usize,
+    /// First display column shown (horizontal scroll)
+    left_col: usize,
+    /// Horizontal scroll set with scroll-left/scroll-right, which
+    /// following the cursor doesn't go below
+    min_left_col: usize,
 }
 
 impl Window {
@@ -31,11 +55,19 @@ impl Window {
             top_line: 0,
             cursor_line: 0,
             cursor_col: 0,
-            mark_line: None,
-            mark_col: None,
+            point: None,
+            point_synced: (0, 0),
+            mark: None,
+            released: Vec::new(),
+            rectangle: false,
             top_row,
             height,
+            screen_col: 0,
+            width: 0,
+            hidden: false,
             goal_col: 0,
+            left_col: 0,
+            min_left_col: 0,
         }
     }
 
@@ -50,8 +82,11 @@ impl Window {
         self.top_line = 0;
         self.cursor_line = 0;
         self.cursor_col = 0;
-        self.mark_line = None;
-        self.mark_col = None;
+        self.released.extend(self.point.take());
+        self.released.extend(self.mark.take());
+        self.rectangle = false;
+        self.left_col = 0;
+        self.min_left_col = 0;
     }
 
     /// Get the top visible line
@@ -90,30 +125,69 @@ impl Window {
         self.goal_col = col;
     }
 
-    /// Get mark position
-    pub fn mark(&self) -> Option<(usize, usize)> {
-        match (self.mark_line, self.mark_col) {
-            (Some(line), Some(col)) => Some((line, col)),
-            _ => None,
-        }
+    /// Get the marker holding the point
+    pub fn point_marker(&self) -> Option<MarkerId> {
+        self.point
+    }
+
+    /// Set the marker holding the point, with the cursor position it and
+    /// the cursor now agree on
+    pub fn set_point_marker(&mut self, id: MarkerId, pos: (usize, usize)) {
+        self.point = Some(id);
+        self.point_synced = pos;
+    }
+
+    /// Cursor position when it was last exchanged with the point marker
+    pub fn point_synced(&self) -> (usize, usize) {
+        self.point_synced
+    }
+
+    /// Get the marker holding the mark
+    pub fn mark_marker(&self) -> Option<MarkerId> {
+        self.mark
+    }
+
+    /// Set the marker holding the mark
+    pub fn set_mark_marker(&mut self, id: MarkerId) {
+        self.mark = Some(id);
+    }
+
+    /// Clear the mark, returning its marker for the caller to unregister
+    pub fn take_mark(&mut self) -> Option<MarkerId> {
+        self.rectangle = false;
+        self.mark.take()
+    }
+
+    /// Take the markers given up by buffer switches
+    pub fn take_released(&mut self) -> Vec<MarkerId> {
+        std::mem::take(&mut self.released)
     }
 
-    /// Set mark at current cursor position
-    pub fn set_mark(&mut self) {
-        self.mark_line = Some(self.cursor_line);
-        self.mark_col = Some(self.cursor_col);
+    /// Take every marker the window holds, for a window going away
+    pub fn take_markers(&mut self) -> Vec<MarkerId> {
+        let mut ids = self.take_released();
+        ids.extend(self.point.take());
+        ids.extend(self.mark.take());
+        ids
     }
 
-    /// Set mark at a specific position
-    pub fn set_mark_at(&mut self, line: usize, col: usize) {
-        self.mark_line = Some(line);
-        self.mark_col = Some(col);
+    /// Drop the markers of a copied window without unregistering them,
+    /// since they belong to the window it was copied from
+    pub fn forget_markers(&mut self) {
+        self.point = None;
+        self.mark = None;
+        self.released.clear();
+        self.rectangle = false;
     }
 
-    /// Clear the mark
-    pub fn clear_mark(&mut self) {
-        self.mark_line = None;
-        self.mark_col = None;
+    /// Check if the region is a rectangle
+    pub fn rectangle_mode(&self) -> bool {
+        self.rectangle
+    }
+
+    /// Set whether the region is a rectangle
+    pub fn set_rectangle_mode(&mut self, on: bool) {
+        self.rectangle = on;
     }
 
     /// Get top row on screen
@@ -136,6 +210,75 @@ impl Window {
         self.height = height;
     }
 
+    /// Get left column on screen
+    pub fn screen_col(&self) -> u16 {
+        self.screen_col
+    }
+
+    /// Set left column on screen
+    pub fn set_screen_col(&mut self, col: u16) {
+        self.screen_col = col;
+    }
+
+    /// Get window width in columns
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Set window width
+    pub fn set_width(&mut self, width: u16) {
+        self.width = width;
+    }
+
+    /// Check if the window is left off the screen
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// Set whether the window is left off the screen
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    /// Get the first display column shown
+    pub fn left_col(&self) -> usize {
+        self.left_col
+    }
+
+    /// Scroll horizontally by `cols` display columns (positive shows
+    /// columns further right); the cursor is followed from the new
+    /// position onwards
+    pub fn scroll_columns(&mut self, cols: isize) {
+        self.left_col = self.left_col.saturating_add_signed(cols);
+        self.min_left_col = self.left_col;
+    }
+
+    /// Scroll horizontally so display column `col` is shown, clear of the
+    /// `$` markers at the edges of a window `width` columns wide
+    ///
+    /// Scrolling jumps half a window at a time, and doesn't go left of
+    /// where scroll-left/scroll-right put it unless `col` is left of that.
+    pub fn ensure_column_visible(&mut self, col: usize, width: usize) {
+        if width < 3 {
+            return;
+        }
+        let first = if self.left_col > 0 { self.left_col + 1 } else { 0 };
+        if col < first || col + 1 >= self.left_col + width {
+            let left = if col + 1 < width { 0 } else { col - width / 2 };
+            if col <= self.min_left_col {
+                // The cursor went left of the scrolled-to columns
+                self.min_left_col = 0;
+            }
+            self.left_col = left.max(self.min_left_col);
+        }
+    }
+
+    /// Stop scrolling horizontally (for wrapped lines)
+    pub fn clear_left_col(&mut self) {
+        self.left_col = 0;
+        self.min_left_col = 0;
+    }
+
     /// Check if a line is visible in the window
     pub fn is_line_visible(&self, line: usize) -> bool {
         line >= self.top_line && line < self.top_line + self.height as usize
@@ -143,10 +286,43 @@ impl Window {
 
     /// Ensure cursor is visible, adjusting top_line if needed
     pub fn ensure_cursor_visible(&mut self) {
+        self.ensure_cursor_visible_rows(0, |_| 1);
+    }
+
+    /// Ensure cursor is visible when lines can wrap onto several screen
+    /// rows: `rows(line)` is the number of rows a buffer line takes and
+    /// `cursor_row` the row of its line the cursor is on
+    pub fn ensure_cursor_visible_rows(&mut self, cursor_row: usize, rows: impl Fn(usize) -> usize) {
+        // A window squeezed to no rows still follows its cursor line
+        let height = (self.height as usize).max(1);
         if self.cursor_line < self.top_line {
             self.top_line = self.cursor_line;
-        } else if self.cursor_line >= self.top_line + self.height as usize {
-            self.top_line = self.cursor_line - self.height as usize + 1;
+            return;
+        }
+        // Every line takes at least one row, so lines further up can't
+        // share the screen with the cursor
+        if self.cursor_line >= self.top_line + height {
+            self.top_line = self.cursor_line + 1 - height;
+        }
+        let mut used: usize = (self.top_line..self.cursor_line).map(&rows).sum::<usize>() + cursor_row + 1;
+        while used > height && self.top_line < self.cursor_line {
+            used -= rows(self.top_line);
+            self.top_line += 1;
+        }
+    }
+
+    /// Last buffer line shown whole in the window, with lines taking
+    /// `rows(line)` screen rows (at least the top line)
+    pub fn bottom_line(&self, rows: impl Fn(usize) -> usize) -> usize {
+        let mut used = rows(self.top_line);
+        let mut line = self.top_line;
+        loop {
+            let next = rows(line + 1);
+            if used + next > self.height as usize {
+                return line;
+            }
+            used += next;
+            line += 1;
         }
     }
 
@@ -161,8 +337,17 @@ impl Window {
 
     /// Scroll down by n lines
     pub fn scroll_down(&mut self, n: usize, max_line: usize) {
-        let new_top = self.top_line.saturating_add(n);
-        self.top_line = new_top.min(max_line.saturating_sub(1));
+        self.scroll_down_rows(n, max_line, |_| 1);
+    }
+
+    /// Scroll down by at least n screen rows, whole lines at a time, with
+    /// lines taking `rows(line)` rows
+    pub fn scroll_down_rows(&mut self, n: usize, max_line: usize, rows: impl Fn(usize) -> usize) {
+        let mut scrolled = 0;
+        while scrolled < n && self.top_line + 1 < max_line {
+            scrolled += rows(self.top_line);
+            self.top_line += 1;
+        }
         // Move cursor to stay in view
         if self.cursor_line < self.top_line {
             self.cursor_line = self.top_line;
@@ -171,11 +356,97 @@ impl Window {
 
     /// Scroll up by n lines
     pub fn scroll_up(&mut self, n: usize) {
-        self.top_line = self.top_line.saturating_sub(n);
+        self.scroll_up_rows(n, |_| 1);
+    }
+
+    /// Scroll up by at least n screen rows, whole lines at a time, with
+    /// lines taking `rows(line)` rows
+    pub fn scroll_up_rows(&mut self, n: usize, rows: impl Fn(usize) -> usize) {
+        let mut scrolled = 0;
+        while scrolled < n && self.top_line > 0 {
+            self.top_line -= 1;
+            scrolled += rows(self.top_line);
+        }
         // Move cursor to stay in view
-        let bottom = self.top_line + self.height as usize - 1;
+        let bottom = self.bottom_line(rows);
         if self.cursor_line > bottom {
             self.cursor_line = bottom;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll_columns() {
+        let mut window = Window::new(0, 0, 10);
+        window.scroll_columns(20);
+        assert_eq!(window.left_col(), 20);
+        window.scroll_columns(-5);
+        assert_eq!(window.left_col(), 15);
+        window.scroll_columns(-30);
+        assert_eq!(window.left_col(), 0);
+    }
+
+    #[test]
+    fn test_ensure_column_visible_follows_cursor() {
+        let mut window = Window::new(0, 0, 10);
+
+        // Inside the window nothing moves
+        window.ensure_column_visible(38, 40);
+        assert_eq!(window.left_col(), 0);
+
+        // Off the right edge jumps half a window
+        window.ensure_column_visible(39, 40);
+        assert_eq!(window.left_col(), 19);
+        window.ensure_column_visible(70, 40);
+        assert_eq!(window.left_col(), 50);
+
+        // The `$` at the left edge hides a column
+        window.ensure_column_visible(51, 40);
+        assert_eq!(window.left_col(), 50);
+        window.ensure_column_visible(50, 40);
+        assert_eq!(window.left_col(), 30);
+
+        // Back near the start shows the start
+        window.ensure_column_visible(5, 40);
+        assert_eq!(window.left_col(), 0);
+    }
+
+    #[test]
+    fn test_ensure_column_visible_keeps_manual_scroll() {
+        let mut window = Window::new(0, 0, 10);
+        window.scroll_columns(20);
+
+        // A cursor still in view leaves the scroll alone
+        window.ensure_column_visible(25, 40);
+        assert_eq!(window.left_col(), 20);
+
+        // Off the right edge scrolls on from there
+        window.ensure_column_visible(60, 40);
+        assert_eq!(window.left_col(), 40);
+
+        // Back left, it doesn't go below the manual scroll while the
+        // cursor shows there
+        window.ensure_column_visible(30, 40);
+        assert_eq!(window.left_col(), 20);
+    }
+
+    #[test]
+    fn test_ensure_column_visible_left_of_manual_scroll() {
+        let mut window = Window::new(0, 0, 10);
+        window.scroll_columns(20);
+
+        // Moving to the start of the line shows it
+        window.ensure_column_visible(0, 40);
+        assert_eq!(window.left_col(), 0);
+
+        // And the manual scroll no longer holds
+        window.ensure_column_visible(45, 40);
+        assert_eq!(window.left_col(), 25);
+        window.ensure_column_visible(10, 40);
+        assert_eq!(window.left_col(), 0);
+    }
+}